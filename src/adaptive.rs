@@ -0,0 +1,186 @@
+//! Raising the level for a while when errors pile up, so the records leading into an incident
+//! come with their debug detail without logging at `Debug` all the time.
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{internal, LogLevel};
+
+/// When to raise the root level and for how long, e.g. to `Debug` for two minutes once 10
+/// errors are logged within a minute.
+///
+/// While raised, the root level is the more verbose of the two. Named loggers with a level of
+/// their own keep it. Another burst of errors while raised extends it.
+///
+/// # Examples
+/// ```
+/// use woody::{AdaptiveLevel, LogLevel};
+/// let adaptive = AdaptiveLevel::parse("10/1m=debug/2m").unwrap();
+/// assert_eq!(adaptive.level(), LogLevel::Debug);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AdaptiveLevel {
+    errors: usize,
+    window: Duration,
+    level: LogLevel,
+    duration: Duration,
+}
+
+impl AdaptiveLevel {
+    /// Raises the level to `level` for `duration` once `errors` records at `ERROR` and above
+    /// are logged within `window`.
+    pub fn new(errors: usize, window: Duration, level: LogLevel, duration: Duration) -> Self {
+        Self {
+            errors: errors.max(1),
+            window,
+            level,
+            duration,
+        }
+    }
+
+    /// Parses `ERRORS/WINDOW=level/DURATION`, with durations in seconds (`90s`), minutes
+    /// (`2m`), or hours (`1h`).
+    pub fn parse(s: &str) -> Option<Self> {
+        let (trigger, boost) = s.split_once('=')?;
+        let (errors, window) = trigger.split_once('/')?;
+        let (level, duration) = boost.split_once('/')?;
+        let level = LogLevel::parse(level);
+        if level == LogLevel::ALL {
+            return None;
+        }
+        Some(Self::new(
+            errors.trim().parse().ok()?,
+            parse_duration(window)?,
+            level,
+            parse_duration(duration)?,
+        ))
+    }
+
+    /// The level used while raised.
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let unit = match s.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = s[..s.len() - 1].trim().parse().ok()?;
+    Some(Duration::from_secs(count * unit))
+}
+
+/// Counts errors and tracks whether the level is raised. Shared between a logger's clones.
+#[derive(Debug)]
+pub(crate) struct Controller {
+    policy: AdaptiveLevel,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// When the errors in the current window were logged, oldest first.
+    errors: VecDeque<Instant>,
+    raised_until: Option<Instant>,
+}
+
+impl Controller {
+    pub(crate) fn new(policy: AdaptiveLevel) -> Self {
+        Self {
+            policy,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Reads `WOODY_ADAPTIVE`, e.g. `10/1m=debug/2m`.
+    pub(crate) fn from_config() -> Option<Self> {
+        let setting = crate::config::setting("WOODY_ADAPTIVE")?;
+        AdaptiveLevel::parse(&setting).map(Self::new)
+    }
+
+    /// Counts a record at `level`, raising the level if it's the error that crosses the rate.
+    pub(crate) fn observe(&self, level: LogLevel) {
+        if level >= LogLevel::Error {
+            self.observe_error(Instant::now());
+        }
+    }
+
+    fn observe_error(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some(first) = state.errors.front() {
+            if now.duration_since(*first) < self.policy.window {
+                break;
+            }
+            state.errors.pop_front();
+        }
+        state.errors.push_back(now);
+        if state.errors.len() >= self.policy.errors {
+            state.errors.clear();
+            state.raised_until = Some(now + self.policy.duration);
+            internal::event(LogLevel::Debug, || {
+                format!(
+                    "raised the level to {:?} for {:?} after {} errors",
+                    self.policy.level, self.policy.duration, self.policy.errors
+                )
+            });
+        }
+    }
+
+    /// The raised level, if it's raised now.
+    pub(crate) fn active(&self) -> Option<LogLevel> {
+        self.active_at(Instant::now())
+    }
+
+    fn active_at(&self, now: Instant) -> Option<LogLevel> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.raised_until {
+            Some(until) if now < until => Some(self.policy.level),
+            Some(_) => {
+                state.raised_until = None;
+                internal::event(LogLevel::Debug, || "restored the level".to_string());
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raises_and_restores() {
+        let policy = AdaptiveLevel::parse("3/1m=trace/2m").unwrap();
+        assert_eq!(
+            policy,
+            AdaptiveLevel::new(
+                3,
+                Duration::from_secs(60),
+                LogLevel::Trace,
+                Duration::from_secs(120)
+            )
+        );
+        assert!(AdaptiveLevel::parse("3/1m=loud/2m").is_none());
+        assert!(AdaptiveLevel::parse("3/1d=trace/2m").is_none());
+
+        let controller = Controller::new(policy);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        // Two errors fall out of the window before the third, so nothing happens.
+        controller.observe_error(at(0));
+        controller.observe_error(at(1));
+        controller.observe_error(at(61));
+        assert_eq!(controller.active_at(at(61)), None);
+        controller.observe_error(at(62));
+        controller.observe_error(at(63));
+        assert_eq!(controller.active_at(at(64)), Some(LogLevel::Trace));
+        assert_eq!(controller.active_at(at(183)), None);
+    }
+}
@@ -0,0 +1,213 @@
+//! A tiny HTTP server on localhost for adjusting a running service's logging with curl:
+//!
+//! ```text
+//! GET  /level           the root level
+//! PUT  /level           set the root level, e.g. `curl -X PUT -d debug localhost:9999/level`
+//! GET  /level/<name>    the effective level of a named logger
+//! PUT  /level/<name>    set the level of a named logger and its descendants
+//! GET  /filters         the source filter, as `allow=...` and `deny=...` lines
+//! PUT  /filters         replace the source filter, in the same format
+//! POST /flush           flush buffered records to disk
+//! ```
+//!
+//! Changes apply to the global logger, so they affect the log macros right away.
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use crate::{LogLevel, Logger, Source, SourceFilter};
+
+/// How long a client may take to send its request or read the response before it's dropped.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts the admin server on `127.0.0.1:port` in a background thread and returns the address
+/// it's listening on. Pass port 0 to pick any free port.
+///
+/// # Examples
+/// ```no_run
+/// let addr = woody::admin::serve(9999).unwrap();
+/// println!("log admin listening on http://{addr}");
+/// ```
+pub fn serve(port: u16) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    let addr = listener.local_addr()?;
+    thread::Builder::new()
+        .name("woody-admin".to_string())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A misbehaving client only affects its own request, and only for so long.
+                let _ = thread::Builder::new()
+                    .name("woody-admin-client".to_string())
+                    .spawn(move || handle(stream));
+            }
+        })?;
+    Ok(addr)
+}
+
+fn handle(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length.min(64 * 1024)];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body);
+
+    let (status, response) = respond(&method, &path, body.trim());
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    )
+}
+
+/// Handles a request, returning the status line and the body.
+fn respond(method: &str, path: &str, body: &str) -> (&'static str, String) {
+    const OK: &str = "200 OK";
+    const BAD_REQUEST: &str = "400 Bad Request";
+    const NOT_FOUND: &str = "404 Not Found";
+
+    let mut logger = Logger::get_instance();
+    match (method, path) {
+        ("GET", "/level") => (OK, level_name(logger.level)),
+        ("GET", path) if path.starts_with("/level/") => {
+            let named = logger.named(&path["/level/".len()..]);
+            (OK, level_name(named.effective_level()))
+        }
+        ("PUT", path) if path == "/level" || path.starts_with("/level/") => {
+            let Some(level) = parse_level(body) else {
+                return (BAD_REQUEST, format!("unknown level: {body}\n"));
+            };
+            match path.strip_prefix("/level/") {
+                Some(name) => logger.set_named_level(name, level),
+                None => logger.set_level(level),
+            }
+            Logger::set_instance(logger);
+            (OK, level_name(level))
+        }
+        ("GET", "/filters") => (OK, render_filters(&logger.sources)),
+        ("PUT", "/filters") => {
+            let (mut allow, mut deny) = ("", "");
+            for line in body.lines() {
+                match line.split_once('=') {
+                    Some(("allow", list)) => allow = list,
+                    Some(("deny", list)) => deny = list,
+                    _ => {
+                        return (
+                            BAD_REQUEST,
+                            format!("expected allow=... or deny=...: {line}\n"),
+                        )
+                    }
+                }
+            }
+            logger.set_source_filter(SourceFilter::from_lists(allow, deny));
+            let response = render_filters(&logger.sources);
+            Logger::set_instance(logger);
+            (OK, response)
+        }
+        ("POST", "/flush") => {
+            logger.flush();
+            (OK, "flushed\n".to_string())
+        }
+        _ => (NOT_FOUND, "not found\n".to_string()),
+    }
+}
+
+fn level_name(level: LogLevel) -> String {
+    match level {
+        LogLevel::ALL => "all\n".to_string(),
+        level => format!("{}\n", level.to_string().to_lowercase()),
+    }
+}
+
+/// Parses a level, rejecting unknown names instead of treating them as `ALL`.
+fn parse_level(s: &str) -> Option<LogLevel> {
+    match LogLevel::parse(s) {
+        LogLevel::ALL if !s.eq_ignore_ascii_case("all") => None,
+        level => Some(level),
+    }
+}
+
+/// Renders the filter as `allow=...` and `deny=...` lines of comma-separated sources.
+fn render_filters(filter: &SourceFilter) -> String {
+    let join = |list: &[Source]| {
+        list.iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    format!(
+        "allow={}\ndeny={}\n",
+        join(filter.allowed()),
+        join(filter.denied())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_named_level_round_trip() {
+        let addr = serve(0).unwrap();
+        let response = request(addr, "PUT", "/level/admin.test", "debug");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        let response = request(addr, "GET", "/level/admin.test.child", "");
+        assert!(response.ends_with("\r\n\r\ndebug\n"), "{response}");
+
+        let response = request(addr, "PUT", "/level/admin.test", "loud");
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+
+        let response = request(addr, "POST", "/flush", "");
+        assert!(response.ends_with("flushed\n"), "{response}");
+        let response = request(addr, "DELETE", "/level", "");
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+    }
+
+    #[test]
+    fn test_silent_client_does_not_block_others() {
+        let addr = serve(0).unwrap();
+        let _silent = TcpStream::connect(addr).unwrap();
+        let mut half = TcpStream::connect(addr).unwrap();
+        write!(half, "GET /level HTTP/1.1\r\nContent-Length: 10\r\n\r\n").unwrap();
+
+        let start = std::time::Instant::now();
+        let response = request(addr, "GET", "/level", "");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(start.elapsed() < TIMEOUT);
+    }
+}
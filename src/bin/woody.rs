@@ -0,0 +1,193 @@
+//! Reads woody logs, including rotated and compressed archives.
+//!
+//! ```text
+//! woody cat  [--since TIME] [--until TIME] [--level LEVEL] FILE
+//! woody grep [--since TIME] [--until TIME] [--level LEVEL] PATTERN FILE
+//! woody tail [-n LINES] [-f] FILE
+//! woody pretty  [FILE]
+//! woody compact [FILE]
+//! ```
+//!
+//! Times are `YYYY-MM-DD HH:MM[:SS]` in local time, or RFC 3339. `--level` only prints
+//! records at that level or above. `cat` and `grep` print files in the `msgpack` format as
+//! text. `pretty` and `compact` convert JSON records between the indented and one-per-line
+//! forms, reading stdin if there's no file.
+use std::{
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    process::ExitCode,
+    time::Duration,
+};
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
+use woody::{LogLevel, LogReader};
+
+const USAGE: &str = "usage:
+  woody cat  [--since TIME] [--until TIME] [--level LEVEL] FILE
+  woody grep [--since TIME] [--until TIME] [--level LEVEL] PATTERN FILE
+  woody tail [-n LINES] [-f] FILE
+  woody pretty  [FILE]
+  woody compact [FILE]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("woody: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> io::Result<()> {
+    let (command, rest) = args.split_first().ok_or_else(usage)?;
+    let mut options = Options::parse(rest)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match command.as_str() {
+        "cat" => {
+            let file = options.positional(1)?;
+            print(&mut out, &LogReader::open(&file[0])?, &options, None)
+        }
+        "grep" => {
+            let args = options.positional(2)?;
+            print(
+                &mut out,
+                &LogReader::open(&args[1])?,
+                &options,
+                Some(&args[0]),
+            )
+        }
+        "tail" => {
+            let file = options.positional(1)?;
+            tail(&mut out, &file[0], options.lines, options.follow)
+        }
+        "pretty" => out.write_all(woody::json::pretty(&read_input(&options)?).as_bytes()),
+        "compact" => out.write_all(woody::json::compact(&read_input(&options)?).as_bytes()),
+        _ => Err(usage()),
+    }
+}
+
+struct Options {
+    since: Option<DateTime<FixedOffset>>,
+    until: Option<DateTime<FixedOffset>>,
+    level: LogLevel,
+    lines: usize,
+    follow: bool,
+    positional: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> io::Result<Self> {
+        let mut options = Options {
+            since: None,
+            until: None,
+            level: LogLevel::ALL,
+            lines: 10,
+            follow: false,
+            positional: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(usage);
+            match arg.as_str() {
+                "--since" => options.since = Some(parse_time(value()?)?),
+                "--until" => options.until = Some(parse_time(value()?)?),
+                "--level" => options.level = LogLevel::parse(value()?),
+                "-n" => options.lines = value()?.parse().map_err(|_| usage())?,
+                "-f" => options.follow = true,
+                _ => options.positional.push(arg.clone()),
+            }
+        }
+        Ok(options)
+    }
+
+    fn positional(&mut self, count: usize) -> io::Result<Vec<String>> {
+        if self.positional.len() != count {
+            return Err(usage());
+        }
+        Ok(std::mem::take(&mut self.positional))
+    }
+}
+
+fn print(
+    out: &mut impl Write,
+    reader: &LogReader,
+    options: &Options,
+    pattern: Option<&str>,
+) -> io::Result<()> {
+    for line in reader.query(options.since, options.until, options.level)? {
+        let line = line?;
+        if pattern.is_none_or(|pattern| line.contains(pattern)) {
+            writeln!(out, "{line}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the file given, or stdin if there isn't one.
+fn read_input(options: &Options) -> io::Result<String> {
+    match options.positional.as_slice() {
+        [] => io::read_to_string(io::stdin()),
+        [path] => std::fs::read_to_string(path),
+        _ => Err(usage()),
+    }
+}
+
+/// Prints the last `count` lines of the active file, then (if `follow`) new lines as they're
+/// written.
+fn tail(out: &mut impl Write, path: &str, count: usize, follow: bool) -> io::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    let mut last = std::collections::VecDeque::with_capacity(count);
+    let mut reader = BufReader::new(&mut file);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        if last.len() == count {
+            last.pop_front();
+        }
+        last.push_back(std::mem::take(&mut line));
+    }
+    for line in last {
+        out.write_all(line.as_bytes())?;
+    }
+    out.flush()?;
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        let position = reader.stream_position()?;
+        if std::fs::metadata(path)?.len() < position {
+            // The file was truncated or replaced, so start over from the top.
+            reader = BufReader::new(&mut file);
+            reader.seek(SeekFrom::Start(0))?;
+        }
+        match reader.read_line(&mut line)? {
+            0 => std::thread::sleep(Duration::from_millis(200)),
+            _ => {
+                out.write_all(std::mem::take(&mut line).as_bytes())?;
+                out.flush()?;
+            }
+        }
+    }
+}
+
+fn parse_time(s: &str) -> io::Result<DateTime<FixedOffset>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time);
+    }
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("bad time: {s}")))?;
+    let local = Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("bad time: {s}")))?;
+    Ok(local.fixed_offset())
+}
+
+fn usage() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
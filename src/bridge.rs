@@ -0,0 +1,139 @@
+//! A `log::Log` backend, so records from crates using the `log` facade end up in woody too.
+use crate::{fields::intern, Fields, LogLevel, Logger, Record, Value};
+
+struct Bridge;
+
+static BRIDGE: Bridge = Bridge;
+
+/// Installs woody as the `log` crate's logger. Records keep their file, line, and module, their
+/// key-values become fields, and a target other than the module path is kept in a `target`
+/// field.
+///
+/// # Errors
+/// Fails if another logger was installed first.
+///
+/// # Examples
+/// ```
+/// woody::init_log_bridge().unwrap();
+/// log::warn!(target: "db", "pool exhausted"); // ... [WARNING] ... pool exhausted target="db"
+/// log::info!(user_id = 42; "signed in"); // ... [INFO] ... signed in user_id=42
+/// ```
+pub fn init_log_bridge() -> Result<(), log::SetLoggerError> {
+    log::set_logger(&BRIDGE)?;
+    log::set_max_level(log::LevelFilter::Trace);
+    Ok(())
+}
+
+fn level(level: log::Level) -> LogLevel {
+    match level {
+        log::Level::Error => LogLevel::Error,
+        log::Level::Warn => LogLevel::Warning,
+        log::Level::Info => LogLevel::Info,
+        log::Level::Debug => LogLevel::Debug,
+        log::Level::Trace => LogLevel::Trace,
+    }
+}
+
+/// Converts a `log` record. Borrowed paths are only known while the record is, so they're left
+/// empty rather than leaked.
+fn record(record: &log::Record) -> Record {
+    let module_path = record.module_path_static().unwrap_or("");
+    let mut converted = Record::new(
+        level(record.level()),
+        record.args().to_string(),
+        record.file_static().unwrap_or(""),
+        record.line().unwrap_or(0),
+        module_path,
+    );
+    let _ = record
+        .key_values()
+        .visit(&mut KeyValues(&mut converted.fields));
+    if record.target() != module_path {
+        converted.fields.push("target", record.target().to_string());
+    }
+    converted
+}
+
+/// Copies a record's key-values into its fields. Numbers and booleans keep their type, and
+/// anything else is kept as its `Display` output.
+struct KeyValues<'a>(&'a mut Fields);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for KeyValues<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = if let Some(x) = value.to_i64() {
+            Value::I64(x)
+        } else if let Some(x) = value.to_u64() {
+            Value::U64(x)
+        } else if let Some(x) = value.to_f64() {
+            Value::F64(x)
+        } else if let Some(x) = value.to_bool() {
+            Value::Bool(x)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.push(intern(key.as_str()), value);
+        Ok(())
+    }
+}
+
+impl log::Log for Bridge {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let logger = Logger::get_instance();
+        let threshold = logger.effective_level();
+        threshold != LogLevel::Off && level(metadata.level()) >= threshold
+    }
+
+    fn log(&self, record: &log::Record) {
+        Logger::get_instance().log(&self::record(record));
+    }
+
+    fn flush(&self) {
+        Logger::get_instance().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_record() {
+        let converted = record(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("db")
+                .args(format_args!("pool {}", "exhausted"))
+                .file_static(Some("src/db.rs"))
+                .line(Some(9))
+                .module_path_static(Some("app::db"))
+                .build(),
+        );
+        assert_eq!(converted.level, LogLevel::Warning);
+        assert_eq!(converted.message, "pool exhausted");
+        assert_eq!(
+            (converted.filepath, converted.line_number),
+            ("src/db.rs", 9)
+        );
+        assert_eq!(
+            converted.fields.get("target"),
+            Some(&"db".to_string().into())
+        );
+    }
+
+    #[test]
+    fn test_key_values_become_fields() {
+        let converted = record(
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("signed in"))
+                .key_values(&[("user_id", 42)])
+                .build(),
+        );
+        assert_eq!(converted.fields.get("user_id"), Some(&Value::I64(42)));
+        assert_eq!(crate::fields::format(&converted.fields), " user_id=42");
+    }
+}
@@ -0,0 +1,205 @@
+//! Settings from the environment and the config file.
+//!
+//! Every setting can be given as an environment variable (`WOODY_LEVEL`) or as a key in the
+//! config file (`level`). From highest to lowest precedence, a setting comes from:
+//!
+//! 1. code, like `Logger::set_level`, which changes the logger after it's created;
+//! 2. the config file;
+//! 3. the environment;
+//! 4. the default.
+//!
+//! The config file is `woody.conf` in the current directory, or wherever `WOODY_CONFIG` points
+//! (which can only be set in the environment), and holds one `key = value` per line:
+//!
+//! ```text
+//! # Verbose during the nightly batch, quiet otherwise.
+//! level = info
+//! schedule = 02:00-03:00=trace
+//! durability = fsync
+//! ```
+//!
+//! The file is read once, the first time a setting is looked up, so changes to it take effect
+//! on restart.
+use std::{
+    collections::BTreeMap,
+    env, fmt,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::LogLevel;
+
+/// The config file used when `WOODY_CONFIG` isn't set.
+#[cfg(feature = "file")]
+const DEFAULT_CONFIG_FILE: &str = "woody.conf";
+
+/// The config file's settings, read on first use.
+static FILE: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+
+/// Every setting read so far, by environment variable name.
+static RESOLVED: Mutex<BTreeMap<String, Setting>> = Mutex::new(BTreeMap::new());
+
+/// Where a setting came from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// The config file.
+    File,
+    /// An environment variable.
+    Environment,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::File => write!(f, "config file"),
+            ConfigSource::Environment => write!(f, "environment"),
+        }
+    }
+}
+
+/// The value a setting was read with, and where from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Setting {
+    /// The value as written.
+    pub value: String,
+    /// Where it was read from.
+    pub source: ConfigSource,
+}
+
+/// The configuration in effect, from [`Logger::effective_config`](crate::Logger::effective_config).
+///
+/// Its `Display` lists one setting per line, e.g. `WOODY_LEVEL=info (config file)`, ready to
+/// be logged at startup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Config {
+    /// The root level of the logger, including changes made in code.
+    pub level: LogLevel,
+    /// The log file, if there is one.
+    pub file: Option<String>,
+    /// The settings that were read from the config file or the environment, by environment
+    /// variable name. Settings missing here have their defaults.
+    pub settings: BTreeMap<String, Setting>,
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "level: {}", self.level)?;
+        if let Some(file) = &self.file {
+            writeln!(f, "file: {file}")?;
+        }
+        for (var, Setting { value, source }) in &self.settings {
+            writeln!(f, "{var}={value} ({source})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a setting by its environment variable name, e.g. `WOODY_LEVEL`, or rather the
+/// matching key of the config file, e.g. `level`, if it's there.
+pub(crate) fn setting(var: &str) -> Option<String> {
+    let file = FILE.get_or_init(read_file);
+    let Setting { value, source } = lookup(var, file)?;
+    let mut resolved = RESOLVED.lock().unwrap_or_else(|e| e.into_inner());
+    resolved.insert(
+        var.to_string(),
+        Setting {
+            value: value.clone(),
+            source,
+        },
+    );
+    Some(value)
+}
+
+/// Every setting read so far.
+pub(crate) fn resolved() -> BTreeMap<String, Setting> {
+    RESOLVED.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+fn lookup(var: &str, file: &BTreeMap<String, String>) -> Option<Setting> {
+    let key = var.trim_start_matches("WOODY_").to_lowercase();
+    if let Some(value) = file.get(&key) {
+        return Some(Setting {
+            value: value.clone(),
+            source: ConfigSource::File,
+        });
+    }
+    let value = env::var(var).ok()?;
+    Some(Setting {
+        value,
+        source: ConfigSource::Environment,
+    })
+}
+
+/// Whether a boolean setting like `WOODY_ASYNC=1` is switched on.
+pub(crate) fn flag(var: &str) -> bool {
+    setting(var)
+        .map(|x| matches!(x.to_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
+}
+
+/// Reads the config file. A missing or unreadable file has no settings.
+///
+/// Without the `file` feature, only a config file named by `WOODY_CONFIG` is read, so nothing
+/// touches the filesystem unless asked to.
+#[cfg(feature = "file")]
+fn read_file() -> BTreeMap<String, String> {
+    let path = env::var("WOODY_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+    let path = crate::expand_path(&path);
+    std::fs::read_to_string(path)
+        .map(|contents| parse(&contents))
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "file"))]
+fn read_file() -> BTreeMap<String, String> {
+    let Ok(path) = env::var("WOODY_CONFIG") else {
+        return BTreeMap::new();
+    };
+    std::fs::read_to_string(path)
+        .map(|contents| parse(&contents))
+        .unwrap_or_default()
+}
+
+/// Parses `key = value` lines, skipping blank lines and `#` comments. Keys are case-insensitive
+/// and may use `-` for `_`.
+fn parse(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let key = key.trim().to_lowercase().replace('-', "_");
+            let value = value.trim().trim_matches('"').to_string();
+            (key, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let settings = parse(
+            "# comment\n\nlevel = info,app.db=debug\nSCHEDULE = 02:00-03:00=trace\nfile-mode=\"640\"\n",
+        );
+        assert_eq!(settings["level"], "info,app.db=debug");
+        assert_eq!(settings["schedule"], "02:00-03:00=trace");
+        assert_eq!(settings["file_mode"], "640");
+        assert_eq!(settings.len(), 3);
+    }
+
+    #[test]
+    fn test_file_beats_environment() {
+        env::set_var("WOODY_TEST_PRECEDENCE", "env");
+        let file = parse("test_precedence = file");
+        let setting = lookup("WOODY_TEST_PRECEDENCE", &file).unwrap();
+        assert_eq!(setting.value, "file");
+        assert_eq!(setting.source, ConfigSource::File);
+        let setting = lookup("WOODY_TEST_PRECEDENCE", &BTreeMap::new()).unwrap();
+        assert_eq!(setting.source, ConfigSource::Environment);
+        assert!(lookup("WOODY_TEST_UNSET", &BTreeMap::new()).is_none());
+    }
+}
@@ -0,0 +1,200 @@
+//! Echoes records to the terminal, cooperating with progress bars and other in-place terminal
+//! UIs so log lines don't shred them.
+use std::{
+    io::{IsTerminal, Write},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::LogLevel;
+
+/// Temporarily hides an in-place terminal UI while a log line is written.
+///
+/// Implemented for closures taking the write to perform, which makes hooking up
+/// `indicatif::MultiProgress` (or a `ProgressBar`) a one-liner:
+///
+/// ```ignore
+/// let bars = indicatif::MultiProgress::new();
+/// let hook = bars.clone();
+/// logger.set_console_suspend(Some(move |write: &mut dyn FnMut()| hook.suspend(write)));
+/// ```
+pub trait ConsoleSuspend: Send + Sync {
+    /// Hides the UI, calls `write`, and redraws the UI.
+    fn suspend(&self, write: &mut dyn FnMut());
+}
+
+impl<F> ConsoleSuspend for F
+where
+    F: Fn(&mut dyn FnMut()) + Send + Sync,
+{
+    fn suspend(&self, write: &mut dyn FnMut()) {
+        self(write)
+    }
+}
+
+/// Writes records to stderr.
+#[derive(Clone, Default)]
+pub(crate) struct Console {
+    pub(crate) enabled: bool,
+    suspend: Option<Arc<dyn ConsoleSuspend>>,
+    /// Where output goes instead of stderr, so tests can see it.
+    #[cfg(test)]
+    pub(crate) captured: Option<Arc<Mutex<Vec<u8>>>>,
+}
+
+impl std::fmt::Debug for Console {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Console")
+            .field("enabled", &self.enabled)
+            .field("suspend", &self.suspend.is_some())
+            .finish()
+    }
+}
+
+/// Serializes console writes across clones of the logger, so a suspend hook never runs
+/// concurrently with itself.
+static CONSOLE_LOCK: Mutex<()> = Mutex::new(());
+
+impl Console {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn set_suspend(&mut self, suspend: Option<Arc<dyn ConsoleSuspend>>) {
+        self.suspend = suspend;
+    }
+
+    /// Writes formatted records, hiding any registered terminal UI while doing so. Levels are
+    /// colored when stderr is a terminal that supports it.
+    pub(crate) fn write(&self, records: &[(LogLevel, String)]) {
+        let colors = colors_supported();
+        let output: String = records
+            .iter()
+            .map(|(level, line)| match colors {
+                true => colorize(*level, line),
+                false => line.clone(),
+            })
+            .collect();
+        let output = output.as_bytes();
+
+        let _guard = CONSOLE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut write = || self.write_line(output);
+        match &self.suspend {
+            Some(suspend) => suspend.suspend(&mut write),
+            None => write(),
+        }
+    }
+
+    fn write_line(&self, output: &[u8]) {
+        #[cfg(test)]
+        if let Some(captured) = &self.captured {
+            captured.lock().unwrap().extend_from_slice(output);
+            return;
+        }
+
+        // Write the whole line at once so it can't interleave with other output. On Windows the
+        // standard library hands console output to `WriteConsoleW` as UTF-16, so non-ASCII
+        // text displays correctly whatever the console code page is.
+        let _ = std::io::stderr().lock().write_all(output);
+    }
+}
+
+/// Wraps the `[LEVEL]` token of a formatted record in ANSI color codes.
+fn colorize(level: LogLevel, output: &str) -> String {
+    let color = match level {
+        LogLevel::Fatal => "1;31",
+        LogLevel::Error => "31",
+        LogLevel::Warning => "33",
+        LogLevel::Info => "32",
+        LogLevel::Debug => "36",
+        LogLevel::Trace => "90",
+        LogLevel::Custom { .. } => "34",
+        LogLevel::Off | LogLevel::ALL => return output.to_string(),
+    };
+    output.replacen(
+        &format!("[{level}]"),
+        &format!("[\x1b[{color}m{level}\x1b[0m]"),
+        1,
+    )
+}
+
+/// Whether stderr is a terminal that understands ANSI colors. Respects `NO_COLOR`.
+fn colors_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal() && enable_ansi()
+    })
+}
+
+#[cfg(not(windows))]
+fn enable_ansi() -> bool {
+    true
+}
+
+/// Turns on virtual terminal processing, without which the Windows console prints escape
+/// codes literally. Returns `false` on consoles that don't support it (before Windows 10).
+#[cfg(windows)]
+fn enable_ansi() -> bool {
+    use std::ffi::c_void;
+
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetStdHandle(std_handle: u32) -> *mut c_void;
+        fn GetConsoleMode(console: *mut c_void, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: *mut c_void, mode: u32) -> i32;
+    }
+
+    // SAFETY: these calls only read and update the mode of our own stderr handle, and `mode`
+    // outlives the call that writes to it.
+    unsafe {
+        let handle = GetStdHandle(STD_ERROR_HANDLE);
+        let mut mode = 0;
+        if handle.is_null() || GetConsoleMode(handle, &mut mode) == 0 {
+            return false;
+        }
+        mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that the line is written while the UI is hidden.
+    #[test]
+    fn test_suspend_wraps_write() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let hook_events = events.clone();
+        let mut console = Console {
+            enabled: true,
+            captured: Some(Arc::new(Mutex::new(Vec::new()))),
+            ..Default::default()
+        };
+        console.set_suspend(Some(Arc::new(move |write: &mut dyn FnMut()| {
+            hook_events.lock().unwrap().push("hide");
+            write();
+            hook_events.lock().unwrap().push("show");
+        })));
+
+        console.write(&[(LogLevel::Off, "hello\n".to_string())]);
+        assert_eq!(*events.lock().unwrap(), ["hide", "show"]);
+        let captured = console.captured.unwrap();
+        assert_eq!(*captured.lock().unwrap(), b"hello\n");
+    }
+
+    #[test]
+    fn test_colorize_level_only() {
+        let line = "[2024-01-01] [ERROR] [main] [src/ERROR.rs:1] [ERROR] oh no\n";
+        assert_eq!(
+            colorize(LogLevel::Error, line),
+            "[2024-01-01] [\x1b[31mERROR\x1b[0m] [main] [src/ERROR.rs:1] [ERROR] oh no\n"
+        );
+        assert_eq!(colorize(LogLevel::Off, "[OFF] text\n"), "[OFF] text\n");
+    }
+}
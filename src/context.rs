@@ -0,0 +1,229 @@
+//! Per-thread logging context, like a request ID, that's added to every record logged on the
+//! thread and can be carried over to threads it spawns.
+//!
+//! # Examples
+//! ```
+//! use woody::context::{self, Context};
+//! let _guard = Context::current().with("request_id", 42).attach();
+//! woody::log_info!("handling request"); // ... [request_id=42] handling request
+//! context::spawn_with_context(|| {
+//!     let id = context::current().get::<i32>("request_id").copied();
+//!     assert_eq!(id, Some(42));
+//! })
+//! .join()
+//! .unwrap();
+//! ```
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt::Display,
+    io,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+thread_local! {
+    static CURRENT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/// One key of a context.
+#[derive(Clone)]
+struct Entry {
+    key: &'static str,
+    value: Arc<dyn Any + Send + Sync>,
+    /// The value as written to the log.
+    display: String,
+}
+
+/// An immutable set of typed key-value pairs. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct Context {
+    entries: Arc<Vec<Entry>>,
+}
+
+impl Context {
+    /// Creates an empty context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current thread's context.
+    pub fn current() -> Self {
+        current()
+    }
+
+    /// Returns a copy of this context with `key` set to `value`, replacing any previous value.
+    pub fn with<T>(&self, key: &'static str, value: T) -> Self
+    where
+        T: Display + Send + Sync + 'static,
+    {
+        let mut entries: Vec<Entry> = self
+            .entries
+            .iter()
+            .filter(|e| e.key != key)
+            .cloned()
+            .collect();
+        entries.push(Entry {
+            key,
+            display: value.to_string(),
+            value: Arc::new(value),
+        });
+        Self {
+            entries: Arc::new(entries),
+        }
+    }
+
+    /// The value of `key`, if it's set and is a `T`.
+    pub fn get<T: 'static>(&self, key: &str) -> Option<&T> {
+        let entry = self.entries.iter().find(|e| e.key == key)?;
+        entry.value.downcast_ref()
+    }
+
+    /// Whether no keys are set.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Makes this the current thread's context until the guard is dropped, when the previous
+    /// context is restored.
+    #[must_use = "the context is detached when the guard is dropped"]
+    pub fn attach(self) -> ContextGuard {
+        let previous = CURRENT.with(|current| current.replace(self));
+        ContextGuard {
+            previous: Some(previous),
+        }
+    }
+
+    /// The keys and values as tags, e.g. `[request_id=42] `.
+    pub(crate) fn tags(&self) -> String {
+        self.entries
+            .iter()
+            .map(|e| {
+                let tag = format!("{}={}", e.key, e.display);
+                format!("[{}] ", crate::escape::token(&tag))
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.entries.iter().map(|e| (e.key, &e.display)))
+            .finish()
+    }
+}
+
+/// Restores the previous context when dropped. See [`Context::attach`].
+#[derive(Debug)]
+pub struct ContextGuard {
+    previous: Option<Context>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            // The thread-local may already be gone if the guard outlives it during thread exit.
+            let _ = CURRENT.try_with(|current| current.replace(previous));
+        }
+    }
+}
+
+/// The current thread's context.
+pub fn current() -> Context {
+    CURRENT
+        .try_with(|current| current.borrow().clone())
+        .unwrap_or_default()
+}
+
+/// Like `std::thread::spawn`, but the new thread starts with the current thread's context.
+pub fn spawn_with_context<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let context = current();
+    thread::spawn(move || {
+        let _guard = context.attach();
+        f()
+    })
+}
+
+/// Like `std::thread::Builder`, but spawned threads start with the spawning thread's context.
+#[derive(Debug)]
+pub struct Builder(thread::Builder);
+
+impl Builder {
+    /// Creates a builder with the default settings.
+    pub fn new() -> Self {
+        Self(thread::Builder::new())
+    }
+
+    /// Names the thread. The name shows up in every record it logs.
+    pub fn name(self, name: String) -> Self {
+        Self(self.0.name(name))
+    }
+
+    /// Sets the stack size of the thread, in bytes.
+    pub fn stack_size(self, size: usize) -> Self {
+        Self(self.0.stack_size(size))
+    }
+
+    /// Spawns the thread, carrying over the current thread's context.
+    pub fn spawn<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let context = current();
+        self.0.spawn(move || {
+            let _guard = context.attach();
+            f()
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<thread::Builder> for Builder {
+    fn from(builder: thread::Builder) -> Self {
+        Self(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_restores_previous() {
+        let outer = Context::new().with("request_id", 1).attach();
+        {
+            let _inner = current().with("user", "ada").attach();
+            assert_eq!(current().get::<i32>("request_id"), Some(&1));
+            assert_eq!(current().get::<&str>("user"), Some(&"ada"));
+            assert_eq!(current().tags(), "[request_id=1] [user=ada] ");
+        }
+        assert!(current().get::<&str>("user").is_none());
+        drop(outer);
+        assert!(current().is_empty());
+    }
+
+    #[test]
+    fn test_spawn_carries_context() {
+        let _guard = Context::new().with("request_id", 7u64).attach();
+        let id = spawn_with_context(|| current().get::<u64>("request_id").copied());
+        assert_eq!(id.join().unwrap(), Some(7));
+
+        let name = Builder::new()
+            .name("worker".to_string())
+            .spawn(|| current().get::<u64>("request_id").copied())
+            .unwrap();
+        assert_eq!(name.join().unwrap(), Some(7));
+        assert!(thread::spawn(|| current().is_empty()).join().unwrap());
+    }
+}
@@ -0,0 +1,436 @@
+//! Writes buffered records and the flight recorder to the log file when the process crashes
+//! on a fatal signal, so the records leading up to a crash aren't lost with it.
+//!
+//! A signal handler may only call async-signal-safe functions, so everything is prepared when
+//! the handler is installed: the log file is opened again for appending and the messages are
+//! formatted up front. The handler itself only takes locks that are free (never waiting on
+//! one) and calls `write(2)` on bytes that already exist. Records queued for the async writer
+//! thread can't be reached from a handler and are lost.
+//!
+//! The handlers are installed with `sigaction` rather than `signal`, whose semantics differ
+//! between platforms, and run on the thread's alternate signal stack, so a stack overflow can
+//! still be reported. The standard library sets one up for the main thread and every thread it
+//! spawns; installing also sets one up for the calling thread if it has none. Threads created
+//! outside the standard library without one can't report overflowing their stack.
+use std::{
+    ffi::{c_int, c_void},
+    fs::{File, OpenOptions},
+    io,
+    os::fd::{AsRawFd, RawFd},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use crate::{msgpack, recorder::FlightRecorder, writer::LogFile, Format, Logger};
+
+const SIGILL: c_int = 4;
+const SIGABRT: c_int = 6;
+const SIGFPE: c_int = 8;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const SIGBUS: c_int = 7;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const SIGBUS: c_int = 10;
+const SIGSEGV: c_int = 11;
+
+/// The size of the alternate signal stack set up for a thread that has none.
+const ALT_STACK_SIZE: usize = 64 * 1024;
+
+/// The signals handled, with their names.
+const SIGNALS: [(c_int, &str); 5] = [
+    (SIGSEGV, "SIGSEGV"),
+    (SIGABRT, "SIGABRT"),
+    (SIGBUS, "SIGBUS"),
+    (SIGILL, "SIGILL"),
+    (SIGFPE, "SIGFPE"),
+];
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    pub const SA_SIGINFO: c_int = 0x4;
+    pub const SA_ONSTACK: c_int = 0x0800_0000;
+    pub const SA_RESETHAND: c_int = 0x8000_0000_u32 as c_int;
+    pub const SS_DISABLE: c_int = 2;
+    /// Where `si_code` is in `siginfo_t`, in `c_int`s.
+    pub const SI_CODE: usize = 2;
+
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_sigaction: usize,
+        pub sa_mask: [u64; 16],
+        pub sa_flags: c_int,
+        pub sa_restorer: usize,
+    }
+
+    #[repr(C)]
+    pub struct StackT {
+        pub ss_sp: *mut c_void,
+        pub ss_flags: c_int,
+        pub ss_size: usize,
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    pub const SA_SIGINFO: c_int = 0x40;
+    pub const SA_ONSTACK: c_int = 0x1;
+    pub const SA_RESETHAND: c_int = 0x4;
+    pub const SS_DISABLE: c_int = 4;
+    /// Where `si_code` is in `siginfo_t`, in `c_int`s.
+    #[cfg(target_os = "netbsd")]
+    pub const SI_CODE: usize = 1;
+    #[cfg(not(target_os = "netbsd"))]
+    pub const SI_CODE: usize = 2;
+
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "openbsd"))]
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_sigaction: usize,
+        pub sa_mask: u32,
+        pub sa_flags: c_int,
+    }
+
+    #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_sigaction: usize,
+        pub sa_flags: c_int,
+        pub sa_mask: [u32; 4],
+    }
+
+    #[cfg(target_os = "netbsd")]
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_sigaction: usize,
+        pub sa_mask: [u32; 4],
+        pub sa_flags: c_int,
+    }
+
+    #[repr(C)]
+    pub struct StackT {
+        pub ss_sp: *mut c_void,
+        pub ss_size: usize,
+        pub ss_flags: c_int,
+    }
+}
+
+use sys::{SigAction, StackT, SA_ONSTACK, SA_RESETHAND, SA_SIGINFO, SI_CODE, SS_DISABLE};
+
+extern "C" {
+    fn sigaction(signum: c_int, act: *const SigAction, oldact: *mut SigAction) -> c_int;
+    fn sigaltstack(ss: *const StackT, old_ss: *mut StackT) -> c_int;
+    fn raise(sig: c_int) -> c_int;
+    fn write(fd: c_int, buf: *const c_void, count: usize) -> isize;
+}
+
+/// Everything the handler needs, prepared when it's installed.
+struct CrashState {
+    /// The log file, opened for appending.
+    file: File,
+    /// The logger's buffer, in sync mode.
+    buffered: Option<Arc<Mutex<LogFile>>>,
+    recorder: Option<Arc<FlightRecorder>>,
+    /// The line announcing the crash, for each signal.
+    messages: Vec<(c_int, Vec<u8>)>,
+    /// The line introducing the flight recorder's records.
+    recorder_header: Vec<u8>,
+    /// Whether the file is MessagePack, so the messages are already frames and only the
+    /// flight recorder's records with frames can be written.
+    binary: bool,
+}
+
+static STATE: OnceLock<CrashState> = OnceLock::new();
+
+/// Whether a handler has dumped already, e.g. before the standard library's stack overflow
+/// handler aborted.
+static DUMPED: AtomicBool = AtomicBool::new(false);
+
+/// The handlers that were installed before ours, which ours hand the signal on to.
+static PREVIOUS: OnceLock<Vec<(c_int, SigAction)>> = OnceLock::new();
+
+/// Installs handlers for `SIGSEGV`, `SIGABRT`, `SIGBUS`, `SIGILL`, and `SIGFPE` that write the
+/// global logger's buffered records and flight recorder to the log file, then hand the signal
+/// on to whatever handled it before: usually its default action, so core dumps still happen,
+/// or the standard library's stack overflow handler, which still prints its message. Also done
+/// by `WOODY_CRASH_HANDLER=1`.
+///
+/// Only the first call has an effect.
+pub fn install_crash_handler() -> io::Result<()> {
+    install(&Logger::get_instance())
+}
+
+pub(crate) fn install(logger: &Logger) -> io::Result<()> {
+    let file = OpenOptions::new().append(true).open(logger.filename())?;
+    let line_ending = logger.line_ending.as_str();
+    let binary = logger.format == Format::MessagePack;
+    // The file's own format, since the handler can't encode anything.
+    let encode = |line: String| match binary {
+        true => msgpack::encode_lines(&line),
+        false => line.into_bytes(),
+    };
+    let messages = SIGNALS
+        .iter()
+        .map(|(sig, name)| {
+            (
+                *sig,
+                encode(format!("[woody: crash] caught {name}{line_ending}")),
+            )
+        })
+        .collect();
+    let state = CrashState {
+        file,
+        buffered: logger.file.shared_file(),
+        recorder: logger.recorder.clone(),
+        messages,
+        recorder_header: encode(format!(
+            "[woody: flight recorder] records before the crash{line_ending}"
+        )),
+        binary,
+    };
+    install_state(state)
+}
+
+/// Whether a crash handler is installed, so the flight recorder keeps frames for it.
+pub(crate) fn installed() -> bool {
+    STATE.get().is_some()
+}
+
+/// Installs the handlers with `state`, unless they're installed already.
+fn install_state(state: CrashState) -> io::Result<()> {
+    if STATE.set(state).is_err() {
+        return Ok(());
+    }
+    ensure_alt_stack()?;
+
+    let mut previous = Vec::with_capacity(SIGNALS.len());
+    for (sig, _) in SIGNALS {
+        // SAFETY: a zeroed `sigaction` is a valid value for `sigaction` to overwrite.
+        let mut old: SigAction = unsafe { std::mem::zeroed() };
+        // SAFETY: `old` is valid for writes, and a null `act` only queries.
+        if unsafe { sigaction(sig, ptr::null(), &mut old) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        previous.push((sig, old));
+    }
+    let _ = PREVIOUS.set(previous);
+
+    for (sig, _) in SIGNALS {
+        // SAFETY: a zeroed `sigaction` has an empty mask, which is then filled in.
+        let mut action: SigAction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handle as extern "C" fn(c_int, *mut c_void, *mut c_void) as usize;
+        // Reset to the default action on entry, so a crash inside the handler ends the
+        // process instead of looping.
+        action.sa_flags = SA_SIGINFO | SA_ONSTACK | SA_RESETHAND;
+        // SAFETY: `handle` has the signature `SA_SIGINFO` expects, and `action` is valid.
+        if unsafe { sigaction(sig, &action, ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Sets up an alternate signal stack for the calling thread, unless it has one.
+fn ensure_alt_stack() -> io::Result<()> {
+    // SAFETY: a zeroed `stack_t` is a valid value for `sigaltstack` to overwrite.
+    let mut current: StackT = unsafe { std::mem::zeroed() };
+    // SAFETY: `current` is valid for writes, and a null `ss` only queries.
+    if unsafe { sigaltstack(ptr::null(), &mut current) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if current.ss_flags & SS_DISABLE == 0 {
+        return Ok(());
+    }
+    // The stack has to outlive the thread's use of it, which may be as long as the process.
+    let stack: &'static mut [u8] = Box::leak(vec![0; ALT_STACK_SIZE].into_boxed_slice());
+    let stack = StackT {
+        ss_sp: stack.as_mut_ptr().cast(),
+        ss_flags: 0,
+        ss_size: ALT_STACK_SIZE,
+    };
+    // SAFETY: `stack` points at `ALT_STACK_SIZE` bytes that are never freed.
+    if unsafe { sigaltstack(&stack, ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+extern "C" fn handle(sig: c_int, info: *mut c_void, _context: *mut c_void) {
+    if let Some(state) = STATE.get().filter(|_| !DUMPED.swap(true, Ordering::SeqCst)) {
+        dump(state, sig);
+    }
+    let previous = PREVIOUS
+        .get()
+        .and_then(|previous| previous.iter().find(|(s, _)| *s == sig));
+    // SAFETY: `info` is the `siginfo_t` the kernel passed, which starts with `c_int`s.
+    let code = unsafe { *info.cast::<c_int>().add(SI_CODE) };
+    // SAFETY: `sigaction` and `raise` are async-signal-safe, and `previous` is a valid action.
+    unsafe {
+        if let Some((_, previous)) = previous {
+            sigaction(sig, previous, ptr::null_mut());
+        }
+        // A fault happens again when the faulting instruction is retried, and reaches the
+        // previous handler with its address intact. A signal that was sent has to be resent.
+        if !is_fault(code) {
+            raise(sig);
+        }
+    }
+}
+
+/// Whether `si_code` says the kernel raised the signal for a fault, rather than someone sending
+/// it. Sent signals have codes of zero or below on Linux, and above `0x10000` elsewhere.
+fn is_fault(code: c_int) -> bool {
+    0 < code && code < 0x10000
+}
+
+/// Writes the buffered records, the flight recorder, and the crash message. Skips anything
+/// whose lock is held, since the crash may have happened while holding it.
+fn dump(state: &CrashState, sig: c_int) {
+    let mut fd = state.file.as_raw_fd();
+    if let Some(buffered) = &state.buffered {
+        if let Ok(file) = buffered.try_lock() {
+            // The logger may have switched files since the handler was installed.
+            fd = file.raw_fd();
+            write_all(fd, file.buffer());
+        }
+    }
+    if let Some(recorder) = &state.recorder {
+        let mut header = Some(&state.recorder_header);
+        recorder.try_for_each(|record, frame| {
+            // Records remembered before the handler was installed have no frame.
+            let bytes = match (state.binary, frame) {
+                (false, _) => record.as_bytes(),
+                (true, Some(frame)) => frame,
+                (true, None) => return,
+            };
+            if let Some(header) = header.take() {
+                write_all(fd, header);
+            }
+            write_all(fd, bytes);
+        });
+    }
+    if let Some((_, message)) = state.messages.iter().find(|(s, _)| *s == sig) {
+        write_all(fd, message);
+    }
+}
+
+/// Writes all of `bytes` with `write(2)`, giving up on errors.
+fn write_all(fd: RawFd, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        // SAFETY: `bytes` is valid for `bytes.len()` bytes for the duration of the call.
+        let written = unsafe { write(fd, bytes.as_ptr().cast(), bytes.len()) };
+        if written <= 0 {
+            return;
+        }
+        bytes = &bytes[written as usize..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let path = std::env::temp_dir().join(format!("woody-crash-{}.log", std::process::id()));
+        let recorder = Arc::new(FlightRecorder::new(4));
+        recorder.push("[TRACE] before the crash\n".to_string(), None);
+        let state = CrashState {
+            file: File::create(&path).unwrap(),
+            buffered: None,
+            recorder: Some(recorder),
+            messages: vec![(SIGABRT, b"[woody: crash] caught SIGABRT\n".to_vec())],
+            recorder_header: b"[woody: flight recorder]\n".to_vec(),
+            binary: false,
+        };
+
+        dump(&state, SIGABRT);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            contents,
+            "[woody: flight recorder]\n[TRACE] before the crash\n[woody: crash] caught SIGABRT\n"
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_dump_msgpack() {
+        let path = std::env::temp_dir().join(format!("woody-crash-mp-{}.log", std::process::id()));
+        let recorder = Arc::new(FlightRecorder::new(4));
+        recorder.push("[TRACE] no frame\n".to_string(), None);
+        let line = "[TRACE] before the crash\n";
+        recorder.push(line.to_string(), Some(msgpack::encode_lines(line)));
+        let state = CrashState {
+            file: File::create(&path).unwrap(),
+            buffered: None,
+            recorder: Some(recorder),
+            messages: vec![(
+                SIGABRT,
+                msgpack::encode_lines("[woody: crash] caught SIGABRT\n"),
+            )],
+            recorder_header: msgpack::encode_lines("[woody: flight recorder]\n"),
+            binary: true,
+        };
+
+        dump(&state, SIGABRT);
+        let contents = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            msgpack::decode(&contents).unwrap(),
+            "[woody: flight recorder]\n[TRACE] before the crash\n[woody: crash] caught SIGABRT\n"
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow_is_reported() {
+        // The child is this test binary running only this test, which overflows its stack
+        // when it finds this set.
+        const CHILD: &str = "WOODY_TEST_OVERFLOW_CHILD";
+        if let Some(path) = std::env::var_os(CHILD) {
+            fn recurse(depth: u64) -> u64 {
+                let frame = std::hint::black_box([depth; 64]);
+                if depth == u64::MAX {
+                    return 0;
+                }
+                recurse(depth + 1) + frame[0]
+            }
+            install_state(CrashState {
+                file: File::create(path).unwrap(),
+                buffered: None,
+                recorder: None,
+                messages: vec![(SIGSEGV, b"[woody: crash] caught SIGSEGV\n".to_vec())],
+                recorder_header: Vec::new(),
+                binary: false,
+            })
+            .unwrap();
+            std::hint::black_box(recurse(0));
+            return;
+        }
+
+        let path = std::env::temp_dir().join(format!("woody-overflow-{}.log", std::process::id()));
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "crash::tests::test_stack_overflow_is_reported"])
+            .env(CHILD, &path)
+            .output()
+            .unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("has overflowed its stack"), "{stderr}");
+        assert_eq!(contents, "[woody: crash] caught SIGSEGV\n");
+    }
+}
@@ -0,0 +1,310 @@
+//! Field-level diffs of two values, so state changes can be logged as a concise list of what
+//! changed instead of two full dumps.
+//!
+//! Values are compared through their pretty `Debug` output, which is flattened into
+//! `path = value` leaves (`db.hosts[1] = "replica"`), so any `#[derive(Debug)]` type works. A
+//! hand-written `Debug` that doesn't nest like the derived one is compared as a single value.
+//!
+//! `log_diff!` logs each change as a structured field named by its struct fields, so redaction
+//! by name (`WOODY_REDACT_FIELDS`) applies to them like to any other field.
+use std::{collections::BTreeMap, fmt::Debug};
+
+use crate::{fields::intern, Fields};
+
+/// A single field that differs between two values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldChange {
+    /// The path to the field, e.g. `db.hosts[1]`. Empty when the values themselves are leaves.
+    pub path: String,
+    /// The old value, or `None` if the field was added.
+    pub old: Option<String>,
+    /// The new value, or `None` if the field was removed.
+    pub new: Option<String>,
+}
+
+impl std::fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let old = self.old.as_deref().unwrap_or("<none>");
+        let new = self.new.as_deref().unwrap_or("<none>");
+        if self.path.is_empty() {
+            return write!(f, "{old} -> {new}");
+        }
+        write!(f, "{}: {old} -> {new}", self.path)
+    }
+}
+
+/// Returns the fields that differ between `old` and `new`, ordered by path.
+pub fn diff<T: Debug + ?Sized, U: Debug + ?Sized>(old: &T, new: &U) -> Vec<FieldChange> {
+    let old = flatten(&format!("{old:#?}"));
+    let mut new = flatten(&format!("{new:#?}"));
+
+    let mut changes = Vec::new();
+    for (path, old_value) in old {
+        match new.remove(&path) {
+            Some(new_value) if new_value == old_value => {}
+            new_value => changes.push(FieldChange {
+                path,
+                old: Some(old_value),
+                new: new_value,
+            }),
+        }
+    }
+    for (path, new_value) in new {
+        changes.push(FieldChange {
+            path,
+            old: None,
+            new: Some(new_value),
+        });
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Formats `message` followed by the changes between `old` and `new`. Used by `log_diff!`.
+pub fn describe<T: Debug + ?Sized, U: Debug + ?Sized>(message: &str, old: &T, new: &U) -> String {
+    let changes = diff(old, new);
+    if changes.is_empty() {
+        return format!("{message}: no changes");
+    }
+    let changes: Vec<String> = changes.iter().map(ToString::to_string).collect();
+    format!("{message}: {}", changes.join(", "))
+}
+
+/// The changes between `old` and `new` as fields named by their paths, with `old -> new`
+/// values (`<none>` for an added or removed field). A change of the values themselves is
+/// named `value`. Used by `log_diff!`.
+///
+/// Only struct field names go in the name, since field names live for the rest of the process:
+/// list indices and map keys go at the start of the value instead, so `hosts[2]` is `hosts`
+/// with `[2]: <none> -> "backup"`.
+pub fn fields<T: Debug + ?Sized, U: Debug + ?Sized>(old: &T, new: &U) -> Fields {
+    diff(old, new)
+        .into_iter()
+        .map(|change| {
+            let old = change.old.as_deref().unwrap_or("<none>");
+            let new = change.new.as_deref().unwrap_or("<none>");
+            let (name, selectors) = split_path(&change.path);
+            let name = match name.as_str() {
+                "" => "value",
+                name => intern(name),
+            };
+            match selectors.is_empty() {
+                true => (name, format!("{old} -> {new}")),
+                false => (name, format!("{selectors}: {old} -> {new}")),
+            }
+        })
+        .collect()
+}
+
+/// Splits a path into its struct field names (`users.token`) and its list indices and map
+/// keys (`[0]`, `"team"`), as they appear in it.
+fn split_path(path: &str) -> (String, String) {
+    let (mut name, mut selectors) = (String::new(), String::new());
+    let mut chars = path.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' if chars.peek() == Some(&'"') => {}
+            '.' if !name.is_empty() => name.push('.'),
+            '.' => {}
+            '[' => {
+                selectors.push('[');
+                for c in chars.by_ref() {
+                    selectors.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                // Map keys are debug strings, so skip over escaped quotes to find the end.
+                selectors.push('"');
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    selectors.push(c);
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => break,
+                        _ => escaped = false,
+                    }
+                }
+            }
+            c => name.push(c),
+        }
+    }
+    (name, selectors)
+}
+
+/// One level of nesting while flattening.
+struct Frame {
+    path: String,
+    /// The next index, for list-like frames.
+    index: usize,
+}
+
+/// Flattens pretty `Debug` output into a map from field path to value.
+fn flatten(pretty: &str) -> BTreeMap<String, String> {
+    let mut leaves = BTreeMap::new();
+    let mut stack: Vec<Frame> = Vec::new();
+
+    for line in pretty.lines() {
+        let line = line.trim().trim_end_matches(',');
+        if line.starts_with(['}', ']', ')']) {
+            stack.pop();
+            continue;
+        }
+
+        let (path, value) = match stack.last_mut() {
+            None => (String::new(), line),
+            Some(frame) => match split_key(line) {
+                Some((key, value)) if frame.path.is_empty() => (key.to_string(), value),
+                Some((key, value)) => (format!("{}.{key}", frame.path), value),
+                None => {
+                    frame.index += 1;
+                    (format!("{}[{}]", frame.path, frame.index - 1), line)
+                }
+            },
+        };
+
+        if value.ends_with(['{', '[', '(']) {
+            stack.push(Frame { path, index: 0 });
+        } else if value != "{}" && value != "[]" {
+            // Empty collections have no leaves, so removing the last element reads the same
+            // as removing any other.
+            leaves.insert(path, value.to_string());
+        }
+    }
+
+    leaves
+}
+
+/// Splits `key: value` (struct fields) or `"key": value` (maps) into key and value.
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    if let Some(rest) = line.strip_prefix('"') {
+        // Map keys are debug strings, so skip over escaped quotes to find the end.
+        let mut escaped = false;
+        for (i, c) in rest.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => {
+                    let value = rest[i + 1..].strip_prefix(": ")?;
+                    return Some((&line[..i + 2], value));
+                }
+                _ => escaped = false,
+            }
+        }
+        return None;
+    }
+
+    let (key, value) = line.split_once(": ")?;
+    let is_field = key.chars().all(|c| c.is_alphanumeric() || c == '_');
+    is_field.then_some((key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Db {
+        url: String,
+        hosts: Vec<&'static str>,
+    }
+
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    struct Config {
+        port: u16,
+        db: Db,
+        labels: HashMap<&'static str, &'static str>,
+    }
+
+    fn config() -> Config {
+        Config {
+            port: 8080,
+            db: Db {
+                url: "postgres://localhost".to_string(),
+                hosts: vec!["primary", "replica"],
+            },
+            labels: HashMap::from([("team", "core")]),
+        }
+    }
+
+    #[test]
+    fn test_diff_fields() {
+        let old = config();
+        let mut new = config();
+        new.port = 9090;
+        new.db.hosts.push("backup");
+        new.labels.clear();
+
+        let changes: Vec<String> = diff(&old, &new).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            changes,
+            [
+                r#"db.hosts[2]: <none> -> "backup""#,
+                r#"labels."team": "core" -> <none>"#,
+                "port: 8080 -> 9090",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe_no_changes() {
+        assert_eq!(
+            describe("config reloaded", &config(), &config()),
+            "config reloaded: no changes"
+        );
+        assert_eq!(describe("count", &1, &2), "count: 1 -> 2");
+    }
+
+    #[test]
+    fn test_fields() {
+        let old = config();
+        let mut new = config();
+        new.port = 9090;
+        new.db.url = "postgres://db".to_string();
+        let fields = fields(&old, &new);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(
+            fields.get("db.url"),
+            Some(&crate::Value::from(
+                r#""postgres://localhost" -> "postgres://db""#
+            ))
+        );
+        assert_eq!(
+            fields.get("port"),
+            Some(&crate::Value::from("8080 -> 9090"))
+        );
+        assert_eq!(
+            super::fields(&1, &2).get("value"),
+            Some(&crate::Value::from("1 -> 2"))
+        );
+    }
+
+    #[test]
+    fn test_fields_keep_indices_and_keys_out_of_names() {
+        let old = config();
+        let mut new = config();
+        new.db.hosts.push("backup");
+        new.labels.insert("region", "eu");
+        let fields = super::fields(&old, &new);
+        assert_eq!(
+            fields.get("db.hosts"),
+            Some(&crate::Value::from(r#"[2]: <none> -> "backup""#))
+        );
+        assert_eq!(
+            fields.get("labels"),
+            Some(&crate::Value::from(r#""region": <none> -> "eu""#))
+        );
+        assert_eq!(
+            split_path(r#"users[0].tags."a.\"b[".token"#),
+            ("users.tags.token".to_string(), r#"[0]"a.\"b[""#.to_string())
+        );
+        assert_eq!(
+            super::fields(&[1], &[2]).get("value"),
+            Some(&crate::Value::from("[0]: 1 -> 2"))
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Errors on their own: a separate file with only the records at `ERROR` and above, and a
+//! count of them over the last minute for self-health checks.
+#[cfg(feature = "file")]
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "file")]
+use crate::file::{self, FilePermissions};
+
+/// The size at which the error file is rotated, by default.
+#[cfg(feature = "file")]
+pub(crate) const DEFAULT_ERROR_FILE_MAX_BYTES: u64 = 10 << 20;
+
+static RATE: ErrorRate = ErrorRate::new();
+static TOTAL: AtomicU64 = AtomicU64::new(0);
+
+/// The number of records at `ERROR` and above logged in the last 60 seconds, e.g. to report
+/// unhealthy when it climbs past a threshold.
+///
+/// # Examples
+/// ```
+/// woody::log_error!("upstream timed out");
+/// assert!(woody::errors_last_minute() >= 1);
+/// ```
+pub fn errors_last_minute() -> u64 {
+    RATE.sum(now())
+}
+
+/// The number of records at `ERROR` and above logged since the process started.
+pub fn errors_total() -> u64 {
+    TOTAL.load(Ordering::Relaxed)
+}
+
+/// Counts `records` just logged at `ERROR` or above.
+pub(crate) fn count(records: u64) {
+    RATE.add(now(), records);
+    TOTAL.fetch_add(records, Ordering::Relaxed);
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Counts per second over the last minute, in a ring indexed by the second.
+struct ErrorRate {
+    /// The second each bucket counts, and its count.
+    buckets: Mutex<[(u64, u64); 60]>,
+}
+
+impl ErrorRate {
+    const fn new() -> Self {
+        Self {
+            buckets: Mutex::new([(0, 0); 60]),
+        }
+    }
+
+    fn add(&self, second: u64, records: u64) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = &mut buckets[(second % 60) as usize];
+        if bucket.0 != second {
+            *bucket = (second, 0);
+        }
+        bucket.1 += records;
+    }
+
+    fn sum(&self, second: u64) -> u64 {
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets
+            .iter()
+            .filter(|(at, _)| second.saturating_sub(*at) < 60)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// A file receiving only the records at `ERROR` and above, rotated to `<path>.1` once it would
+/// grow past `max_bytes`.
+#[cfg(feature = "file")]
+#[derive(Debug)]
+pub(crate) struct ErrorFile {
+    path: PathBuf,
+    max_bytes: u64,
+    /// The open file and its length, opened on the first error.
+    file: Mutex<Option<(File, u64)>>,
+}
+
+#[cfg(feature = "file")]
+impl ErrorFile {
+    pub(crate) fn new(path: &Path, max_bytes: u64) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Reads `WOODY_ERROR_FILE` and `WOODY_ERROR_FILE_MAX_BYTES`.
+    pub(crate) fn from_config() -> Option<Self> {
+        let path = crate::config::setting("WOODY_ERROR_FILE")?;
+        let max_bytes = crate::config::setting("WOODY_ERROR_FILE_MAX_BYTES")
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(DEFAULT_ERROR_FILE_MAX_BYTES);
+        Some(Self::new(&crate::expand_path(&path), max_bytes))
+    }
+
+    /// Appends formatted records. Failures are only counted, like those of the log file.
+    pub(crate) fn write(&self, output: &str) {
+        if let Err(e) = self.try_write(output.as_bytes()) {
+            crate::internal::event(crate::LogLevel::Warning, || {
+                format!(
+                    "couldn't write to the error file {}: {e}",
+                    self.path.display()
+                )
+            });
+            #[cfg(feature = "metrics")]
+            crate::metrics::sink_error();
+        }
+    }
+
+    /// Syncs the file to disk, if an error has opened it.
+    pub(crate) fn flush(&self) {
+        let file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(Err(e)) = file.as_ref().map(|(file, _)| file.sync_data()) {
+            crate::internal::event(crate::LogLevel::Warning, || {
+                format!("couldn't sync the error file {}: {e}", self.path.display())
+            });
+            #[cfg(feature = "metrics")]
+            crate::metrics::sink_error();
+        }
+    }
+
+    fn try_write(&self, output: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if file.is_none() {
+            *file = Some(self.open()?);
+        }
+        let full = file
+            .as_ref()
+            .is_some_and(|(_, len)| *len > 0 && len + output.len() as u64 > self.max_bytes);
+        if full {
+            *file = None;
+            let mut archive = self.path.clone().into_os_string();
+            archive.push(".1");
+            std::fs::rename(&self.path, &archive)?;
+            crate::internal::event(crate::LogLevel::Debug, || {
+                format!("rotated the error file to {}", archive.to_string_lossy())
+            });
+            *file = Some(self.open()?);
+        }
+        let (file, len) = file.as_mut().expect("opened above");
+        file.write_all(output)?;
+        *len += output.len() as u64;
+        Ok(())
+    }
+
+    fn open(&self) -> io::Result<(File, u64)> {
+        let file = file::open(&self.path, FilePermissions::from_env())?;
+        let len = file.metadata()?.len();
+        Ok((file, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_forgets_old_seconds() {
+        let rate = ErrorRate::new();
+        rate.add(1_000, 2);
+        rate.add(1_030, 1);
+        assert_eq!(rate.sum(1_030), 3);
+        assert_eq!(rate.sum(1_059), 3);
+        assert_eq!(rate.sum(1_060), 1);
+        rate.add(1_060, 5); // Reuses the bucket of second 1000.
+        assert_eq!(rate.sum(1_060), 6);
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn test_error_file_rotates() {
+        let dir = std::env::temp_dir().join(format!("woody-errors-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("errors.log");
+        let errors = ErrorFile::new(&path, 10);
+        errors.write("first\n");
+        errors.write("second\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second\n");
+        let archive = std::fs::read_to_string(dir.join("errors.log.1")).unwrap();
+        assert_eq!(archive, "first\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
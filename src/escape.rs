@@ -0,0 +1,62 @@
+//! Escaping for the text format, so a message can't forge log lines (log injection) and every
+//! record stays on one line that splits cleanly into its bracketed tokens.
+use std::fmt::Write;
+
+/// Escapes a message: backslashes, newlines, and other control characters are written as
+/// escape sequences (`\\`, `\n`, `\u{1b}`), so the record ends at the line ending.
+pub(crate) fn message(s: &str) -> String {
+    escape(s, false)
+}
+
+/// Escapes a token written inside brackets, like the thread name. Like [`message`], and `]` is
+/// written as `\]` so the token can't end early.
+pub(crate) fn token(s: &str) -> String {
+    escape(s, true)
+}
+
+fn escape(s: &str, bracketed: bool) -> String {
+    if !s.chars().any(|c| needs_escape(c, bracketed)) {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ']' if bracketed => out.push_str("\\]"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{{{:x}}}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn needs_escape(c: char, bracketed: bool) -> bool {
+    c == '\\' || c.is_control() || (bracketed && c == ']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_message() {
+        assert_eq!(message("héllo, 世界"), "héllo, 世界");
+        assert_eq!(
+            message("ok\n[2024-01-01] [ERROR] forged"),
+            "ok\\n[2024-01-01] [ERROR] forged"
+        );
+        assert_eq!(message("a\\nb\r\t\x1b[31m"), "a\\\\nb\\r\\t\\u{1b}[31m");
+        assert_eq!(message("[x]"), "[x]");
+    }
+
+    #[test]
+    fn test_escape_token() {
+        assert_eq!(token("worker] [ERROR"), "worker\\] [ERROR");
+        assert_eq!(token("main"), "main");
+    }
+}
@@ -0,0 +1,274 @@
+//! Structured fields attached to a record, e.g. `user_id=42`. Stored inline for typical
+//! records, so attaching a few numbers or static strings doesn't allocate.
+use std::{borrow::Cow, collections::BTreeSet, sync::Mutex};
+
+/// How many fields are stored without allocating.
+pub const INLINE_FIELDS: usize = 4;
+
+/// The name of a field.
+pub type Key = &'static str;
+
+/// The value of a field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A signed integer.
+    I64(i64),
+    /// An unsigned integer.
+    U64(u64),
+    /// A floating point number.
+    F64(f64),
+    /// A boolean.
+    Bool(bool),
+    /// A string. Static strings are borrowed, so they don't allocate.
+    Str(Cow<'static, str>),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::I64(x) => write!(f, "{x}"),
+            Value::U64(x) => write!(f, "{x}"),
+            Value::F64(x) => write!(f, "{x}"),
+            Value::Bool(x) => write!(f, "{x}"),
+            Value::Str(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($variant:ident as $target:ty: $($source:ty),+) => {
+        $(impl From<$source> for Value {
+            fn from(x: $source) -> Self {
+                Value::$variant(x as $target)
+            }
+        })+
+    };
+}
+
+impl_from!(I64 as i64: i8, i16, i32, i64, isize);
+impl_from!(U64 as u64: u8, u16, u32, u64, usize);
+impl_from!(F64 as f64: f32, f64);
+
+impl From<bool> for Value {
+    fn from(x: bool) -> Self {
+        Value::Bool(x)
+    }
+}
+
+/// Static strings are borrowed. Convert shorter-lived ones with `to_string()`.
+impl From<&'static str> for Value {
+    fn from(x: &'static str) -> Self {
+        Value::Str(Cow::Borrowed(x))
+    }
+}
+
+impl From<String> for Value {
+    fn from(x: String) -> Self {
+        Value::Str(Cow::Owned(x))
+    }
+}
+
+impl From<&String> for Value {
+    fn from(x: &String) -> Self {
+        Value::Str(Cow::Owned(x.clone()))
+    }
+}
+
+impl From<Cow<'static, str>> for Value {
+    fn from(x: Cow<'static, str>) -> Self {
+        Value::Str(x)
+    }
+}
+
+/// The fields of a record, in the order they were added. The first [`INLINE_FIELDS`] are
+/// stored inline; only records with more than that allocate.
+///
+/// # Examples
+/// ```
+/// use woody::{Fields, Value};
+/// let mut fields = Fields::new();
+/// fields.push("user_id", 42);
+/// fields.push("cached", true);
+/// assert_eq!(fields.get("user_id"), Some(&Value::I64(42)));
+/// let keys: Vec<_> = fields.iter().map(|(key, _)| *key).collect();
+/// assert_eq!(keys, ["user_id", "cached"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fields {
+    inline: [Option<(Key, Value)>; INLINE_FIELDS],
+    spilled: Vec<(Key, Value)>,
+}
+
+impl Fields {
+    /// Creates an empty set of fields.
+    pub const fn new() -> Self {
+        Self {
+            inline: [const { None }; INLINE_FIELDS],
+            spilled: Vec::new(),
+        }
+    }
+
+    /// Adds a field.
+    pub fn push(&mut self, key: Key, value: impl Into<Value>) {
+        let field = (key, value.into());
+        match self.inline.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(field),
+            None => self.spilled.push(field),
+        }
+    }
+
+    /// The value of the first field named `key`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// The fields, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &(Key, Value)> {
+        self.inline
+            .iter()
+            .map_while(Option::as_ref)
+            .chain(&self.spilled)
+    }
+
+    /// The number of fields.
+    pub fn len(&self) -> usize {
+        self.inline.iter().filter(|slot| slot.is_some()).count() + self.spilled.len()
+    }
+
+    /// Whether there are no fields.
+    pub fn is_empty(&self) -> bool {
+        self.inline[0].is_none()
+    }
+}
+
+impl<K: Into<Key>, V: Into<Value>> FromIterator<(K, V)> for Fields {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut fields = Fields::new();
+        for (key, value) in iter {
+            fields.push(key.into(), value);
+        }
+        fields
+    }
+}
+
+/// Field keys are static, so keys only known at run time are leaked, once each.
+pub(crate) fn intern(key: &str) -> Key {
+    static KEYS: Mutex<BTreeSet<Key>> = Mutex::new(BTreeSet::new());
+    let mut keys = KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(key) = keys.get(key) {
+        return key;
+    }
+    let key: Key = Box::leak(key.to_string().into_boxed_str());
+    keys.insert(key);
+    key
+}
+
+/// Reads the fields to add to every record from `WOODY_FIELDS`, e.g. `service=billing,env=prod`
+/// or, in the config file, `fields = { service = "billing", env = "prod" }`. Values are kept as
+/// strings.
+pub(crate) fn from_config() -> Fields {
+    crate::config::setting("WOODY_FIELDS")
+        .map(|x| parse(&x))
+        .unwrap_or_default()
+}
+
+fn parse(s: &str) -> Fields {
+    let s = s.trim();
+    let s = s
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(s);
+    s.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| {
+            // Keys are static, so the few set at startup are leaked.
+            let key: Key = Box::leak(key.trim().to_string().into_boxed_str());
+            (key, value.trim().trim_matches('"').to_string())
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// Formats fields for the text format, e.g. ` user_id=42 path="/a b"`. Strings are quoted if
+/// they'd be ambiguous otherwise.
+pub(crate) fn format(fields: &Fields) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| format_field(key, value))
+        .collect()
+}
+
+/// Formats one field for the text format, e.g. ` user_id=42`. Characters in the key that
+/// would end it or forge another field or line, like whitespace, `=` and `]`, are written as
+/// `_`.
+pub(crate) fn format_field(key: &str, value: &Value) -> String {
+    let value = match value {
+        Value::Str(s) if needs_quotes(s) => format!("{s:?}"),
+        value => crate::escape::message(&value.to_string()),
+    };
+    match key.chars().any(unsafe_in_key) {
+        true => format!(" {}={value}", key.replace(unsafe_in_key, "_")),
+        false => format!(" {key}={value}"),
+    }
+}
+
+fn unsafe_in_key(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || matches!(c, '"' | '=' | '\\' | '[' | ']')
+}
+
+fn needs_quotes(s: &str) -> bool {
+    s.is_empty()
+        || s.chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '"' | '=' | '\\'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_leaks_each_key_once() {
+        let key = String::from("region");
+        assert!(std::ptr::eq(intern(&key), intern("region")));
+    }
+
+    #[test]
+    fn test_spills_past_inline() {
+        let mut fields: Fields = [("a", 1), ("b", 2), ("c", 3)].into_iter().collect();
+        assert!(fields.spilled.capacity() == 0);
+        fields.push("d", 4u8);
+        fields.push("e", "five");
+        assert_eq!(fields.len(), 5);
+        assert_eq!(fields.spilled.len(), 1);
+        assert_eq!(fields.get("e"), Some(&Value::Str(Cow::Borrowed("five"))));
+        assert!(!fields.is_empty() && Fields::new().is_empty());
+    }
+
+    #[test]
+    fn test_parse_static_fields() {
+        let fields = parse(r#"{ service = "billing", env = prod, }"#);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields.get("service"), Some(&Value::from("billing")));
+        assert_eq!(fields.get("env"), Some(&Value::from("prod")));
+        assert_eq!(parse("service=billing").len(), 1);
+    }
+
+    #[test]
+    fn test_format_fields() {
+        let mut fields = Fields::new();
+        fields.push("id", 7u64);
+        fields.push("ratio", 0.5);
+        fields.push("path", "/tmp/a b");
+        fields.push("name", "ok".to_string());
+        assert_eq!(
+            format(&fields),
+            r#" id=7 ratio=0.5 path="/tmp/a b" name=ok"#
+        );
+        let mut evil = Fields::new();
+        evil.push("msg", "x\n[ERROR] forged");
+        assert_eq!(format(&evil), r#" msg="x\n[ERROR] forged""#);
+        let mut evil = Fields::new();
+        evil.push("a=1 b\n[ERROR]", "x");
+        assert_eq!(format(&evil), " a_1_b__ERROR_=x");
+    }
+}
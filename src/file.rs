@@ -0,0 +1,342 @@
+//! Finding and opening log files.
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Permissions applied to log files, so security policies like "no world-readable logs" hold
+/// without a wrapper script. Only has an effect on Unix.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FilePermissions {
+    /// Mode bits, e.g. `0o640`.
+    pub(crate) mode: Option<u32>,
+    /// The group ID to hand the file to.
+    pub(crate) group: Option<u32>,
+}
+
+impl FilePermissions {
+    /// Reads `WOODY_FILE_MODE` (octal, e.g. `640`) and `WOODY_FILE_GROUP` (a numeric group ID).
+    pub(crate) fn from_env() -> Self {
+        let var = crate::config::setting;
+        Self {
+            mode: var("WOODY_FILE_MODE").and_then(|x| u32::from_str_radix(x.trim(), 8).ok()),
+            group: var("WOODY_FILE_GROUP").and_then(|x| x.trim().parse().ok()),
+        }
+    }
+}
+
+/// Expands a leading `~` to the home directory and `$VAR` or `${VAR}` (also `%VAR%` on
+/// Windows) to the value of the environment variable. Variables that aren't set are left as
+/// they are, so a typo can't turn `$LOGDIR/app.log` into `/app.log`.
+///
+/// # Examples
+/// ```
+/// std::env::set_var("WOODY_DOC_DIR", "/var/log/app");
+/// assert_eq!(
+///     woody::expand_path("${WOODY_DOC_DIR}/woody.log"),
+///     std::path::PathBuf::from("/var/log/app/woody.log")
+/// );
+/// ```
+pub fn expand_path(path: &str) -> PathBuf {
+    let mut expanded = String::new();
+    let mut rest = path;
+
+    if let Some(after) = rest.strip_prefix('~') {
+        if after.is_empty() || after.starts_with(['/', '\\']) {
+            if let Some(home) = home_dir() {
+                expanded.push_str(&home.to_string_lossy());
+                rest = after;
+            }
+        }
+    }
+
+    while let Some(i) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..i]);
+        let (name, len) = variable_at(&rest[i..]);
+        match name.and_then(|name| env::var(name).ok()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[i..i + len]),
+        }
+        rest = &rest[i + len..];
+    }
+    expanded.push_str(rest);
+
+    PathBuf::from(expanded)
+}
+
+/// Parses the variable reference at the start of `s`, returning its name (if it is one) and
+/// how many bytes it spans.
+fn variable_at(s: &str) -> (Option<&str>, usize) {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    if let Some(braced) = s.strip_prefix("${") {
+        if let Some(end) = braced.find('}') {
+            return (Some(&braced[..end]), end + 3);
+        }
+    } else if let Some(name) = s.strip_prefix('$') {
+        let end = name.find(|c| !is_name(c)).unwrap_or(name.len());
+        if end > 0 {
+            return (Some(&name[..end]), end + 1);
+        }
+    } else if cfg!(windows) {
+        if let Some(name) = s.strip_prefix('%') {
+            if let Some(end) = name.find('%') {
+                if end > 0 && name[..end].chars().all(is_name) {
+                    return (Some(&name[..end]), end + 2);
+                }
+            }
+        }
+    }
+    (None, 1)
+}
+
+fn home_dir() -> Option<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env::var_os(var)
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Returns where a desktop application named `app` should keep its log on this platform:
+///
+/// - Linux and other Unixes: `$XDG_STATE_HOME/<app>/woody.log` (`~/.local/state` by default)
+/// - macOS: `~/Library/Logs/<app>/woody.log`
+/// - Windows: `%LOCALAPPDATA%\<app>\woody.log`
+///
+/// Returns `None` if the needed directories can't be determined from the environment.
+pub fn platform_log_path(app: &str) -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|home| home.join("Library").join("Logs"))
+    } else {
+        env::var_os("XDG_STATE_HOME")
+            .filter(|x| Path::new(x).is_absolute())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".local").join("state")))
+    }?;
+    Some(dir.join(app).join("woody.log"))
+}
+
+/// How many times to try opening the file when other processes keep creating and removing it
+/// in between, e.g. while rotating.
+const OPEN_ATTEMPTS: usize = 8;
+
+/// Opens `path` for appending, creating it (and its parent directories) if needed, and applies
+/// `permissions`.
+pub(crate) fn open(path: &Path, permissions: FilePermissions) -> io::Result<File> {
+    open_with_header(path, permissions, &[])
+}
+
+/// Like [`open`], but a file it creates starts with `header`, like a byte order mark.
+///
+/// When several processes start at once, they may all find the file missing. Only one of them
+/// creates it, with `create_new`, and it's linked into place with its header already written,
+/// so no record can come before the header. The others open the file it created. Every handle
+/// appends (`O_APPEND`), so records from different processes never overwrite each other.
+pub(crate) fn open_with_header(
+    path: &Path,
+    permissions: FilePermissions,
+    header: &[u8],
+) -> io::Result<File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    for _ in 0..OPEN_ATTEMPTS {
+        match options(permissions).open(path) {
+            Ok(file) => return apply(file, permissions),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        match create(path, permissions, header) {
+            Ok(file) => return apply(file, permissions),
+            // Another process created it first; open theirs.
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::other(format!(
+        "{} kept appearing and disappearing while opening it",
+        path.display()
+    )))
+}
+
+/// Options for appending to an existing file, and creating it with the right mode on Unix.
+fn options(permissions: FilePermissions) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.append(true);
+    #[cfg(unix)]
+    if let Some(mode) = permissions.mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        // Create the file with the right mode so it's never readable by others, not even
+        // briefly.
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = permissions;
+    options
+}
+
+/// Creates `path` starting with `header`, failing with `AlreadyExists` if it's there already.
+fn create(path: &Path, permissions: FilePermissions, header: &[u8]) -> io::Result<File> {
+    let mut options = options(permissions);
+    options.create_new(true);
+    if header.is_empty() {
+        return options.open(path);
+    }
+    static TEMP: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(
+        ".{name}.{}-{}.tmp",
+        std::process::id(),
+        TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = options.open(&temp)?;
+    let linked = file
+        .write_all(header)
+        .and_then(|()| std::fs::hard_link(&temp, path));
+    let _ = std::fs::remove_file(&temp);
+    match linked {
+        Ok(()) => Ok(file),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        // Without hard links, the header is written right after creating the file instead.
+        Err(_) => {
+            let mut file = options.open(path)?;
+            file.write_all(header)?;
+            Ok(file)
+        }
+    }
+}
+
+/// Applies `permissions` to an opened file.
+fn apply(file: File, permissions: FilePermissions) -> io::Result<File> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(mode) = permissions.mode {
+            // The mode passed to `open` is masked by the umask and ignored for existing files.
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        }
+        if let Some(group) = permissions.group {
+            std::os::unix::fs::fchown(&file, None, Some(group))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = permissions;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_path() {
+        env::set_var("WOODY_TEST_EXPAND", "state");
+        assert_eq!(
+            expand_path("/var/$WOODY_TEST_EXPAND/${WOODY_TEST_EXPAND}s/app.log"),
+            PathBuf::from("/var/state/states/app.log")
+        );
+        assert_eq!(
+            expand_path("$WOODY_TEST_UNSET/app-$5.log"),
+            PathBuf::from("$WOODY_TEST_UNSET/app-$5.log")
+        );
+        assert_eq!(expand_path("~user/app.log"), PathBuf::from("~user/app.log"));
+        if let Some(home) = home_dir() {
+            assert_eq!(expand_path("~/app.log"), home.join("app.log"));
+        }
+    }
+
+    #[test]
+    fn test_concurrent_first_open() {
+        // Each child is this test binary running only this test, which opens the file when it
+        // finds these set, at the same agreed time as the others.
+        const PATH: &str = "WOODY_TEST_RACE_PATH";
+        const AT: &str = "WOODY_TEST_RACE_AT";
+        const CHILD: &str = "WOODY_TEST_RACE_CHILD";
+        if let (Ok(path), Ok(at), Ok(child)) = (env::var(PATH), env::var(AT), env::var(CHILD)) {
+            let at = std::time::UNIX_EPOCH + std::time::Duration::from_millis(at.parse().unwrap());
+            if let Ok(wait) = at.duration_since(std::time::SystemTime::now()) {
+                std::thread::sleep(wait);
+            }
+            let mut file = open_with_header(
+                Path::new(&path),
+                FilePermissions::default(),
+                b"\xEF\xBB\xBF",
+            )
+            .unwrap();
+            file.write_all(format!("record {child}\n").as_bytes())
+                .unwrap();
+            return;
+        }
+
+        let path = env::temp_dir().join(format!("woody-race-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let at = std::time::SystemTime::now() + std::time::Duration::from_millis(500);
+        let at = at
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let children: Vec<_> = (0..8)
+            .map(|i| {
+                std::process::Command::new(env::current_exe().unwrap())
+                    .args(["--exact", "file::tests::test_concurrent_first_open"])
+                    .env(PATH, &path)
+                    .env(AT, at.to_string())
+                    .env(CHILD, i.to_string())
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(contents.starts_with(b"\xEF\xBB\xBF"));
+        let text = String::from_utf8(contents[3..].to_vec()).unwrap();
+        assert!(!text.contains('\u{feff}'), "{text:?}");
+        let mut lines: Vec<_> = text.lines().collect();
+        lines.sort_unstable();
+        let expected: Vec<_> = (0..8).map(|i| format!("record {i}")).collect();
+        assert_eq!(lines, expected, "{text:?}");
+    }
+
+    #[test]
+    fn test_platform_log_path() {
+        if let Some(path) = platform_log_path("my-app") {
+            assert!(path.ends_with(Path::new("my-app").join("woody.log")));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mode_and_group() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let path = std::env::temp_dir().join(format!("woody-mode-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // Handing the file to the group it already has is the only change that's always allowed.
+        let own_group = open(&path, FilePermissions::default())
+            .unwrap()
+            .metadata()
+            .unwrap()
+            .gid();
+        std::fs::remove_file(&path).unwrap();
+        let permissions = FilePermissions {
+            mode: Some(0o640),
+            group: Some(own_group),
+        };
+
+        let file = open(&path, permissions).unwrap();
+        let metadata = file.metadata().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert_eq!(metadata.gid(), own_group);
+    }
+}
@@ -0,0 +1,146 @@
+//! Filters records by where they come from, so generated code or a dependency that logs too
+//! much can be silenced wholesale.
+
+/// A place records can come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Files whose `file!()` path starts with the given prefix, e.g. `src/generated/`.
+    Path(String),
+    /// Everything logged from the given crate, e.g. `noisy_dep`.
+    Crate(String),
+}
+
+impl Source {
+    /// Parses `crate:name` as a crate and anything else as a path prefix.
+    fn parse(s: &str) -> Self {
+        match s.strip_prefix("crate:") {
+            Some(name) => Source::Crate(name.replace('-', "_")),
+            None => Source::Path(s.to_string()),
+        }
+    }
+
+    fn matches(&self, filepath: &str, module_path: &str) -> bool {
+        match self {
+            Source::Path(prefix) => filepath.starts_with(prefix.as_str()),
+            Source::Crate(name) => module_path.split("::").next() == Some(name.as_str()),
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Path(prefix) => write!(f, "{prefix}"),
+            Source::Crate(name) => write!(f, "crate:{name}"),
+        }
+    }
+}
+
+/// Decides which sources may log. Denied sources always lose; if anything is allowed, only
+/// allowed sources may log.
+///
+/// # Examples
+/// ```
+/// use woody::SourceFilter;
+/// let filter = SourceFilter::new()
+///     .deny_path("src/generated/")
+///     .deny_crate("noisy_dep");
+/// assert!(!filter.permits("src/generated/schema.rs", "my_app::generated::schema"));
+/// assert!(!filter.permits("src/lib.rs", "noisy_dep::client"));
+/// assert!(filter.permits("src/main.rs", "my_app"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceFilter {
+    allow: Vec<Source>,
+    deny: Vec<Source>,
+}
+
+impl SourceFilter {
+    /// Creates a filter that permits every source.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a filter from comma-separated lists like `src/generated/,crate:noisy_dep`.
+    pub(crate) fn from_lists(allow: &str, deny: &str) -> Self {
+        let parse = |list: &str| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(Source::parse)
+                .collect()
+        };
+        Self {
+            allow: parse(allow),
+            deny: parse(deny),
+        }
+    }
+
+    /// The allowed sources. Empty if every source not denied is permitted.
+    pub fn allowed(&self) -> &[Source] {
+        &self.allow
+    }
+
+    /// The denied sources.
+    pub fn denied(&self) -> &[Source] {
+        &self.deny
+    }
+
+    /// Only permit files under the given path prefix (and other allowed sources).
+    pub fn allow_path(mut self, prefix: impl Into<String>) -> Self {
+        self.allow.push(Source::Path(prefix.into()));
+        self
+    }
+
+    /// Only permit the given crate (and other allowed sources).
+    pub fn allow_crate(mut self, name: &str) -> Self {
+        self.allow.push(Source::Crate(name.replace('-', "_")));
+        self
+    }
+
+    /// Silence files under the given path prefix.
+    pub fn deny_path(mut self, prefix: impl Into<String>) -> Self {
+        self.deny.push(Source::Path(prefix.into()));
+        self
+    }
+
+    /// Silence the given crate.
+    pub fn deny_crate(mut self, name: &str) -> Self {
+        self.deny.push(Source::Crate(name.replace('-', "_")));
+        self
+    }
+
+    /// Whether a record logged from `filepath` in `module_path` may be logged.
+    pub fn permits(&self, filepath: &str, module_path: &str) -> bool {
+        if self.deny.iter().any(|s| s.matches(filepath, module_path)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|s| s.matches(filepath, module_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_list() {
+        let filter = SourceFilter::new()
+            .allow_crate("my-app")
+            .allow_path("vendor/ok/");
+        assert!(filter.permits("src/main.rs", "my_app::server"));
+        assert!(filter.permits("vendor/ok/lib.rs", "vendored"));
+        assert!(!filter.permits("src/lib.rs", "my_application"));
+    }
+
+    #[test]
+    fn test_deny_beats_allow() {
+        let filter = SourceFilter::from_lists("crate:my_app", "src/generated/, crate:other");
+        assert!(filter.permits("src/main.rs", "my_app"));
+        assert!(!filter.permits("src/generated/api.rs", "my_app::generated::api"));
+        assert!(!filter.permits("src/lib.rs", "other"));
+        assert_eq!(filter.allowed(), [Source::Crate("my_app".to_string())]);
+        assert_eq!(filter.denied()[0].to_string(), "src/generated/");
+        assert_eq!(filter.denied()[1].to_string(), "crate:other");
+    }
+}
@@ -0,0 +1,71 @@
+//! Fingerprints that group records of the same issue, like Sentry's grouping: a hash of where
+//! the record was logged and its message with the parts that vary (numbers, IDs, quoted values)
+//! taken out, so `user 42 not found` and `user 7 not found` share one.
+use crate::{crypto, Record};
+
+/// The fingerprint of `record`: 16 hex digits, the same in every process and release as long
+/// as the call stays on the same line.
+pub(crate) fn of(record: &Record) -> String {
+    let key = format!(
+        "{}:{}:{}",
+        record.filepath,
+        record.line_number,
+        normalize(&record.message)
+    );
+    crypto::hex(&crypto::sha256(key.as_bytes())[..8])
+}
+
+/// Replaces every word with a digit in it (numbers, UUIDs, hex IDs) and every double-quoted
+/// value with `#`.
+fn normalize(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in message.chars() {
+        if quoted {
+            quoted = c != '"';
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        push_word(&mut out, &mut word);
+        if c == '"' {
+            quoted = true;
+            out.push('#');
+        } else {
+            out.push(c);
+        }
+    }
+    push_word(&mut out, &mut word);
+    out
+}
+
+fn push_word(out: &mut String, word: &mut String) {
+    if word.chars().any(|c| c.is_ascii_digit()) {
+        out.push('#');
+    } else {
+        out.push_str(word);
+    }
+    word.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LogLevel;
+
+    #[test]
+    fn test_fingerprint_ignores_varying_parts() {
+        assert_eq!(
+            normalize(r#"user 42 not found in "eu-west" (req 550e8400-e29b)"#),
+            "user # not found in # (req #-#)"
+        );
+        let record = |message: &str| Record::new(LogLevel::Error, message, "src/db.rs", 40, "");
+        let fingerprint = of(&record("timeout after 30s on shard 3"));
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(fingerprint, of(&record("timeout after 5s on shard 12")));
+        assert_ne!(fingerprint, of(&record("connection refused on shard 3")));
+    }
+}
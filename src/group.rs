@@ -0,0 +1,170 @@
+//! Buffers the records of one logical operation (say, a request) and writes them together when
+//! it completes, tagged with a shared group ID.
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use crate::{
+    context::Context,
+    memory::{self, Reservation},
+    IdGenerator, LogLevel, Logger, Record, Timestamp, UuidV7, Value,
+};
+
+/// Collects records for an operation, then either writes them all contiguously (on failure) or
+/// only the important ones (on success). This is the "log only on error" pattern: debug output
+/// of requests that went fine never reaches the file, but a failed request comes with its full
+/// story.
+///
+/// Dropping a group without calling [`RecordGroup::success`] counts as a failure, so an early
+/// return through `?` still writes everything.
+///
+/// # Examples
+/// ```
+/// use woody::{log, LogLevel, Logger};
+/// let group = Logger::get_instance().group();
+/// log!(logger: group, LogLevel::Debug, "parsed headers");
+/// log!(logger: group, LogLevel::Debug, "loaded user");
+/// group.success(); // nothing below Warning is written
+/// ```
+#[derive(Debug)]
+pub struct RecordGroup {
+    logger: Logger,
+    id: String,
+    /// Records at or above this level are written even if the operation succeeds.
+    success_level: LogLevel,
+    /// The buffered records, with when they were logged.
+    records: Mutex<Vec<(Record, Logged)>>,
+    finished: bool,
+}
+
+/// When and in what context a buffered record was logged.
+#[derive(Debug)]
+pub(crate) struct Logged {
+    pub(crate) time: Timestamp,
+    pub(crate) context: Context,
+    _reservation: Reservation,
+}
+
+/// Roughly the bytes a buffered record holds, to count against the memory limit.
+fn footprint(record: &Record) -> usize {
+    let fields: usize = record
+        .fields
+        .iter()
+        .map(|(key, value)| match value {
+            Value::Str(s) => key.len() + s.len(),
+            _ => key.len() + 8,
+        })
+        .sum();
+    std::mem::size_of::<Record>() + record.message.len() + fields
+}
+
+impl RecordGroup {
+    pub(crate) fn new(logger: Logger) -> Self {
+        Self {
+            logger,
+            id: UuidV7.generate(),
+            success_level: LogLevel::Warning,
+            records: Mutex::new(Vec::new()),
+            finished: false,
+        }
+    }
+
+    /// Sets the lowest level still written when the operation succeeds. Defaults to `Warning`.
+    pub fn with_success_level(mut self, level: LogLevel) -> Self {
+        self.success_level = level;
+        self
+    }
+
+    /// The ID every record of this group is tagged with.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Buffers a record until the group finishes. It's written with the time, thread and
+    /// context it was logged in, not those of the flush. A record that would go over the
+    /// memory limit is dropped.
+    pub fn log(&self, record: &Record) {
+        let mut record = record.clone();
+        if record.thread.is_none() {
+            let thread = std::thread::current();
+            record.thread = Some(thread.name().unwrap_or("unnamed").to_string());
+        }
+        let Some(reservation) = memory::reserve(footprint(&record)) else {
+            crate::internal::event(LogLevel::Warning, || {
+                format!(
+                    "over the memory limit, dropped a record of group {}",
+                    self.id
+                )
+            });
+            #[cfg(feature = "metrics")]
+            crate::metrics::dropped();
+            return;
+        };
+        let logged = Logged {
+            time: chrono::Local::now(),
+            context: crate::context::current(),
+            _reservation: reservation,
+        };
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.push((record, logged));
+    }
+
+    /// Writes a record to `writer` right away, like `Logger::write_to`.
+    pub fn write_to(&self, record: &Record, writer: &mut dyn Write) -> io::Result<()> {
+        self.logger.write_to(record, writer)
+    }
+
+    /// Finishes a successful operation: only records at or above the success level are
+    /// written, subject to the logger's usual filtering.
+    pub fn success(mut self) {
+        self.finished = true;
+        let records = self.take();
+        let records = records
+            .iter()
+            .filter(|(info, _)| info.level >= self.success_level)
+            .map(|(info, logged)| (info, Some(logged)))
+            .collect();
+        self.tagged_logger().log_batch_at(records);
+    }
+
+    /// Finishes a failed operation: every record is written, including those below the
+    /// logger's level, so the failure comes with full context.
+    pub fn failure(mut self) {
+        self.finished = true;
+        self.write_all();
+    }
+
+    fn write_all(&mut self) {
+        let records = self.take();
+        let mut logger = self.tagged_logger();
+        logger.level = LogLevel::ALL;
+        logger.named_levels.clear();
+        logger.schedule.clear();
+        logger.log_batch_at(
+            records
+                .iter()
+                .map(|(info, logged)| (info, Some(logged)))
+                .collect(),
+        );
+    }
+
+    fn take(&mut self) -> Vec<(Record, Logged)> {
+        std::mem::take(&mut *self.records.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn tagged_logger(&self) -> Logger {
+        Logger {
+            group: Some(self.id.clone()),
+            ..self.logger.clone()
+        }
+    }
+}
+
+impl Drop for RecordGroup {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.write_all();
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Unique IDs for log records, so a single line can be referenced from tickets or matched up
+//! across systems.
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Generates a unique ID for every record.
+///
+/// Implemented for closures, so `|| my_id().to_string()` works as a generator.
+pub trait IdGenerator: Send + Sync {
+    /// Returns the next ID.
+    fn generate(&self) -> String;
+}
+
+impl<F> IdGenerator for F
+where
+    F: Fn() -> String + Send + Sync,
+{
+    fn generate(&self) -> String {
+        self()
+    }
+}
+
+/// A generator shared between clones of the logger.
+#[derive(Clone)]
+pub(crate) struct SharedIdGenerator(pub(crate) Arc<dyn IdGenerator>);
+
+impl std::fmt::Debug for SharedIdGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IdGenerator")
+    }
+}
+
+/// Generates time-ordered UUIDv7s, e.g. `01920cf1-5b3e-7a4c-9d2e-3f1a6b8c0d4e`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct UuidV7;
+
+impl IdGenerator for UuidV7 {
+    fn generate(&self) -> String {
+        let millis = unix_millis() & 0xffff_ffff_ffff;
+        let rand_a = random_u64() & 0x0fff;
+        let rand_b = random_u64() & 0x3fff_ffff_ffff_ffff;
+
+        let high = (millis << 16) | (0x7 << 12) | rand_a;
+        let low = (0b10 << 62) | rand_b;
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            high >> 32,
+            (high >> 16) & 0xffff,
+            high & 0xffff,
+            low >> 48,
+            low & 0xffff_ffff_ffff
+        )
+    }
+}
+
+/// Generates Twitter-style snowflake IDs: 41 bits of milliseconds since 2020-01-01, 10 bits of
+/// node ID, and a 12 bit per-millisecond sequence, rendered as a decimal number.
+///
+/// IDs from one generator are strictly increasing. Give every process its own node ID to keep
+/// IDs unique across processes.
+#[derive(Debug)]
+pub struct Snowflake {
+    node: u64,
+    /// The millisecond and sequence number of the last ID handed out.
+    last: Mutex<(u64, u64)>,
+}
+
+/// 2020-01-01T00:00:00Z in milliseconds since the Unix epoch.
+const SNOWFLAKE_EPOCH: u64 = 1_577_836_800_000;
+
+impl Snowflake {
+    /// Creates a generator for the given node. Only the lower 10 bits of `node` are used.
+    pub fn new(node: u16) -> Self {
+        Self {
+            node: u64::from(node) & 0x3ff,
+            last: Mutex::new((0, 0)),
+        }
+    }
+}
+
+impl IdGenerator for Snowflake {
+    fn generate(&self) -> String {
+        let mut last = self.last.lock().unwrap();
+        let (last_millis, last_sequence) = *last;
+        let mut millis = unix_millis()
+            .saturating_sub(SNOWFLAKE_EPOCH)
+            .max(last_millis);
+        let mut sequence = 0;
+        if millis == last_millis {
+            sequence = (last_sequence + 1) & 0xfff;
+            if sequence == 0 {
+                // The sequence ran out for this millisecond, so wait for the next one.
+                while millis <= last_millis {
+                    millis = unix_millis().saturating_sub(SNOWFLAKE_EPOCH);
+                }
+            }
+        }
+        *last = (millis, sequence);
+
+        let id = (millis << 22) | (self.node << 12) | sequence;
+        id.to_string()
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Returns a random number without pulling in a dependency, by hashing a counter with the
+/// randomly seeded keys of the standard library's `RandomState`.
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid_v7_layout() {
+        let id = UuidV7.generate();
+        assert_eq!(id.len(), 36, "{id}");
+        let groups: Vec<&str> = id.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('7'), "not version 7: {id}");
+        assert!(
+            groups[3].starts_with(['8', '9', 'a', 'b']),
+            "wrong variant: {id}"
+        );
+        assert_ne!(id, UuidV7.generate());
+    }
+
+    #[test]
+    fn test_snowflake_increasing() {
+        let generator = Snowflake::new(7);
+        let ids: Vec<u64> = (0..10_000)
+            .map(|_| generator.generate().parse().unwrap())
+            .collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!((ids[0] >> 12) & 0x3ff, 7);
+    }
+}
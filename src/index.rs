@@ -0,0 +1,243 @@
+//! A sidecar index of the log file (`woody.log.idx`), so reading a time range or only the
+//! errors of a huge file can seek to the right places instead of scanning all of it.
+//!
+//! Every line describes one minute of the log: `<unix minute> <start offset> <end offset>
+//! <highest severity>`. A minute is written once the next one starts, so the records of the
+//! current minute are found by reading from the end of the last entry.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{reader, LogLevel};
+
+/// One minute of the log.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) minute: i64,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    pub(crate) max_severity: i16,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace().map(str::parse::<i64>);
+        let entry = Entry {
+            minute: parts.next()?.ok()?,
+            start: parts.next()?.ok()? as u64,
+            end: parts.next()?.ok()? as u64,
+            max_severity: parts.next()?.ok()? as i16,
+        };
+        Some(entry)
+    }
+
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let Entry {
+            minute,
+            start,
+            end,
+            max_severity,
+        } = self;
+        writeln!(out, "{minute} {start} {end} {max_severity}")
+    }
+}
+
+/// The path of the index of the log file at `path`.
+pub(crate) fn path_for(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".idx");
+    PathBuf::from(name)
+}
+
+/// Appends to the index as records are written.
+#[derive(Debug)]
+pub(crate) struct IndexWriter {
+    file: File,
+    /// The minute being collected, if any records were written in it.
+    current: Option<Entry>,
+    /// Where the next record starts in the log file.
+    offset: u64,
+}
+
+impl IndexWriter {
+    /// Opens the index of the log file at `path`, which is `len` bytes long. A missing index
+    /// of a non-empty file is built first.
+    pub(crate) fn open(path: &Path, len: u64) -> io::Result<Self> {
+        let index_path = path_for(path);
+        if len > 0 && !index_path.exists() {
+            build(path)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(index_path)?;
+        Ok(Self {
+            file,
+            current: None,
+            offset: len,
+        })
+    }
+
+    /// Notes that `len` bytes of records, the most severe at `level`, were just written.
+    pub(crate) fn record(&mut self, len: usize, level: LogLevel) -> io::Result<()> {
+        let minute = chrono::Utc::now().timestamp().div_euclid(60);
+        if self.current.is_some_and(|entry| entry.minute != minute) {
+            self.finish_minute()?;
+        }
+        let offset = self.offset;
+        let entry = self.current.get_or_insert(Entry {
+            minute,
+            start: offset,
+            end: offset,
+            max_severity: i16::MIN,
+        });
+        self.offset += len as u64;
+        entry.end = self.offset;
+        entry.max_severity = entry.max_severity.max(level.severity());
+        Ok(())
+    }
+
+    fn finish_minute(&mut self) -> io::Result<()> {
+        match self.current.take() {
+            Some(entry) => entry.write(&mut self.file),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for IndexWriter {
+    fn drop(&mut self) {
+        let _ = self.finish_minute();
+    }
+}
+
+/// Reads the index of the log file at `path`, building it if it's missing or describes a
+/// longer file (say, one that was truncated since). Returns `None` if there's no usable index
+/// and it can't be written (say, in a read-only directory).
+pub(crate) fn load(path: &Path) -> Option<Vec<Entry>> {
+    let len = std::fs::metadata(path).ok()?.len();
+    match read(path) {
+        Some(entries) if entries.last().is_none_or(|entry| entry.end <= len) => Some(entries),
+        _ => {
+            build(path).ok()?;
+            read(path)
+        }
+    }
+}
+
+fn read(path: &Path) -> Option<Vec<Entry>> {
+    let file = File::open(path_for(path)).ok()?;
+    let entries = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| Entry::parse(&line))
+        .collect();
+    Some(entries)
+}
+
+/// Writes the index of the log file at `path` by scanning it.
+pub(crate) fn build(path: &Path) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut index = io::BufWriter::new(File::create(path_for(path))?);
+    let mut current: Option<Entry> = None;
+    let mut offset = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let mut len = reader.read_line(&mut line)? as u64;
+        if len == 0 {
+            break;
+        }
+        // An indented JSON record is indexed as a whole.
+        while crate::json::is_unfinished(&line) {
+            match reader.read_line(&mut line)? as u64 {
+                0 => break,
+                more => len += more,
+            }
+        }
+        let minute = reader::timestamp(&line).map(|time| time.timestamp().div_euclid(60));
+        let severity = reader::level(&line).map_or(i16::MIN, |level| level.severity());
+        match (&mut current, minute) {
+            (Some(entry), Some(minute)) if entry.minute != minute => {
+                entry.write(&mut index)?;
+                current = None;
+            }
+            _ => {}
+        }
+        if current.is_none() {
+            if let Some(minute) = minute {
+                current = Some(Entry {
+                    minute,
+                    start: offset,
+                    end: offset,
+                    max_severity: i16::MIN,
+                });
+            }
+        }
+        offset += len;
+        if let Some(entry) = &mut current {
+            entry.end = offset;
+            entry.max_severity = entry.max_severity.max(severity);
+        }
+    }
+    // The last minute may still be growing, so it's left to be scanned.
+    index.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_index() {
+        let path = std::env::temp_dir().join(format!("woody-index-{}.log", std::process::id()));
+        let lines = [
+            "[2024-01-01 10:00:01.000 +00:00] [INFO] [main] [src/main.rs:1] a\n",
+            "[2024-01-01 10:00:02.000 +00:00] [ERROR] [main] [src/main.rs:1] b\n",
+            "[2024-01-01 10:01:00.000 +00:00] [DEBUG] [main] [src/main.rs:1] c\n",
+            "[2024-01-01 10:02:00.000 +00:00] [DEBUG] [main] [src/main.rs:1] d\n",
+        ];
+        std::fs::write(&path, lines.concat()).unwrap();
+        let _ = std::fs::remove_file(path_for(&path));
+
+        let entries = load(&path).unwrap();
+        let minute = 1_704_103_200 / 60;
+        let (first, second) = (lines[0].len() as u64, lines[1].len() as u64);
+        assert_eq!(
+            entries,
+            [
+                Entry {
+                    minute,
+                    start: 0,
+                    end: first + second,
+                    max_severity: 50
+                },
+                Entry {
+                    minute: minute + 1,
+                    start: first + second,
+                    end: first + second + lines[2].len() as u64,
+                    max_severity: 30
+                },
+            ]
+        );
+        let _ = std::fs::remove_file(path_for(&path));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_writer_appends_minutes() {
+        let path = std::env::temp_dir().join(format!("woody-index-w-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(path_for(&path));
+        let mut writer = IndexWriter::open(&path, 0).unwrap();
+        writer.record(10, LogLevel::Info).unwrap();
+        writer.record(5, LogLevel::Error).unwrap();
+        drop(writer);
+
+        let contents = std::fs::read_to_string(path_for(&path)).unwrap();
+        let entry = Entry::parse(contents.trim()).unwrap();
+        assert_eq!((entry.start, entry.end, entry.max_severity), (0, 15, 50));
+        let _ = std::fs::remove_file(path_for(&path));
+    }
+}
@@ -0,0 +1,72 @@
+//! Catching a second explicit initialization, e.g. by two crates that both set woody up, and
+//! pointing at the first one instead of silently keeping whichever settings came first.
+use std::{error::Error, fmt, panic::Location, sync::OnceLock};
+
+use crate::LogLevel;
+
+/// Where [`Logger::init`](crate::Logger::init) was first called.
+static FIRST: OnceLock<&'static Location<'static>> = OnceLock::new();
+
+/// Returned by [`Logger::init`](crate::Logger::init) when woody was already initialized.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InitError {
+    first: &'static Location<'static>,
+}
+
+impl InitError {
+    /// Where the logger was first initialized.
+    pub fn first_init(&self) -> &'static Location<'static> {
+        self.first
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "woody was already initialized at {}; use `Logger::init_or_noop` where that's expected",
+            self.first
+        )
+    }
+}
+
+impl Error for InitError {}
+
+/// Records `location` as the first initialization, or says where that was.
+pub(crate) fn claim(location: &'static Location<'static>) -> Result<(), InitError> {
+    claim_in(&FIRST, location)
+}
+
+fn claim_in(
+    first: &OnceLock<&'static Location<'static>>,
+    location: &'static Location<'static>,
+) -> Result<(), InitError> {
+    let mut claimed = false;
+    let first = first.get_or_init(|| {
+        claimed = true;
+        location
+    });
+    if !claimed {
+        return Err(InitError { first });
+    }
+    crate::internal::event(LogLevel::Debug, || format!("initialized at {location}"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_init_names_the_first() {
+        let first = OnceLock::new();
+        let here = Location::caller();
+        assert!(claim_in(&first, here).is_ok());
+        assert!(claim_in(&first, here).is_err());
+        let error = claim_in(&first, Location::caller()).unwrap_err();
+        assert_eq!(error.first_init(), here);
+        assert!(error
+            .to_string()
+            .starts_with(&format!("woody was already initialized at {here}")));
+    }
+}
@@ -0,0 +1,47 @@
+//! Diagnostics about woody itself, like the file it opened or a sink failing, for working out
+//! why a record didn't show up. Off unless `WOODY_INTERNAL` sets a level, e.g. `debug` for
+//! everything or `warning` for failures and drops only.
+//!
+//! They go straight to stderr, never through the logger, so reporting a failing logger can't
+//! fail the same way or recurse.
+use std::{io::Write, sync::OnceLock};
+
+use crate::LogLevel;
+
+static LEVEL: OnceLock<Option<LogLevel>> = OnceLock::new();
+
+/// The level of internal events to report, if any.
+fn threshold() -> Option<LogLevel> {
+    *LEVEL.get_or_init(|| {
+        let level = LogLevel::parse(&crate::config::setting("WOODY_INTERNAL")?);
+        (level != LogLevel::Off).then_some(level)
+    })
+}
+
+/// Reports an event at `level`: `Debug` for the logger's lifecycle, `Warning` for failures and
+/// dropped records. The message is only built if it'll be written.
+pub(crate) fn event(level: LogLevel, message: impl FnOnce() -> String) {
+    if threshold().is_some_and(|threshold| level >= threshold) {
+        let _ = std::io::stderr().write_all(line(level, &message()).as_bytes());
+    }
+}
+
+fn line(level: LogLevel, message: &str) -> String {
+    format!(
+        "[woody: internal] [{level}] {}\n",
+        crate::escape::message(message)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        assert_eq!(
+            line(LogLevel::Warning, "sink 3 failed: broken\npipe"),
+            "[woody: internal] [WARNING] sink 3 failed: broken\\npipe\n"
+        );
+    }
+}
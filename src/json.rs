@@ -0,0 +1,534 @@
+//! The JSON formats: one compact object per line for shipping, and indented objects for
+//! reading, with conversion between the two that leaves text records alone.
+use std::fmt::Write;
+
+use crate::fields::{Fields, Value};
+
+/// Formats a record as a compact JSON object, with the keys in the order given and `fields`
+/// (if any) as a nested object last.
+pub(crate) fn record(entries: &[(&str, &str)], fields: &Fields) -> String {
+    let mut out = String::from("{");
+    for (key, value) in entries {
+        let _ = write!(out, "{}:{},", string(key), string(value));
+    }
+    if !fields.is_empty() {
+        out.push_str("\"fields\":{");
+        for (key, value) in fields.iter() {
+            let _ = write!(out, "{}:{},", string(key), self::value(value));
+        }
+        out.pop();
+        out.push_str("},");
+    }
+    if out.ends_with(',') {
+        out.pop();
+    }
+    out.push('}');
+    out
+}
+
+pub(crate) fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn value(value: &Value) -> String {
+    match value {
+        Value::F64(x) if !x.is_finite() => string(&x.to_string()),
+        Value::Str(x) => string(x),
+        value => value.to_string(),
+    }
+}
+
+/// A parsed JSON value, for converting records to the `msgpack` format.
+#[cfg(feature = "file")]
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Scalar(Value),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Parses the JSON value at the start of `s`, returning it and the rest of `s`.
+#[cfg(feature = "file")]
+pub(crate) fn parse(s: &str) -> Option<(Json, &str)> {
+    let mut parser = Parser { s, pos: 0 };
+    let value = parser.value()?;
+    Some((value, &s[parser.pos..]))
+}
+
+/// The string under `key` in the JSON object `s`, like a record's `"time"`.
+#[cfg(feature = "file")]
+pub(crate) fn string_member(s: &str, key: &str) -> Option<String> {
+    let (Json::Object(members), _) = parse(s)? else {
+        return None;
+    };
+    members.into_iter().find_map(|(name, value)| match value {
+        Json::Scalar(Value::Str(value)) if name == key => Some(value.into_owned()),
+        _ => None,
+    })
+}
+
+/// Whether `s` starts a JSON object that isn't closed yet, like the first lines of an indented
+/// record.
+#[cfg(feature = "file")]
+pub(crate) fn is_unfinished(s: &str) -> bool {
+    if !s.trim_start().starts_with('{') {
+        return false;
+    }
+    let mut depth = 0;
+    squeeze(s, &mut String::new(), &mut depth);
+    depth > 0
+}
+
+#[cfg(feature = "file")]
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+#[cfg(feature = "file")]
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        while self.s.as_bytes().get(self.pos)?.is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        self.s.as_bytes().get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> Option<()> {
+        self.peek();
+        self.s[self.pos..]
+            .starts_with(token)
+            .then(|| self.pos += token.len())
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        match self.peek()? {
+            b'{' => {
+                self.pos += 1;
+                let mut entries = Vec::new();
+                if self.eat("}").is_some() {
+                    return Some(Json::Object(entries));
+                }
+                loop {
+                    self.peek();
+                    let key = self.string()?;
+                    self.eat(":")?;
+                    entries.push((key, self.value()?));
+                    if self.eat(",").is_none() {
+                        self.eat("}")?;
+                        return Some(Json::Object(entries));
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.eat("]").is_some() {
+                    return Some(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.eat(",").is_none() {
+                        self.eat("]")?;
+                        return Some(Json::Array(items));
+                    }
+                }
+            }
+            b'"' => Some(Json::Scalar(Value::from(self.string()?))),
+            b't' => self.eat("true").map(|_| Json::Scalar(Value::Bool(true))),
+            b'f' => self.eat("false").map(|_| Json::Scalar(Value::Bool(false))),
+            b'n' => self.eat("null").map(|_| Json::Null),
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let rest = &self.s[self.pos..];
+        let len = rest
+            .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            .unwrap_or(rest.len());
+        let number = &rest[..len];
+        let value = if number.contains(['.', 'e', 'E']) {
+            Value::F64(number.parse().ok()?)
+        } else if number.starts_with('-') {
+            Value::I64(number.parse().ok()?)
+        } else {
+            Value::U64(number.parse().ok()?)
+        };
+        self.pos += len;
+        Some(Json::Scalar(value))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.s.as_bytes().get(self.pos) != Some(&b'"') {
+            return None;
+        }
+        let mut out = String::new();
+        let mut chars = self.s[self.pos + 1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 2;
+                    return Some(out);
+                }
+                '\\' => match chars.next()?.1 {
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'u' => {
+                        let mut code = hex4(&mut chars)?;
+                        if (0xd800..0xdc00).contains(&code) {
+                            // A surrogate pair, e.g. for an emoji.
+                            let (_, '\\') = chars.next()? else {
+                                return None;
+                            };
+                            let (_, 'u') = chars.next()? else { return None };
+                            let low = hex4(&mut chars)?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.checked_sub(0xdc00)?);
+                        }
+                        out.push(char::from_u32(code)?);
+                    }
+                    c => out.push(c),
+                },
+                c => out.push(c),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(feature = "file")]
+fn hex4(chars: &mut std::str::CharIndices) -> Option<u32> {
+    let digits: String = chars.take(4).map(|(_, c)| c).collect();
+    u32::from_str_radix(&digits, 16).ok()
+}
+
+/// Rewrites JSON records, compact or indented, as one compact object per line. Lines that
+/// aren't JSON, like text records, are copied as they are.
+///
+/// # Examples
+/// ```
+/// let pretty = "{\n  \"level\": \"INFO\",\n  \"message\": \"ready\"\n}\n";
+/// assert_eq!(woody::json::compact(pretty), "{\"level\":\"INFO\",\"message\":\"ready\"}\n");
+/// ```
+pub fn compact(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for chunk in chunks(input) {
+        match chunk {
+            Chunk::Json(json) => {
+                out.push_str(&json);
+                out.push('\n');
+            }
+            Chunk::Other(line) => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// Rewrites JSON records, compact or indented, as indented objects. Lines that aren't JSON are
+/// copied as they are.
+pub fn pretty(input: &str) -> String {
+    let mut out = String::with_capacity(input.len() * 2);
+    for chunk in chunks(input) {
+        match chunk {
+            Chunk::Json(json) => {
+                out.push_str(&indent(&json));
+                out.push('\n');
+            }
+            Chunk::Other(line) => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// Adds keys to every record in `output`, formatted or not: a JSON object gets them as members
+/// (on lines of their own if it's indented), and a text record as ` key=value` at the end of
+/// its first line. `keys` is called once per record with whether it's JSON, and returns the
+/// values already written in that format.
+pub(crate) fn append(
+    output: &str,
+    mut keys: impl FnMut(bool) -> Vec<(&'static str, String)>,
+) -> String {
+    let mut out = String::with_capacity(output.len() + 32);
+    let mut depth = 0;
+    let mut scratch = String::new();
+    for line in output.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+        if depth == 0 && !content.trim_start().starts_with('{') {
+            out.push_str(content);
+            // The lines after a streamed record's first belong to it.
+            if !content.starts_with(crate::stream::CONTINUATION) {
+                for (key, value) in keys(false) {
+                    let _ = write!(out, " {key}={value}");
+                }
+            }
+            out.push_str(ending);
+            continue;
+        }
+        squeeze(content, &mut scratch, &mut depth);
+        scratch.clear();
+        let close = content.rfind('}');
+        let (true, Some(close)) = (depth <= 0, close) else {
+            out.push_str(line);
+            continue;
+        };
+        depth = 0;
+        let keys = keys(true);
+        if content[..close].trim().is_empty() && !keys.is_empty() {
+            // An indented object closes on a line of its own.
+            out.truncate(out.trim_end_matches(['\r', '\n']).len());
+            for (key, value) in keys {
+                let _ = write!(out, ",{ending}  {}: {value}", string(key));
+            }
+            out.push_str(ending);
+            out.push_str(line);
+        } else {
+            out.push_str(&content[..close]);
+            for (key, value) in keys {
+                let _ = write!(out, ",{}:{value}", string(key));
+            }
+            out.push_str(&line[close..]);
+        }
+    }
+    out
+}
+
+enum Chunk<'a> {
+    /// A JSON object, compacted.
+    Json(String),
+    /// Any other line, with its line ending.
+    Other(&'a str),
+}
+
+/// Splits `input` into JSON objects, which start a line with `{` and may span lines, and other
+/// lines (text records start with `[`, so arrays don't count). An unfinished object at the end
+/// is kept as it is.
+fn chunks(input: &str) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut pending = String::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        if depth == 0 && !line.trim_start().starts_with('{') {
+            chunks.push(Chunk::Other(line));
+            continue;
+        }
+        if depth == 0 {
+            start = line_start;
+        }
+        squeeze(line, &mut pending, &mut depth);
+        if depth <= 0 {
+            chunks.push(Chunk::Json(std::mem::take(&mut pending)));
+            depth = 0;
+        }
+    }
+    if !pending.is_empty() {
+        chunks.push(Chunk::Other(&input[start..]));
+    }
+    chunks
+}
+
+/// Appends `line` to `out` without the whitespace outside of strings, tracking the nesting.
+/// Strings can't span lines, since JSON escapes newlines in them.
+fn squeeze(line: &str, out: &mut String, depth: &mut i32) {
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in line.chars() {
+        if in_string {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            c if c.is_whitespace() => continue,
+            '"' => in_string = true,
+            '{' | '[' => *depth += 1,
+            '}' | ']' => *depth -= 1,
+            _ => {}
+        }
+        out.push(c);
+    }
+}
+
+/// Indents a compact JSON value by two spaces per level.
+fn indent(json: &str) -> String {
+    let mut out = String::with_capacity(json.len() * 2);
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = json.chars().peekable();
+    let newline = |out: &mut String, depth: usize| {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    };
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                out.push(c);
+            }
+            '{' | '[' => {
+                out.push(c);
+                // Keep empty objects and arrays on one line.
+                if let Some(&close @ ('}' | ']')) = chars.peek() {
+                    out.push(close);
+                    chars.next();
+                } else {
+                    depth += 1;
+                    newline(&mut out, depth);
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                newline(&mut out, depth);
+                out.push(c);
+            }
+            ',' => {
+                out.push(c);
+                newline(&mut out, depth);
+            }
+            ':' => out.push_str(": "),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_escapes_and_nests_fields() {
+        let mut fields = Fields::new();
+        fields.push("user_id", 42);
+        fields.push("note", "a \"b\"");
+        let json = record(&[("level", "INFO"), ("message", "line\nbreak")], &fields);
+        assert_eq!(
+            json,
+            r#"{"level":"INFO","message":"line\nbreak","fields":{"user_id":42,"note":"a \"b\""}}"#
+        );
+        assert_eq!(record(&[], &Fields::new()), "{}");
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn test_parse() {
+        let mut fields = Fields::new();
+        fields.push("n", -3);
+        fields.push("quote", "\"\u{1}é😀");
+        let json = record(&[("level", "INFO")], &fields) + " rest";
+        let (parsed, rest) = parse(&json).unwrap();
+        let string = |s: &str| Json::Scalar(Value::from(s.to_string()));
+        assert_eq!(
+            parsed,
+            Json::Object(vec![
+                ("level".to_string(), string("INFO")),
+                (
+                    "fields".to_string(),
+                    Json::Object(vec![
+                        ("n".to_string(), Json::Scalar(Value::I64(-3))),
+                        ("quote".to_string(), string("\"\u{1}é😀")),
+                    ])
+                ),
+            ])
+        );
+        assert_eq!(rest, " rest");
+        let (parsed, _) = parse(r#"[1.5, true, null, "\ud83d\ude00"]"#).unwrap();
+        assert_eq!(
+            parsed,
+            Json::Array(vec![
+                Json::Scalar(Value::F64(1.5)),
+                Json::Scalar(Value::Bool(true)),
+                Json::Null,
+                string("😀"),
+            ])
+        );
+        assert!(parse("{\"a\":").is_none());
+    }
+
+    #[cfg(feature = "file")]
+    #[test]
+    fn test_append_keeps_objects_valid() {
+        let mut seq = 0;
+        let mut keys = |json: bool| {
+            seq += 1;
+            match json {
+                true => vec![("seq", seq.to_string()), ("note", string("a b"))],
+                false => vec![("seq", seq.to_string())],
+            }
+        };
+        let input = "{\"message\":\"}\",\"fields\":{\"n\":1}}\r\n[plain] text\n    | more\n";
+        let compact = append(input, &mut keys);
+        let lines: Vec<&str> = compact.split("\r\n").collect();
+        assert_eq!(
+            lines[0],
+            r#"{"message":"}","fields":{"n":1},"seq":1,"note":"a b"}"#
+        );
+        assert_eq!(lines[1], "[plain] text seq=2\n    | more\n");
+
+        let indented = append(
+            &pretty("{\"message\":\"x\"}\n{\"message\":\"y\"}\n"),
+            &mut keys,
+        );
+        for json in indented.split_inclusive("}\n") {
+            let (parsed, rest) = parse(json).unwrap();
+            assert_eq!(rest.trim(), "", "{indented}");
+            let Json::Object(members) = parsed else {
+                panic!("{indented}")
+            };
+            assert_eq!(members[1].0, "seq");
+        }
+        assert!(indented.ends_with("  \"note\": \"a b\"\n}\n"), "{indented}");
+    }
+
+    #[test]
+    fn test_pretty_and_compact_round_trip() {
+        let input = "{\"a\":\"x, {y}\",\"b\":{\"c\":[1,2],\"d\":{}}}\n[plain] text line\n";
+        let pretty = pretty(input);
+        assert_eq!(
+            pretty,
+            "{\n  \"a\": \"x, {y}\",\n  \"b\": {\n    \"c\": [\n      1,\n      2\n    ],\n    \"d\": {}\n  }\n}\n[plain] text line\n"
+        );
+        assert_eq!(compact(&pretty), input);
+        assert_eq!(compact("{\"unfinished\":\n"), "{\"unfinished\":\n");
+    }
+}
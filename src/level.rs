@@ -0,0 +1,242 @@
+//! Log levels, including renamed and user-defined ones.
+use std::{cmp::Ordering, collections::BTreeMap, sync::RwLock};
+
+/// Determines the log level of a message.
+///
+/// Levels are ordered by their severity, in tenths: `Trace` is 10, `Info` 20, `Debug` 30,
+/// `Warning` 40, `Error` 50, and `Fatal` 60. Custom levels slot in between, e.g. a `NOTICE`
+/// with severity 25 sits between `Info` and `Debug`. Levels with the same severity are ordered
+/// by name.
+///
+/// Since custom levels carry data, levels can't be cast to integers; use
+/// [`LogLevel::severity`] instead.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LogLevel {
+    /// Fatal level, for unrecoverable states. See `log_fatal!`.
+    Fatal,
+    /// Error level.
+    Error,
+    /// Warning level.
+    Warning,
+    /// Debug level.
+    Debug,
+    /// Info level.
+    Info,
+    /// Trace level.
+    Trace,
+    /// Off level.
+    Off,
+    /// ! Internal use only. Do not use.
+    ALL,
+    /// A user-defined level. See [`LogLevel::custom`].
+    Custom {
+        /// The name the level is displayed as.
+        name: &'static str,
+        /// Where the level sits among the others, in tenths.
+        severity: i16,
+    },
+}
+
+/// Display names replacing the defaults, by [`LogLevel::key`].
+static NAMES: RwLock<BTreeMap<(bool, &str), String>> = RwLock::new(BTreeMap::new());
+/// Custom levels that can be looked up by name.
+static CUSTOM: RwLock<Vec<LogLevel>> = RwLock::new(Vec::new());
+
+impl LogLevel {
+    /// Defines a level between the built-in ones.
+    ///
+    /// # Examples
+    /// ```
+    /// use woody::LogLevel;
+    /// const NOTICE: LogLevel = LogLevel::custom("NOTICE", 25);
+    /// assert!(NOTICE > LogLevel::Info && NOTICE < LogLevel::Debug);
+    /// woody::register_level(NOTICE); // lets `WOODY_LEVEL=notice` find it
+    /// woody::log!(NOTICE, "Hello, world!");
+    /// ```
+    pub const fn custom(name: &'static str, severity: i16) -> Self {
+        LogLevel::Custom { name, severity }
+    }
+
+    /// Where the level sits among the others, in tenths.
+    pub const fn severity(&self) -> i16 {
+        match self {
+            LogLevel::Fatal => 60,
+            LogLevel::Error => 50,
+            LogLevel::Warning => 40,
+            LogLevel::Debug => 30,
+            LogLevel::Info => 20,
+            LogLevel::Trace => 10,
+            LogLevel::Off => 0,
+            LogLevel::ALL => -10,
+            LogLevel::Custom { severity, .. } => *severity,
+        }
+    }
+
+    /// The name the level is displayed as unless renamed.
+    const fn default_name(&self) -> &'static str {
+        match self {
+            LogLevel::Fatal => "FATAL",
+            LogLevel::Error => "ERROR",
+            LogLevel::Warning => "WARNING",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Trace => "TRACE",
+            LogLevel::Off => "OFF",
+            LogLevel::ALL => "",
+            LogLevel::Custom { name, .. } => name,
+        }
+    }
+
+    /// Identifies the level for renaming: a built-in one by its variant, a custom one by its
+    /// name, so a custom level never shares a display name with a built-in one.
+    const fn key(&self) -> (bool, &'static str) {
+        (matches!(self, LogLevel::Custom { .. }), self.default_name())
+    }
+
+    /// Parses a level name or number, e.g. `warn`, `4`, or `2.5` for a custom level between
+    /// `Info` and `Debug`. Custom levels are found if registered with `register_level`. Unknown
+    /// values mean `ALL`.
+    pub fn parse(s: &str) -> Self {
+        let s = s.trim().to_lowercase();
+        let builtin = match s.as_str() {
+            "fatal" => Some(LogLevel::Fatal),
+            "error" => Some(LogLevel::Error),
+            "warning" | "warn" => Some(LogLevel::Warning),
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "trace" => Some(LogLevel::Trace),
+            "off" => Some(LogLevel::Off),
+            _ => None,
+        };
+        if let Some(level) = builtin {
+            return level;
+        }
+
+        let custom = CUSTOM.read().unwrap_or_else(|e| e.into_inner());
+        let by_name = custom.iter().find(|level| match level {
+            LogLevel::Custom { name, .. } => name.eq_ignore_ascii_case(&s),
+            _ => false,
+        });
+        if let Some(level) = by_name {
+            return *level;
+        }
+
+        let Ok(number) = s.parse::<f32>() else {
+            return LogLevel::ALL;
+        };
+        let severity = (number * 10.0).round() as i16;
+        [
+            LogLevel::Fatal,
+            LogLevel::Error,
+            LogLevel::Warning,
+            LogLevel::Debug,
+            LogLevel::Info,
+            LogLevel::Trace,
+            LogLevel::Off,
+        ]
+        .iter()
+        .chain(custom.iter())
+        .find(|level| level.severity() == severity)
+        .copied()
+        .unwrap_or(LogLevel::ALL)
+    }
+}
+
+impl PartialOrd for LogLevel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LogLevel {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.severity(), self.key()).cmp(&(other.severity(), other.key()))
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = NAMES.read().unwrap_or_else(|e| e.into_inner());
+        match names.get(&self.key()) {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "{}", self.default_name()),
+        }
+    }
+}
+
+/// Changes how a level is displayed, e.g. `WARN` instead of `WARNING`. Other levels with the
+/// same severity keep their names.
+///
+/// # Examples
+/// ```
+/// use woody::LogLevel;
+/// woody::set_level_name(LogLevel::Warning, "WARN");
+/// assert_eq!(LogLevel::Warning.to_string(), "WARN");
+/// ```
+pub fn set_level_name(level: LogLevel, name: &str) {
+    let mut names = NAMES.write().unwrap_or_else(|e| e.into_inner());
+    names.insert(level.key(), name.to_string());
+}
+
+/// Makes a custom level known by name, so it can be used in `WOODY_LEVEL` and the like.
+pub fn register_level(level: LogLevel) {
+    let mut custom = CUSTOM.write().unwrap_or_else(|e| e.into_inner());
+    if !custom.contains(&level) {
+        custom.push(level);
+    }
+}
+
+/// Applies `WOODY_LEVEL_NAMES`, e.g. `warning=WARN,error=ERR`.
+pub(crate) fn set_level_names_from_env() {
+    let Some(names) = crate::config::setting("WOODY_LEVEL_NAMES") else {
+        return;
+    };
+    for entry in names.split(',') {
+        if let Some((level, name)) = entry.split_once('=') {
+            let level = LogLevel::parse(level);
+            if level != LogLevel::ALL {
+                set_level_name(level, name.trim());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTICE: LogLevel = LogLevel::custom("NOTICE", 25);
+
+    #[test]
+    fn test_custom_level_order() {
+        assert!(LogLevel::Info < NOTICE && NOTICE < LogLevel::Debug);
+        assert!(LogLevel::Fatal > LogLevel::Error);
+        assert!(LogLevel::ALL < LogLevel::Off);
+        assert_eq!(NOTICE.to_string(), "NOTICE");
+    }
+
+    #[test]
+    fn test_parse_custom_level() {
+        register_level(NOTICE);
+        assert_eq!(LogLevel::parse("notice"), NOTICE);
+        assert_eq!(LogLevel::parse("2.5"), NOTICE);
+        assert_eq!(LogLevel::parse("4"), LogLevel::Warning);
+        assert_eq!(LogLevel::parse("loud"), LogLevel::ALL);
+    }
+
+    #[test]
+    fn test_rename_level() {
+        const AUDIT: LogLevel = LogLevel::custom("AUDIT", 45);
+        const ALERT: LogLevel = LogLevel::custom("ALERT", 45);
+        set_level_name(AUDIT, "SECURITY");
+        assert_eq!(AUDIT.to_string(), "SECURITY");
+        assert_eq!(ALERT.to_string(), "ALERT");
+
+        // Renaming doesn't reorder levels that share a severity.
+        assert!(ALERT < AUDIT);
+        set_level_name(ALERT, "ZZZ");
+        assert!(ALERT < AUDIT);
+        assert!(LogLevel::custom("ERROR", 50) > LogLevel::Error);
+    }
+}
@@ -1,55 +1,263 @@
-///! A (really) very simple logger that can be used globally in any project.
-///!
-///! Logs the current time, the log level, the thread name, the file and line number, and the message.
-///! Log messages are written to a file (`woody.log` by default).
+//! A (really) very simple logger that can be used globally in any project.
+//!
+//! Logs the current time, the log level, the thread name, the file and line number, and the message.
+//! Log messages are written to a file (`woody.log` by default).
+//!
+//! # Stability
+//!
+//! The extension points other crates build on follow semver, and `tests/public_api.rs` uses
+//! them from outside the crate so a breaking change fails to compile:
+//!
+//! - [`Record`]: build one with [`Record::new`] and [`Record::with_field`]. Its fields can be
+//!   read, but a struct literal may break when a field is added.
+//! - [`Sink`]: the contract for anything passed to [`Logger::add_sink`].
+//! - [`Format`], [`Fields`], and [`Value`]. `Format` may gain variants in a minor release.
 use lazy_static::lazy_static;
 use std::{
-    env,
-    fs::{File, OpenOptions},
-    io::Write,
+    collections::BTreeMap,
+    io::{self, Write},
     sync::{Arc, Mutex},
 };
+#[cfg(feature = "file")]
+use std::{fs::File, time::Duration};
 
-#[cfg(test)]
+mod adaptive;
+#[cfg(feature = "admin")]
+pub mod admin;
+#[cfg(feature = "log")]
+mod bridge;
+mod config;
+mod console;
+pub mod context;
+#[cfg(all(unix, feature = "file"))]
+mod crash;
+mod crypto;
+pub mod diff;
+mod errors;
+mod escape;
+mod fields;
+#[cfg(feature = "file")]
+mod file;
+mod filter;
+mod fingerprint;
+mod group;
+mod id;
+#[cfg(feature = "file")]
+mod index;
+mod init;
+mod internal;
+pub mod json;
+mod level;
+mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "file")]
+mod msgpack;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(feature = "file")]
+mod reader;
+mod recorder;
+mod redact;
+mod reentrancy;
+mod schedule;
+#[cfg(feature = "sentry")]
+pub mod sentry;
+#[cfg(all(unix, feature = "shm"))]
+pub mod shm;
+mod sink;
+mod spill;
+mod stopwatch;
+mod stream;
+mod subscribe;
+pub mod thread;
+mod timing;
+#[cfg(feature = "viewer")]
+pub mod viewer;
+pub mod wrap;
+#[cfg(feature = "file")]
+mod writer;
+
+pub use adaptive::AdaptiveLevel;
+#[cfg(feature = "log")]
+pub use bridge::init_log_bridge;
+pub use config::{Config, ConfigSource, Setting};
+pub use console::ConsoleSuspend;
+#[cfg(all(unix, feature = "file"))]
+pub use crash::install_crash_handler;
+pub use errors::{errors_last_minute, errors_total};
+pub use fields::{Fields, Key, Value, INLINE_FIELDS};
+#[cfg(feature = "file")]
+pub use file::{expand_path, platform_log_path};
+pub use filter::{Source, SourceFilter};
+pub use group::RecordGroup;
+pub use id::{IdGenerator, Snowflake, UuidV7};
+pub use init::InitError;
+pub use level::{register_level, set_level_name, LogLevel};
+pub use memory::memory_used;
+#[cfg(feature = "file")]
+pub use reader::{timestamp, LogReader};
+pub use redact::RedactMode;
+pub use schedule::LevelWindow;
+pub use sink::{Sink, SinkId};
+pub use spill::Spill;
+pub use stopwatch::{stopwatch, Stopwatch};
+pub use stream::{RecordStream, DEFAULT_STREAM_BYTES};
+pub use subscribe::{subscribe, subscribe_with_capacity, DEFAULT_SUBSCRIBER_CAPACITY};
+
+use console::Console;
+#[cfg(feature = "file")]
+use file::FilePermissions;
+use id::SharedIdGenerator;
+use recorder::FlightRecorder;
+use redact::Redaction;
+use sink::Sinks;
+#[cfg(feature = "file")]
+use writer::Writer;
+
+/// When a record was logged.
+pub(crate) type Timestamp = chrono::DateTime<chrono::Local>;
+
+#[cfg(all(test, feature = "file"))]
 use std::hash::{Hash, Hasher};
 
+#[cfg(feature = "file")]
+#[cfg_attr(test, allow(dead_code))]
 const DEFAULT_LOG_FILE: &str = "woody.log";
 
 lazy_static! {
     static ref INSTANCE: Arc<Mutex<Option<Logger>>> = Arc::new(Mutex::new(None));
-    static ref FILENAME: Arc<Mutex<String>> = Arc::new(Mutex::new(DEFAULT_LOG_FILE.to_string()));
 }
 
-/// Determines the log level of a message.
-#[allow(dead_code)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum LogLevel {
-    /// Error level.
-    Error = 5,
-    /// Warning level.
-    Warning = 4,
-    /// Debug level.
-    Debug = 3,
-    /// Info level.
-    Info = 2,
-    /// Trace level.
-    Trace = 1,
-    /// Off level.
-    Off = 0,
-    /// ! Internal use only. Do not use.
-    ALL = -1,
+/// The line ending written after every record.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`. This is the default.
+    #[default]
+    Lf,
+    /// `\r\n`, which some Windows log viewers need to show one record per line.
+    CrLf,
+}
+
+impl LineEnding {
+    /// Parses `lf` or `crlf`.
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            _ => None,
+        }
+    }
+
+    /// The line ending itself.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// How records are written.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    /// One line of bracketed tokens per record. This is the default.
+    #[default]
+    Text,
+    /// One compact JSON object per line.
+    Json,
+    /// Indented JSON objects, for reading while debugging locally. The keys are always in the
+    /// same order. Tools that read one record per line need [`json::compact`] first.
+    JsonPretty,
+    /// Length-prefixed MessagePack maps with the same keys as `Json`, for the smallest files.
+    /// Only the log file is binary: the console, sinks, and error file get `Json`'s lines.
+    /// [`LogReader`] and `woody cat` decode it back to text.
+    MessagePack,
+}
+
+impl Format {
+    /// Parses `text`, `json`, `json-pretty`, or `msgpack`.
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            "json-pretty" | "json_pretty" => Some(Format::JsonPretty),
+            "msgpack" | "messagepack" => Some(Format::MessagePack),
+            _ => None,
+        }
+    }
+}
+
+/// The text encoding of the log file.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// Plain UTF-8. This is the default.
+    #[default]
+    Utf8,
+    /// UTF-8 starting with a byte order mark, which some Windows log viewers need to detect
+    /// the encoding. The mark is only written when woody creates the file.
+    Utf8Bom,
 }
 
-impl std::fmt::Display for LogLevel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Encoding {
+    /// Parses `utf8` or `utf8-bom`.
+    #[cfg(feature = "file")]
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "utf8" | "utf-8" => Some(Encoding::Utf8),
+            "utf8-bom" | "utf-8-bom" => Some(Encoding::Utf8Bom),
+            _ => None,
+        }
+    }
+
+    /// Reads `WOODY_ENCODING`.
+    #[cfg(feature = "file")]
+    fn from_config() -> Self {
+        config::setting("WOODY_ENCODING")
+            .and_then(|x| Encoding::parse(&x))
+            .unwrap_or_default()
+    }
+
+    /// The bytes a new log file starts with.
+    #[cfg(feature = "file")]
+    fn header(self) -> &'static [u8] {
         match self {
-            LogLevel::Debug => write!(f, "DEBUG"),
-            LogLevel::Info => write!(f, "INFO"),
-            LogLevel::Warning => write!(f, "WARNING"),
-            LogLevel::Error => write!(f, "ERROR"),
-            LogLevel::Trace => write!(f, "TRACE"),
-            LogLevel::Off => write!(f, "OFF"),
-            LogLevel::ALL => write!(f, ""),
+            Encoding::Utf8 => b"",
+            Encoding::Utf8Bom => b"\xEF\xBB\xBF",
+        }
+    }
+}
+
+/// Determines when log output is flushed to the OS and synced to disk.
+///
+/// Stronger guarantees cost throughput, so pick the weakest one your application can live with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Output is buffered and only written when the buffer fills (or on `Logger::flush`).
+    /// Records still in the buffer are lost if the process exits.
+    None,
+    /// Flush the buffer after every record. Records survive a crash of the process, but not
+    /// a power loss. This is the default.
+    #[default]
+    Flush,
+    /// Flush and `sync_data()` after every record. Records survive a power loss.
+    Fsync,
+    /// Flush after every record and `sync_data()` after every N records.
+    FsyncEveryN(u32),
+}
+
+impl Durability {
+    /// Parses a durability setting like `none`, `flush`, `fsync`, or `fsync:100`.
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Some(Durability::None),
+            "flush" => Some(Durability::Flush),
+            "fsync" => Some(Durability::Fsync),
+            other => {
+                let n = other.strip_prefix("fsync:")?.parse().ok()?;
+                Some(Durability::FsyncEveryN(n))
+            }
         }
     }
 }
@@ -58,16 +266,63 @@ impl std::fmt::Display for LogLevel {
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub struct Logger {
-    file: Arc<Mutex<File>>,
+    #[cfg(feature = "file")]
+    file: Writer,
     level: LogLevel,
-    filename: String,
+    durability: Durability,
+    ids: Option<SharedIdGenerator>,
+    console: Console,
+    sources: SourceFilter,
+    /// The name of this logger in the hierarchy, e.g. `app.db.pool`. `None` for the root.
+    name: Option<String>,
+    /// Levels set for named loggers, which apply to their descendants too.
+    named_levels: BTreeMap<String, LogLevel>,
+    /// Daily windows that replace the root level.
+    schedule: Vec<LevelWindow>,
+    /// Raises the root level for a while when errors pile up.
+    adaptive: Option<Arc<adaptive::Controller>>,
+    /// Keeps records below the level, to dump when an error occurs.
+    recorder: Option<Arc<FlightRecorder>>,
+    /// Structured fields to mask or hash.
+    redaction: Arc<Redaction>,
+    /// Fields added to every record, like the service name.
+    static_fields: Arc<Fields>,
+    /// Whether records at `WARNING` and above get a `fingerprint` field.
+    fingerprint: bool,
+    /// Filters, enrichment, and sinks loaded from dynamic libraries.
+    #[cfg(feature = "plugins")]
+    plugins: Vec<Arc<plugin::Plugin>>,
+    /// Where records at `ERROR` and above are forwarded as Sentry events, if anywhere.
+    #[cfg(feature = "sentry")]
+    sentry: Option<sentry::Sentry>,
+    /// Sinks added at runtime.
+    sinks: Sinks,
+    /// The `elapsed` and `delta` fields appended to records, if any.
+    timing: Option<timing::Timing>,
+    /// When each built-in sink was last written to, for `delta`.
+    sink_times: Arc<timing::SinkTimes>,
+    /// Where records at `ERROR` and above are copied to, if anywhere.
+    #[cfg(feature = "file")]
+    error_file: Option<Arc<errors::ErrorFile>>,
+    /// The ID of the `RecordGroup` being written, if any.
+    group: Option<String>,
+    /// Whether line breaks in messages are kept, as continuation lines. Set for the logger of
+    /// a `RecordStream`.
+    multiline: bool,
+    /// The code to exit with after a fatal record, if any.
+    fatal_exit: Option<i32>,
+    format: Format,
+    line_ending: LineEnding,
+    /// The path of the log file, shared between clones since they share the file.
+    #[cfg(feature = "file")]
+    filename: Arc<Mutex<String>>,
 }
 
 /// Generates a temp file name
 ///
 /// Returns a string that looks like this:
 /// `temp-8444741687653642537.log`
-#[cfg(test)]
+#[cfg(all(test, feature = "file"))]
 fn generate_temp_file_name() -> String {
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
     let now = chrono::Local::now();
@@ -83,26 +338,29 @@ fn generate_temp_file_name() -> String {
     format!("temp-{hash}.log")
 }
 
-#[cfg(not(test))]
-fn get_file_and_filename() -> (Arc<Mutex<File>>, String) {
-    let mut filename: String;
-    let file: Arc<Mutex<File>>;
-    filename = FILENAME.lock().unwrap().clone();
-    let env_filename = env::var("WOODY_FILE");
-    if let Ok(env_filename) = env_filename {
+#[cfg(all(feature = "file", not(test)))]
+fn get_file_and_filename() -> (File, String) {
+    let mut filename = DEFAULT_LOG_FILE.to_string();
+    // Desktop applications can opt into the platform's usual place for logs.
+    if let Some(path) = config::setting("WOODY_APP").and_then(|app| platform_log_path(&app)) {
+        filename = path.to_string_lossy().into_owned();
+    }
+    if let Some(env_filename) = config::setting("WOODY_FILE") {
         filename = env_filename;
     }
-    let f = OpenOptions::new().create(true).append(true).open(&filename);
-    file = Arc::new(Mutex::new(f.unwrap()));
-    return (file, filename);
+    let filename = expand_path(&filename).to_string_lossy().into_owned();
+    let f = file::open_with_header(
+        filename.as_ref(),
+        FilePermissions::from_env(),
+        Encoding::from_config().header(),
+    );
+    (f.unwrap(), filename)
 }
 
 /// Gets the file and filename to use for logging.
-#[cfg(test)]
-fn get_file_and_filename() -> (Arc<Mutex<File>>, String) {
-    let filename: String;
-    let file: Arc<Mutex<File>>;
-    let temp_dir_base = env::temp_dir();
+#[cfg(all(feature = "file", test))]
+fn get_file_and_filename() -> (File, String) {
+    let temp_dir_base = std::env::temp_dir();
     // append "logger" to the temp dir so it's like this:
     // /tmp/logger/temp-af44fa0-1f2c-4b5a-9c1f-7f8e9d0a1b2c.log
     let temp_dir = temp_dir_base.join("logger");
@@ -113,41 +371,231 @@ fn get_file_and_filename() -> (Arc<Mutex<File>>, String) {
     std::fs::create_dir(&temp_dir).unwrap();
     let temp_file_name = generate_temp_file_name();
     let temp_file_path = temp_dir.join(temp_file_name);
-    filename = temp_file_path.to_str().unwrap().to_string();
+    let filename = temp_file_path.to_str().unwrap().to_string();
 
-    let f = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(temp_file_path);
-    file = Arc::new(Mutex::new(f.unwrap()));
+    let f = file::open_with_header(
+        &temp_file_path,
+        FilePermissions::from_env(),
+        Encoding::from_config().header(),
+    );
 
-    (file, filename)
+    (f.unwrap(), filename)
 }
 
 impl Logger {
     /// Create a new logger. This is a singleton, so it can only be called once.
     fn new() -> Self {
-        let env_level = env::var("WOODY_LEVEL");
-        let level = match env_level {
-            Ok(x) => match x.to_lowercase().as_str() {
-                "error" | "5" => LogLevel::Error,
-                "warning" | "warn" | "4" => LogLevel::Warning,
-                "debug" | "3" => LogLevel::Debug,
-                "info" | "2" => LogLevel::Info,
-                "trace" | "1" => LogLevel::Trace,
-                "off" | "0" => LogLevel::Off,
-                _ => LogLevel::ALL,
+        level::set_level_names_from_env();
+        timing::start();
+
+        // `WOODY_LEVEL=info,app.db=debug` sets the root level and the level of `app.db`.
+        let mut level = LogLevel::ALL;
+        let mut named_levels = BTreeMap::new();
+        for entry in config::setting("WOODY_LEVEL")
+            .unwrap_or_default()
+            .split(',')
+        {
+            match entry.split_once('=') {
+                Some((name, x)) => {
+                    named_levels.insert(name.trim().to_string(), LogLevel::parse(x));
+                }
+                None if !entry.trim().is_empty() => level = LogLevel::parse(entry),
+                None => {}
+            }
+        }
+
+        // `WOODY_SCHEDULE=02:00-03:00=trace` replaces the root level during the window.
+        let schedule = config::setting("WOODY_SCHEDULE")
+            .map(|x| LevelWindow::parse_list(&x))
+            .unwrap_or_default();
+
+        // `WOODY_FLIGHT_RECORDER=1000` keeps the last 1000 records below the level.
+        let recorder = config::setting("WOODY_FLIGHT_RECORDER")
+            .and_then(|x| x.trim().parse().ok())
+            .filter(|capacity| *capacity > 0)
+            .map(|capacity| Arc::new(FlightRecorder::new(capacity)));
+
+        // `WOODY_PLUGINS=/opt/site/libenrich.so` loads plugins, separated by commas.
+        #[cfg(feature = "plugins")]
+        let plugins = config::setting("WOODY_PLUGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .filter_map(|path| match plugin::Plugin::load(path) {
+                Ok(plugin) => Some(Arc::new(plugin)),
+                Err(e) => {
+                    let _ =
+                        std::io::stderr().write_all(format!("[woody: plugin] {e}\n").as_bytes());
+                    None
+                }
+            })
+            .collect();
+
+        let durability = config::setting("WOODY_DURABILITY")
+            .and_then(|x| Durability::parse(&x))
+            .unwrap_or_default();
+
+        let ids = match config::setting("WOODY_IDS") {
+            Some(x) => match x.to_lowercase().as_str() {
+                "uuid" | "uuidv7" => Some(SharedIdGenerator(Arc::new(UuidV7))),
+                "snowflake" => Some(SharedIdGenerator(Arc::new(Snowflake::new(0)))),
+                _ => None,
             },
-            Err(_) => LogLevel::ALL,
+            None => None,
         };
 
-        let (file, filename) = get_file_and_filename();
+        // Without the file sink, the console is the only place records can go.
+        let console = Console::new(config::flag("WOODY_CONSOLE") || !cfg!(feature = "file"));
 
-        Self {
+        let fatal_exit = config::setting("WOODY_FATAL_EXIT").and_then(|x| x.parse().ok());
+
+        let sources = SourceFilter::from_lists(
+            &config::setting("WOODY_ALLOW").unwrap_or_default(),
+            &config::setting("WOODY_DENY").unwrap_or_default(),
+        );
+
+        let format = config::setting("WOODY_FORMAT")
+            .and_then(|x| Format::parse(&x))
+            .unwrap_or_default();
+
+        let line_ending = config::setting("WOODY_LINE_ENDING")
+            .and_then(|x| LineEnding::parse(&x))
+            .unwrap_or_default();
+
+        #[cfg(feature = "file")]
+        let (file, filename) = Self::open_file();
+
+        let logger = Self {
+            #[cfg(feature = "file")]
             file,
             level,
+            durability,
+            ids,
+            console,
+            sources,
+            name: None,
+            named_levels,
+            schedule,
+            adaptive: adaptive::Controller::from_config().map(Arc::new),
+            recorder,
+            redaction: Arc::new(Redaction::from_config()),
+            static_fields: Arc::new(fields::from_config()),
+            fingerprint: config::flag("WOODY_FINGERPRINT"),
+            #[cfg(feature = "plugins")]
+            plugins,
+            #[cfg(feature = "sentry")]
+            sentry: None,
+            sinks: Sinks::default(),
+            timing: timing::Timing::from_config(),
+            sink_times: Arc::default(),
+            #[cfg(feature = "file")]
+            error_file: errors::ErrorFile::from_config().map(Arc::new),
+            group: None,
+            multiline: false,
+            fatal_exit,
+            format,
+            line_ending,
+            #[cfg(feature = "file")]
             filename,
+        };
+
+        #[cfg(all(unix, feature = "file"))]
+        if config::flag("WOODY_CRASH_HANDLER") {
+            let _ = crash::install(&logger);
         }
+
+        internal::event(LogLevel::Debug, || {
+            #[cfg(feature = "file")]
+            let file = logger.filename();
+            #[cfg(not(feature = "file"))]
+            let file = "none";
+            format!(
+                "initialized: file={file} level={:?} format={:?} console={}",
+                logger.level, logger.format, logger.console.enabled
+            )
+        });
+        logger
+    }
+
+    /// Opens the log file and starts its writer, according to the file settings.
+    #[cfg(feature = "file")]
+    fn open_file() -> (Writer, Arc<Mutex<String>>) {
+        let is_async = config::flag("WOODY_ASYNC");
+        let idle_flush = config::setting("WOODY_IDLE_FLUSH_MS")
+            .and_then(|x| x.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(writer::DEFAULT_IDLE_FLUSH);
+
+        let (file, filename) = get_file_and_filename();
+        let (file, index) = Self::prepare_file(file, &filename).unwrap();
+        let mut file = writer::LogFile::new(file, index);
+        if config::flag("WOODY_SEQUENCE") {
+            file = file.numbered();
+        }
+        let file = if is_async {
+            Writer::spawn(file, idle_flush)
+        } else {
+            Writer::sync(file)
+        };
+        (file, Arc::new(Mutex::new(filename)))
+    }
+
+    /// Opens the index of a newly opened log file if `WOODY_INDEX` is set.
+    #[cfg(feature = "file")]
+    fn prepare_file(file: File, filename: &str) -> io::Result<(File, Option<index::IndexWriter>)> {
+        if !config::flag("WOODY_INDEX") {
+            return Ok((file, None));
+        }
+        let len = file.metadata().map_or(0, |m| m.len());
+        match index::IndexWriter::open(std::path::Path::new(filename), len) {
+            Ok(index) => Ok((file, Some(index))),
+            Err(e) => {
+                internal::event(LogLevel::Warning, || {
+                    format!("couldn't open the index of {filename}: {e}")
+                });
+                Ok((file, None))
+            }
+        }
+    }
+
+    /// Switches to another log file at runtime, e.g. `WOODY_FILE`'s value from a reloaded
+    /// config. The new file is opened (and created, with its directories) first, so on error
+    /// nothing changes. Records logged before this call stay in the old file, which is flushed
+    /// and closed; records logged after it go to the new one, from every clone of this logger.
+    ///
+    /// On Unix, a crash handler installed in async mode keeps writing to the old file.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// woody::Logger::get_instance().set_log_file("/var/log/app/woody.log")?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(feature = "file")]
+    pub fn set_log_file(&self, path: &str) -> io::Result<()> {
+        let filename = expand_path(path).to_string_lossy().into_owned();
+        let file = file::open_with_header(
+            std::path::Path::new(&filename),
+            FilePermissions::from_env(),
+            Encoding::from_config().header(),
+        )?;
+        let (file, index) = Self::prepare_file(file, &filename)?;
+        let mut current = self.filename.lock().unwrap_or_else(|e| e.into_inner());
+        self.file.replace(file, index)?;
+        internal::event(LogLevel::Debug, || {
+            format!("switched the log file from {current} to {filename}")
+        });
+        *current = filename;
+        Ok(())
+    }
+
+    /// The path of the log file.
+    #[cfg(feature = "file")]
+    fn filename(&self) -> String {
+        self.filename
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
     }
 
     /// Set the log level. This will only log messages that are equal to or above the log level.
@@ -155,35 +603,638 @@ impl Logger {
         self.level = level;
     }
 
-    /// Log a message at the given level.
-    pub fn log<W: Write>(&self, info: &LogInfo, writer: Option<&mut W>) {
-        if self.level > info.level || self.level == LogLevel::Off {
-            // println!(
-            //     "not logging because self.level ({} {}) > info.level ({} {})",
-            //     self.level, self.level as u8, info.level, info.level as u8
-            // );
+    /// Set the level of the named logger `name` and its descendants, e.g. `app.db` also covers
+    /// `app.db.pool` unless that has a level of its own.
+    pub fn set_named_level(&mut self, name: &str, level: LogLevel) {
+        self.named_levels.insert(name.to_string(), level);
+    }
+
+    /// Returns a logger named `name`, which shares everything with this one except its name.
+    /// Its level is the one set for the closest ancestor in the dotted hierarchy, falling back
+    /// to the root level.
+    ///
+    /// # Examples
+    /// ```
+    /// use woody::{log, LogLevel, Logger};
+    /// let mut root = Logger::get_instance();
+    /// root.set_level(LogLevel::Warning);
+    /// root.set_named_level("app.db", LogLevel::Debug);
+    /// let pool = root.named("app.db.pool");
+    /// assert_eq!(pool.effective_level(), LogLevel::Debug);
+    /// log!(logger: pool, LogLevel::Debug, "connection checked out");
+    /// ```
+    pub fn named(&self, name: &str) -> Logger {
+        Logger {
+            name: Some(name.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a child of this logger, e.g. `pool` under `app.db` is `app.db.pool`.
+    pub fn child(&self, name: &str) -> Logger {
+        match &self.name {
+            Some(parent) => self.named(&format!("{parent}.{name}")),
+            None => self.named(name),
+        }
+    }
+
+    /// Starts buffering the records of one operation. See [`RecordGroup`].
+    pub fn group(&self) -> RecordGroup {
+        RecordGroup::new(self.clone())
+    }
+
+    /// Starts a record that streamed output is appended to, written as one multi-line entry
+    /// when it's dropped. See [`RecordStream`].
+    #[track_caller]
+    pub fn stream(&self, level: LogLevel, message: impl Into<String>) -> RecordStream {
+        let location = std::panic::Location::caller();
+        let record = Record::new(level, message, location.file(), location.line(), "");
+        RecordStream::new(self.clone(), record)
+    }
+
+    /// The configuration in effect: the root level and log file as they are now, and every
+    /// setting that was read and where from. See the `config` docs in the README for which
+    /// source wins.
+    ///
+    /// # Examples
+    /// ```
+    /// let config = woody::Logger::get_instance().effective_config();
+    /// woody::log_info!("logging with\n{config}");
+    /// ```
+    pub fn effective_config(&self) -> Config {
+        Config {
+            level: self.level,
+            #[cfg(feature = "file")]
+            file: Some(self.filename()),
+            #[cfg(not(feature = "file"))]
+            file: None,
+            settings: config::resolved(),
+        }
+    }
+
+    /// The level this logger actually filters at, after inheriting from its ancestors.
+    pub fn effective_level(&self) -> LogLevel {
+        let mut name = self.name.as_deref();
+        while let Some(current) = name {
+            if let Some(level) = self.named_levels.get(current) {
+                return *level;
+            }
+            name = current.rsplit_once('.').map(|(parent, _)| parent);
+        }
+        let level = schedule::active(&self.schedule).unwrap_or(self.level);
+        match self.adaptive.as_ref().and_then(|x| x.active()) {
+            Some(raised) => level.min(raised),
+            None => level,
+        }
+    }
+
+    /// Set the daily windows during which the root level is replaced, e.g. `Trace` during the
+    /// nightly batch. The first window containing the current time wins.
+    pub fn set_level_schedule(&mut self, windows: Vec<LevelWindow>) {
+        self.schedule = windows;
+    }
+
+    /// Set when to raise the root level for a while as errors pile up, e.g. to `Debug` for two
+    /// minutes after 10 errors in a minute. `None` turns it off.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use woody::{AdaptiveLevel, LogLevel, Logger};
+    /// let mut logger = Logger::get_instance();
+    /// let minute = Duration::from_secs(60);
+    /// logger.set_adaptive_level(Some(AdaptiveLevel::new(10, minute, LogLevel::Debug, 2 * minute)));
+    /// Logger::set_instance(logger);
+    /// ```
+    pub fn set_adaptive_level(&mut self, adaptive: Option<AdaptiveLevel>) {
+        self.adaptive = adaptive.map(|x| Arc::new(adaptive::Controller::new(x)));
+    }
+
+    /// Set how records are written. Records already in the file are left as they are.
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// Set the line ending written after every record.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    /// Set the durability. This controls when the log file is flushed and synced to disk.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Set the generator used to stamp every record with a unique ID, or `None` to stop
+    /// stamping records.
+    pub fn set_id_generator<G: IdGenerator + 'static>(&mut self, generator: Option<G>) {
+        self.ids = generator.map(|g| SharedIdGenerator(Arc::new(g)));
+    }
+
+    /// Set which files and crates may log. Records from other sources are dropped regardless
+    /// of their level.
+    pub fn set_source_filter(&mut self, filter: SourceFilter) {
+        self.sources = filter;
+    }
+
+    /// Enable or disable echoing records to stderr in addition to the log file.
+    pub fn set_console(&mut self, enabled: bool) {
+        self.console.enabled = enabled;
+    }
+
+    /// Set the hook used to hide progress bars (or any other in-place terminal UI) while a
+    /// record is echoed to the console, so the two don't garble each other.
+    ///
+    /// # Examples
+    /// ```
+    /// use woody::Logger;
+    /// let mut logger = Logger::get_instance();
+    /// logger.set_console(true);
+    /// logger.set_console_suspend(Some(|write: &mut dyn FnMut()| {
+    ///     // e.g. `multi_progress.suspend(write)`
+    ///     write();
+    /// }));
+    /// ```
+    pub fn set_console_suspend<S: ConsoleSuspend + 'static>(&mut self, suspend: Option<S>) {
+        self.console
+            .set_suspend(suspend.map(|s| Arc::new(s) as Arc<dyn ConsoleSuspend>));
+    }
+
+    /// Set the code the process exits with after `log_fatal!`, or `None` to return control to
+    /// the caller instead.
+    pub fn set_fatal_exit(&mut self, code: Option<i32>) {
+        self.fatal_exit = code;
+    }
+
+    /// Flushes everything synchronously (see [`Logger::flush`]), then exits if a fatal exit code
+    /// is set. Called by `log_fatal!` after logging, so the fatal record is never lost.
+    pub fn finish_fatal(&self) {
+        self.flush();
+        if let Some(code) = self.fatal_exit {
+            std::process::exit(code);
+        }
+    }
+
+    /// Flush any buffered records: sync the log file and the error file to disk, flush every
+    /// sink added with [`Logger::add_sink`], and flush stderr. With the `metrics` feature, every
+    /// timer's summary is logged first.
+    ///
+    /// In async mode this blocks until the writer thread has written everything queued so far.
+    pub fn flush(&self) {
+        #[cfg(feature = "metrics")]
+        metrics::summarize_timers();
+        #[cfg(feature = "file")]
+        self.file.flush();
+        #[cfg(feature = "file")]
+        if let Some(error_file) = &self.error_file {
+            error_file.flush();
+        }
+        self.sinks.flush();
+        let _ = std::io::stderr().flush();
+    }
+
+    /// Logs a record to the log file (and the console, if enabled).
+    ///
+    /// If this is called from inside the logger on the same thread (say, an ID generator or
+    /// console hook that logs), the record is written straight to stderr instead, without
+    /// running any hooks, rather than deadlocking.
+    pub fn log(&self, record: &Record) {
+        let redacted = self.enrich(record);
+        let record: &Record = &redacted;
+        if !self.enabled(record) {
+            self.remember(record);
             return;
         }
+        let Some((_guard, output)) = self.prepare(record) else {
+            return;
+        };
+        subscribe::publish(record);
+        #[cfg(feature = "sentry")]
+        self.forward_to_sentry(record);
+        match self.flight_dump(record.level) {
+            Some((dump, count)) => {
+                self.write_file(&format!("{dump}{output}"), count + 1, record.level);
+            }
+            None => self.write_file(&output, 1, record.level),
+        }
+        #[cfg(feature = "plugins")]
+        plugin::write(&self.plugins, &output);
+        self.sinks.write(&output, self.timing);
+        self.write_errors(record.level, &output);
+        if self.console.enabled {
+            let output = self.sink_times.console.stamp(self.timing, &output);
+            self.console.write(&[(record.level, output.into_owned())]);
+        }
+    }
 
-        let now = chrono::Local::now();
+    /// Writes a record to `writer` instead of the log file, subject to the same filtering as
+    /// [`Logger::log`].
+    ///
+    /// # Examples
+    /// ```
+    /// use woody::{LogLevel, Logger, Record};
+    /// let record = Record::new(LogLevel::Error, "disk full", file!(), line!(), module_path!());
+    /// let mut out = Vec::new();
+    /// Logger::get_instance().write_to(&record, &mut out).unwrap();
+    /// assert!(String::from_utf8(out).unwrap().contains("[ERROR]"));
+    /// ```
+    pub fn write_to(&self, record: &Record, writer: &mut dyn Write) -> io::Result<()> {
+        let redacted = self.enrich(record);
+        let record: &Record = &redacted;
+        if !self.enabled(record) {
+            return Ok(());
+        }
+        match self.prepare(record) {
+            Some((_guard, output)) => writer.write_all(output.as_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    /// Renders a record that passed the filters. The guard must be held until the record is
+    /// written, so hooks that log while writing take the fallback path.
+    fn prepare(&self, record: &Record) -> Option<(reentrancy::Guard, String)> {
+        let Some(guard) = reentrancy::Guard::enter() else {
+            reentrancy::fallback(&self.format(record, "", None, None));
+            return None;
+        };
+        #[cfg(feature = "plugins")]
+        let enriched = plugin::apply(&self.plugins, record, &self.redaction)?;
+        #[cfg(feature = "plugins")]
+        let record: &Record = &enriched;
+        Some((guard, self.render(record, None)))
+    }
+
+    /// Forwards records at `ERROR` and above to Sentry, see [`sentry::Sentry`]. `None` stops.
+    #[cfg(feature = "sentry")]
+    pub fn set_sentry(&mut self, sentry: Option<sentry::Sentry>) {
+        self.sentry = sentry;
+    }
+
+    #[cfg(feature = "sentry")]
+    fn forward_to_sentry(&self, record: &Record) {
+        if let Some(sentry) = &self.sentry {
+            sentry.send(record, &self.tags(None));
+        }
+    }
+
+    /// Loads a plugin from a dynamic library. See [`plugin`] for what it may export.
+    #[cfg(feature = "plugins")]
+    pub fn load_plugin(&mut self, path: &str) -> io::Result<()> {
+        self.plugins.push(Arc::new(plugin::Plugin::load(path)?));
+        Ok(())
+    }
+
+    /// Copies records at `ERROR` and above to a file of their own, e.g. `errors.log`, besides
+    /// the log file. It's rotated to `<path>.1` once it would grow past `max_bytes`. `None`
+    /// turns it off.
+    #[cfg(feature = "file")]
+    pub fn set_error_file(&mut self, path: Option<&str>, max_bytes: u64) {
+        self.error_file =
+            path.map(|path| Arc::new(errors::ErrorFile::new(&expand_path(path), max_bytes)));
+    }
+
+    /// Attaches a sink that receives every formatted record from now on, like the log file,
+    /// until it's removed. Takes effect for this logger and every clone of it, including the
+    /// global instance and loggers already handed out.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::net::TcpListener;
+    /// use woody::Logger;
+    /// let (client, _) = TcpListener::bind("127.0.0.1:9998")?.accept()?;
+    /// let id = Logger::get_instance().add_sink(client);
+    /// // ... until the client is done debugging:
+    /// Logger::get_instance().remove_sink(id);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn add_sink(&self, sink: impl Sink + 'static) -> SinkId {
+        self.sinks.add(Box::new(sink))
+    }
+
+    /// Detaches a sink added with [`Logger::add_sink`], returning it. Returns `None` if it was
+    /// already removed.
+    pub fn remove_sink(&self, id: SinkId) -> Option<Box<dyn Write + Send>> {
+        self.sinks.remove(id)
+    }
+
+    /// Set fields to add to every record, like deployment metadata, replacing those from
+    /// `WOODY_FIELDS`. A record's own field with the same name wins.
+    ///
+    /// # Examples
+    /// ```
+    /// use woody::{Fields, Logger};
+    /// let mut logger = Logger::get_instance();
+    /// logger.set_static_fields([("service", "billing"), ("env", "prod")].into_iter().collect());
+    /// Logger::set_instance(logger);
+    /// ```
+    pub fn set_static_fields(&mut self, fields: Fields) {
+        self.static_fields = Arc::new(fields);
+    }
+
+    /// Adds a `fingerprint` field to records at `WARNING` and above, grouping records of the
+    /// same issue: a hash of the file, line, and message with numbers, IDs, and quoted values
+    /// taken out. Also turned on by `WOODY_FINGERPRINT=1`.
+    pub fn set_fingerprint(&mut self, enabled: bool) {
+        self.fingerprint = enabled;
+    }
+
+    /// Redacts a record and adds the fingerprint and static fields, only cloning it if any of
+    /// them changes it.
+    fn enrich<'a>(&self, record: &'a Record) -> std::borrow::Cow<'a, Record> {
+        let redacted = self.redaction.apply(record);
+        let fingerprint = (self.fingerprint && record.level >= LogLevel::Warning)
+            .then(|| fingerprint::of(record));
+        if self.static_fields.is_empty() && fingerprint.is_none() {
+            return redacted;
+        }
+        let mut record = redacted.into_owned();
+        if let Some(fingerprint) = fingerprint {
+            record.fields.push("fingerprint", fingerprint);
+        }
+        for (key, value) in self.static_fields.iter() {
+            if record.fields.get(key).is_none() {
+                record.fields.push(key, value.clone());
+            }
+        }
+        std::borrow::Cow::Owned(record)
+    }
+
+    /// Set the structured fields to redact, by name (ignoring case), and what to replace their
+    /// values with. Applies to every sink and format.
+    pub fn set_redacted_fields(&mut self, fields: &[&str], mode: RedactMode) {
+        let salt = config::setting("WOODY_REDACT_SALT").unwrap_or_default();
+        self.redaction = Arc::new(Redaction::new(fields, mode, &salt));
+    }
+
+    /// Keep the last `capacity` records below the level in memory, and write them to the file
+    /// before the next error (or when [`Logger::dump_flight_recorder`] is called). `0` turns the
+    /// flight recorder off.
+    ///
+    /// Clones of this logger made afterwards share the recorder.
+    pub fn set_flight_recorder(&mut self, capacity: usize) {
+        self.recorder = (capacity > 0).then(|| Arc::new(FlightRecorder::new(capacity)));
+    }
+
+    /// Writes the flight recorder's records to the file and empties it.
+    pub fn dump_flight_recorder(&self) {
+        if let Some((dump, count)) = self.flight_dump(LogLevel::Fatal) {
+            self.write_file(&dump, count, LogLevel::ALL);
+        }
+    }
+
+    /// Writes `records` formatted records, the most severe at `level`, to the log file. Without
+    /// the `file` feature there's no log file, so this does nothing.
+    #[cfg(feature = "file")]
+    fn write_file(&self, output: &str, records: u32, level: LogLevel) {
+        let output = self.sink_times.file.stamp(self.timing, output);
+        match self.format {
+            Format::MessagePack => {
+                let frames = msgpack::encode_lines(&output);
+                self.file
+                    .write(&frames, records, level, self.durability, false);
+            }
+            _ => self
+                .file
+                .write(output.as_bytes(), records, level, self.durability, true),
+        }
+    }
+
+    #[cfg(not(feature = "file"))]
+    fn write_file(&self, _output: &str, _records: u32, _level: LogLevel) {}
+
+    /// Counts a written record at `ERROR` or above and copies it to the error file, if set.
+    #[cfg_attr(not(feature = "file"), allow(unused_variables))]
+    fn write_errors(&self, level: LogLevel, output: &str) {
+        if level < LogLevel::Error {
+            return;
+        }
+        errors::count(1);
+        #[cfg(feature = "file")]
+        if let Some(error_file) = &self.error_file {
+            error_file.write(&self.sink_times.error_file.stamp(self.timing, output));
+        }
+    }
+
+    /// Keeps a record that didn't pass the level filter in the flight recorder.
+    fn remember(&self, record: &Record) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        if record.level > LogLevel::Off && self.sources.permits(record.filepath, record.module_path)
+        {
+            // No ID, since generating one would run a hook for a record that may never be read.
+            let line = self.format(record, &self.tags(None), None, None);
+            #[cfg(all(unix, feature = "file"))]
+            let frame = (self.format == Format::MessagePack && crash::installed())
+                .then(|| msgpack::encode_lines(&line));
+            #[cfg(not(all(unix, feature = "file")))]
+            let frame = None;
+            recorder.push(line, frame);
+        }
+    }
+
+    /// Empties the flight recorder if a record at `level` should dump it, returning the text to
+    /// write and how many lines it has.
+    fn flight_dump(&self, level: LogLevel) -> Option<(String, u32)> {
+        if level < LogLevel::Error {
+            return None;
+        }
+        let records = self.recorder.as_ref()?.take();
+        if records.is_empty() {
+            return None;
+        }
+        let count = records.len();
+        let header = format!(
+            "[woody: flight recorder] {count} earlier records below the log level{}",
+            self.line_ending.as_str()
+        );
+        Some((header + &records.concat(), count as u32 + 1))
+    }
+
+    /// Logs many records at once, e.g. the events collected while handling a request.
+    ///
+    /// The records are written with a single locked write, so they end up next to each other
+    /// in the file and cost one syscall instead of one each.
+    pub fn log_batch(&self, infos: &[Record]) {
+        self.log_batch_at(infos.iter().map(|info| (info, None)).collect());
+    }
+
+    /// Like [`Logger::log_batch`], but each record may come with the time and context it was
+    /// logged in, for records written some time after (see [`RecordGroup`]).
+    pub(crate) fn log_batch_at(&self, infos: Vec<(&Record, Option<&group::Logged>)>) {
+        let redacted: Vec<_> = infos
+            .into_iter()
+            .map(|(info, at)| (self.enrich(info), at))
+            .collect();
+        let (infos, filtered): (Vec<_>, Vec<_>) = redacted
+            .iter()
+            .map(|(info, at)| (info.as_ref(), *at))
+            .partition(|(info, _)| self.enabled(info));
+        for (info, _) in filtered {
+            self.remember(info);
+        }
+        if infos.is_empty() {
+            return;
+        }
+
+        let Some(_guard) = reentrancy::Guard::enter() else {
+            for (info, logged) in infos {
+                reentrancy::fallback(&self.format(info, "", None, logged.map(|x| x.time)));
+            }
+            return;
+        };
+
+        #[cfg(feature = "plugins")]
+        let infos: Vec<(std::borrow::Cow<Record>, Option<&group::Logged>)> = infos
+            .into_iter()
+            .filter_map(|(info, logged)| {
+                Some((plugin::apply(&self.plugins, info, &self.redaction)?, logged))
+            })
+            .collect();
+        // Records logged earlier are rendered in the context they were logged in.
+        let attach = |logged: Option<&group::Logged>| logged.map(|x| x.context.clone().attach());
+        let lines: Vec<(LogLevel, String)> = infos
+            .iter()
+            .map(|(info, logged)| {
+                let _context = attach(*logged);
+                (info.level, self.render(info, logged.map(|x| x.time)))
+            })
+            .collect();
+        for (info, _logged) in &infos {
+            subscribe::publish(info);
+            #[cfg(feature = "sentry")]
+            let _context = attach(*_logged);
+            #[cfg(feature = "sentry")]
+            self.forward_to_sentry(info);
+        }
+        let mut output: String = lines.iter().map(|(_, line)| line.as_str()).collect();
+        self.sinks.write(&output, self.timing);
+        let mut count = lines.len() as u32;
+        let worst = infos
+            .iter()
+            .map(|(info, _)| info.level)
+            .max()
+            .unwrap_or(LogLevel::ALL);
+        if let Some((dump, dumped)) = self.flight_dump(worst) {
+            output = dump + &output;
+            count += dumped;
+        }
+
+        self.write_file(&output, count, worst);
+        #[cfg(feature = "plugins")]
+        for (_, line) in &lines {
+            plugin::write(&self.plugins, line);
+        }
+        for (level, line) in &lines {
+            self.write_errors(*level, line);
+        }
+        if self.console.enabled {
+            let mut lines = lines;
+            if self.timing.is_some() {
+                for (_, line) in &mut lines {
+                    *line = self
+                        .sink_times
+                        .console
+                        .stamp(self.timing, line)
+                        .into_owned();
+                }
+            }
+            self.console.write(&lines);
+        }
+    }
+
+    /// Whether a record passes the level and source filters.
+    fn enabled(&self, info: &Record) -> bool {
+        // Every record is offered here first, so it's where errors are counted.
+        if let Some(adaptive) = &self.adaptive {
+            adaptive.observe(info.level);
+        }
+        let level = self.effective_level();
+        if level > info.level || level == LogLevel::Off {
+            return false;
+        }
+        self.sources.permits(info.filepath, info.module_path)
+    }
+
+    /// Counts and formats a record that passed the filters, running the hooks that tag it.
+    fn render(&self, info: &Record, at: Option<Timestamp>) -> String {
+        #[cfg(feature = "metrics")]
+        metrics::record(info.level);
+
+        let id = self.ids.as_ref().map(|ids| ids.0.generate());
+        match self.format {
+            Format::Text => self.format(info, &self.tags(id.as_deref()), None, at),
+            // The ID gets a key of its own, so aggregators can index it.
+            _ => self.format(info, &self.tags(None), id.as_deref(), at),
+        }
+    }
+
+    /// The bracketed tokens put before the message: the logger name, group, record ID (if
+    /// given), and context.
+    fn tags(&self, id: Option<&str>) -> String {
+        let mut tags = String::new();
+        if let Some(name) = &self.name {
+            tags.push_str(&format!("[{}] ", escape::token(name)));
+        }
+        if let Some(group) = &self.group {
+            tags.push_str(&format!("[group={}] ", escape::token(group)));
+        }
+        if let Some(id) = id {
+            tags.push_str(&format!("[id={}] ", escape::token(id)));
+        }
+        tags.push_str(&context::current().tags());
+        tags
+    }
+
+    /// Formats a record as a line of text. `tags` are extra bracketed tokens to put before the
+    /// message, like the logger name, and must already be escaped. `id` is the record ID for
+    /// the JSON formats, which put it under a key of its own. `at` is when the record was
+    /// logged, if that wasn't just now.
+    fn format(&self, info: &Record, tags: &str, id: Option<&str>, at: Option<Timestamp>) -> String {
+        let now = at.unwrap_or_else(chrono::Local::now);
         let thread = info.thread.clone().unwrap_or_else(|| {
             let thread = std::thread::current();
             let name = thread.name().unwrap_or("unnamed");
             name.to_string()
         });
         let location = format!("{}:{}", info.filepath, info.line_number);
-        let level = info.level;
-        let message = info.message.clone();
-        let now_string = now.format("%Y-%m-%d %H:%M:%S%.3f %Z");
-        let output = format!("[{now_string}] [{level}] [{thread}] [{location}] {message}\n");
-
-        if let Some(writer) = writer {
-            writer.write_all(output.as_bytes()).unwrap();
-            return;
+        let level = info.level.to_string();
+        let line_ending = self.line_ending.as_str();
+        if self.format != Format::Text {
+            let now_string = now.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string();
+            let mut entries = vec![
+                ("time", now_string.as_str()),
+                ("level", level.as_str()),
+                ("thread", thread.as_str()),
+                ("location", location.as_str()),
+            ];
+            if let Some(id) = id {
+                entries.push(("id", id));
+            }
+            let tags = tags.trim_end();
+            if !tags.is_empty() {
+                entries.push(("tags", tags));
+            }
+            entries.push(("message", info.message.as_str()));
+            let json = json::record(&entries, &info.fields);
+            return match self.format {
+                Format::JsonPretty => json::pretty(&json).replace('\n', line_ending),
+                _ => json + line_ending,
+            };
         }
-
-        let mut file = self.file.lock().unwrap();
-        file.write_all(output.as_bytes()).unwrap();
+        let thread = escape::token(&thread);
+        let location = escape::token(&location);
+        let level = escape::token(&level);
+        let message = match info.message.split_once('\n') {
+            Some((first, rest)) if self.multiline => {
+                escape::message(first)
+                    + &fields::format(&info.fields)
+                    + &stream::continuation(rest, line_ending)
+            }
+            _ => escape::message(&info.message) + &fields::format(&info.fields),
+        };
+        let now_string = now.format("%Y-%m-%d %H:%M:%S%.3f %Z");
+        format!("[{now_string}] [{level}] [{thread}] [{location}] {tags}{message}{line_ending}")
     }
 
     /// Gets the instance of the logger. If the logger is not created, it will create it.
@@ -201,11 +1252,53 @@ impl Logger {
             current_global_instance_lock.clone().unwrap()
         }
     }
+
+    /// Initializes the global logger and returns it, failing if `init` was already called.
+    /// The error says where the first call was, for tracking down two crates that both set
+    /// woody up. [`Logger::get_instance`] never fails, and calling it first doesn't count.
+    ///
+    /// # Errors
+    /// Fails if the logger was already initialized with `init` or `init_or_noop`.
+    ///
+    /// # Examples
+    /// ```
+    /// use woody::Logger;
+    /// let logger = Logger::init().unwrap();
+    /// let error = Logger::init().unwrap_err();
+    /// assert_eq!(error.first_init().file(), file!());
+    /// ```
+    #[track_caller]
+    pub fn init() -> Result<Logger, InitError> {
+        init::claim(std::panic::Location::caller())?;
+        Ok(Logger::get_instance())
+    }
+
+    /// Like [`Logger::init`], but returns the existing logger if it was already initialized,
+    /// e.g. in tests that each set it up.
+    #[track_caller]
+    pub fn init_or_noop() -> Logger {
+        let _ = init::claim(std::panic::Location::caller());
+        Logger::get_instance()
+    }
+
+    /// Replaces the global instance, so settings changed on a logger apply to the log macros.
+    ///
+    /// # Examples
+    /// ```
+    /// use woody::{Logger, UuidV7};
+    /// let mut logger = Logger::get_instance();
+    /// logger.set_id_generator(Some(UuidV7));
+    /// Logger::set_instance(logger);
+    /// ```
+    pub fn set_instance(logger: Logger) {
+        internal::event(LogLevel::Debug, || "replaced the global logger".to_string());
+        *INSTANCE.lock().unwrap() = Some(logger);
+    }
 }
 
-/// The log info struct. This is used to log a message.
-#[derive(Clone)]
-pub struct LogInfo {
+/// A record to log: a message and where it came from.
+#[derive(Clone, Debug)]
+pub struct Record {
     /// The log level.
     pub level: LogLevel,
     /// The message to log.
@@ -214,10 +1307,57 @@ pub struct LogInfo {
     pub filepath: &'static str,
     /// The line number of the file that called the log macro.
     pub line_number: u32,
+    /// The module path of the code that called the log macro, e.g. `my_app::server`.
+    pub module_path: &'static str,
     /// The thread that called the log macro.
     pub thread: Option<String>,
+    /// Structured fields, e.g. `user_id=42`.
+    pub fields: Fields,
 }
 
+impl Record {
+    /// Creates a record logged from the current thread.
+    pub fn new(
+        level: LogLevel,
+        message: impl Into<String>,
+        filepath: &'static str,
+        line_number: u32,
+        module_path: &'static str,
+    ) -> Self {
+        Self {
+            level,
+            message: message.into(),
+            filepath,
+            line_number,
+            module_path,
+            thread: None,
+            fields: Fields::new(),
+        }
+    }
+
+    /// Adds a structured field.
+    pub fn with_field(mut self, key: fields::Key, value: impl Into<Value>) -> Self {
+        self.fields.push(key, value);
+        self
+    }
+}
+
+/// Switches the global logger to another log file. See [`Logger::set_log_file`].
+#[cfg(feature = "file")]
+pub fn set_log_file(path: &str) -> io::Result<()> {
+    Logger::get_instance().set_log_file(path)
+}
+
+/// Writes the global logger's flight recorder to the file. See
+/// [`Logger::set_flight_recorder`].
+pub fn dump_flight_recorder() {
+    Logger::get_instance().dump_flight_recorder();
+}
+
+/// The old name of [`Record`].
+#[deprecated(note = "renamed to `Record`")]
+pub type LogInfo = Record;
+
 /// The log macro. Used in other macros.
 ///
 /// # Examples
@@ -226,35 +1366,61 @@ pub struct LogInfo {
 /// use woody::LogLevel;
 /// log!(LogLevel::Info, "Hello, world!");
 /// log!("Hello, world!");
+/// let db = woody::Logger::get_instance().named("app.db");
+/// log!(logger: db, LogLevel::Info, "Hello from app.db!");
+/// log!(LogLevel::Info, "Request handled"; status = 200, cached = false);
 /// ```
 #[macro_export]
 macro_rules! log {
-    ($message:expr) => {
+    (logger: $logger:expr, $level:expr, $message:expr; $($key:ident = $value:expr),+ $(,)?) => {{
+        let mut info = $crate::Record::new($level, $message.to_string(), file!(), line!(), module_path!());
+        $(info.fields.push(stringify!($key), $value);)+
+        $logger.log(&info);
+    }};
+    ($level:expr, $message:expr; $($key:ident = $value:expr),+ $(,)?) => {{
+        $crate::log!(logger: $crate::Logger::get_instance(), $level, $message; $($key = $value),+);
+    }};
+    (logger: $logger:expr, $level:expr, $message:expr) => {{
+        let message = $message.to_string();
+        let info = $crate::Record {
+            level: $level,
+            message,
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: None,
+            fields: $crate::Fields::new(),
+        };
+        $logger.log(&info);
+    }};
+    ($message:expr) => {{
         let message = $message.to_string();
         let logger = $crate::Logger::get_instance();
-        let info = $crate::LogInfo {
+        let info = $crate::Record {
             level: $crate::LogLevel::Info,
             message,
             filepath: file!(),
             line_number: line!(),
+            module_path: module_path!(),
             thread: None,
+            fields: $crate::Fields::new(),
         };
-        let writer: Option<&mut Vec<u8>> = None;
-        logger.log(&info, writer);
-    };
-    ($level:expr, $message:expr) => {
+        logger.log(&info);
+    }};
+    ($level:expr, $message:expr) => {{
         let message = $message.to_string();
         let logger = $crate::Logger::get_instance();
-        let info = $crate::LogInfo {
+        let info = $crate::Record {
             level: $level,
             message,
             filepath: file!(),
             line_number: line!(),
+            module_path: module_path!(),
             thread: None,
+            fields: $crate::Fields::new(),
         };
-        let writer: Option<&mut Vec<u8>> = None;
-        logger.log(&info, writer);
-    };
+        logger.log(&info);
+    }};
 }
 
 /// Logs a debug message.
@@ -266,14 +1432,14 @@ macro_rules! log {
 /// ```
 #[macro_export]
 macro_rules! log_debug {
-    ($message:expr) => {
+    ($message:expr) => {{
         $crate::log!($crate::LogLevel::Debug, $message);
-    };
+    }};
 
-    ($message:expr, $($arg:tt)*) => {
+    ($message:expr, $($arg:tt)*) => {{
         let message = format!($message, $($arg)*).to_string();
         $crate::log!($crate::LogLevel::Debug, message);
-    };
+    }};
 }
 
 /// Logs an info message.
@@ -284,14 +1450,14 @@ macro_rules! log_debug {
 /// ```
 #[macro_export]
 macro_rules! log_info {
-    ($message:expr) => {
+    ($message:expr) => {{
         $crate::log!($crate::LogLevel::Info, $message);
-    };
+    }};
 
-    ($message:expr, $($arg:tt)*) => {
+    ($message:expr, $($arg:tt)*) => {{
         let message = format!($message, $($arg)*).to_string();
         $crate::log!($crate::LogLevel::Info, message);
-    };
+    }};
 }
 
 /// Logs a warning message.
@@ -302,14 +1468,14 @@ macro_rules! log_info {
 /// ```
 #[macro_export]
 macro_rules! log_warning {
-    ($message:expr) => {
+    ($message:expr) => {{
         $crate::log!($crate::LogLevel::Warning, $message);
-    };
+    }};
 
-    ($message:expr, $($arg:tt)*) => {
+    ($message:expr, $($arg:tt)*) => {{
         let message = format!($message, $($arg)*).to_string();
         $crate::log!($crate::LogLevel::Warning, message);
-    };
+    }};
 }
 
 /// Logs an error message.
@@ -320,14 +1486,36 @@ macro_rules! log_warning {
 /// ```
 #[macro_export]
 macro_rules! log_error {
-    ($message:expr) => {
+    ($message:expr) => {{
         $crate::log!($crate::LogLevel::Error, $message);
-    };
+    }};
 
-    ($message:expr, $($arg:tt)*) => {
+    ($message:expr, $($arg:tt)*) => {{
         let message = format!($message, $($arg)*).to_string();
         $crate::log!($crate::LogLevel::Error, message);
-    };
+    }};
+}
+
+/// Logs a fatal message, then flushes the log file, error file, and sinks (see
+/// [`Logger::flush`]) before returning. If a fatal exit code is set (`WOODY_FATAL_EXIT` or
+/// `Logger::set_fatal_exit`), the process exits with it instead of returning.
+/// # Examples
+/// ```
+/// use woody::log_fatal;
+/// log_fatal!("Hello, world!");
+/// ```
+#[macro_export]
+macro_rules! log_fatal {
+    ($message:expr) => {{
+        $crate::log!($crate::LogLevel::Fatal, $message);
+        $crate::Logger::get_instance().finish_fatal();
+    }};
+
+    ($message:expr, $($arg:tt)*) => {{
+        let message = format!($message, $($arg)*).to_string();
+        $crate::log!($crate::LogLevel::Fatal, message);
+        $crate::Logger::get_instance().finish_fatal();
+    }};
 }
 
 /// Logs a trace message.
@@ -338,14 +1526,14 @@ macro_rules! log_error {
 /// ```
 #[macro_export]
 macro_rules! log_trace {
-    ($message:expr) => {
+    ($message:expr) => {{
         $crate::log!($crate::LogLevel::Trace, $message);
-    };
+    }};
 
-    ($message:expr, $($arg:tt)*) => {
+    ($message:expr, $($arg:tt)*) => {{
         let message = format!($message, $($arg)*).to_string();
         $crate::log!($crate::LogLevel::Trace, message);
-    };
+    }};
 }
 
 /// Logs a text message.
@@ -356,14 +1544,43 @@ macro_rules! log_trace {
 /// ```
 #[macro_export]
 macro_rules! log_text {
-    ($message:expr) => {
+    ($message:expr) => {{
         $crate::log!($crate::LogLevel::Off, $message);
-    };
+    }};
 
-    ($message:expr, $($arg:tt)*) => {
+    ($message:expr, $($arg:tt)*) => {{
         let message = format!($message, $($arg)*).to_string();
         $crate::log!($crate::LogLevel::Off, message);
-    };
+    }};
+}
+
+/// Logs the fields that changed between two values, e.g. after reloading a configuration.
+///
+/// Values only need to implement `Debug`. See `woody::diff` for how fields are compared.
+///
+/// # Examples
+/// ```
+/// use woody::{log_diff, LogLevel};
+/// #[derive(Debug)]
+/// struct Config {
+///     port: u16,
+/// }
+/// let old = Config { port: 8080 };
+/// let new = Config { port: 9090 };
+/// log_diff!(LogLevel::Info, "config reloaded", &old, &new); // config reloaded port="8080 -> 9090"
+/// ```
+#[macro_export]
+macro_rules! log_diff {
+    ($level:expr, $message:expr, $old:expr, $new:expr) => {{
+        let fields = $crate::diff::fields($old, $new);
+        let message = match fields.is_empty() {
+            true => format!("{}: no changes", $message),
+            false => $message.to_string(),
+        };
+        let mut info = $crate::Record::new($level, message, file!(), line!(), module_path!());
+        info.fields = fields;
+        $crate::Logger::get_instance().log(&info);
+    }};
 }
 
 /// Gets the name of the current function.
@@ -381,10 +1598,15 @@ macro_rules! function {
     }};
 }
 
-#[cfg(test)]
+// These check what ends up in the log file.
+#[cfg(all(test, feature = "file"))]
 mod tests {
     use serial_test::serial;
-    use std::io::Read;
+    use std::{
+        fs::OpenOptions,
+        io::Read,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
     use tokio::runtime::Runtime;
 
     use super::*;
@@ -399,16 +1621,17 @@ mod tests {
         };
         let id = id.unwrap_or(0);
         let message = format!("Hello, world! {id}");
-        let info = LogInfo {
+        let info = Record {
             level: LogLevel::Info,
             message,
             filepath: file!(),
             line_number: line!(),
+            module_path: module_path!(),
             thread: Some(thread),
+            fields: Fields::new(),
         };
 
-        let writer: Option<&mut Vec<u8>> = None;
-        logger.log(&info, writer);
+        logger.log(&info);
     }
 
     /// Get the global instance of the Logger (or None if it doesn't exist).
@@ -436,16 +1659,18 @@ mod tests {
     #[test]
     fn test_writing_to_logger() {
         let logger = Logger::get_instance();
-        let info = LogInfo {
+        let info = Record {
             level: LogLevel::Info,
             message: "Hello, world!".to_string(),
             filepath: file!(),
             line_number: line!(),
+            module_path: module_path!(),
             thread: None,
+            fields: Fields::new(),
         };
 
         let mut writer = Vec::new();
-        logger.log(&info, Some(&mut writer));
+        logger.write_to(&info, &mut writer).unwrap();
 
         let mut contents = String::new();
         contents.push_str(&String::from_utf8(writer).unwrap());
@@ -459,13 +1684,13 @@ mod tests {
     fn check_log_file_contains(s: String) {
         // open the file and check that it contains the message
         let logger = Logger::get_instance();
-        let filename = &logger.filename;
-        let file = OpenOptions::new().read(true).open(filename);
-        if file.is_err() {
-            panic!("Could not open {}: {:?}", filename, file.unwrap_err());
-        }
+        let filename = &logger.filename();
+        let mut file = match OpenOptions::new().read(true).open(filename) {
+            Ok(file) => file,
+            Err(e) => panic!("Could not open {filename}: {e:?}"),
+        };
         let mut contents = String::new();
-        file.unwrap().read_to_string(&mut contents).unwrap();
+        file.read_to_string(&mut contents).unwrap();
         assert!(
             contents.contains(s.as_str()),
             "Contents of log does not contain '{s}'\nContents: {contents}\nLogger: {logger:?}"
@@ -490,7 +1715,7 @@ mod tests {
         let rt = Runtime::new().unwrap();
         rt.block_on(spawn_logs());
 
-        let filename = Logger::get_instance().filename;
+        let filename = Logger::get_instance().filename();
         let mut file = OpenOptions::new().read(true).open(&filename).unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
@@ -533,6 +1758,42 @@ mod tests {
         check_log_file_contains(s);
     }
 
+    #[test]
+    fn test_log_fatal() {
+        let f = function!();
+        let s = format!("Hello, {f}!");
+        log_fatal!(s);
+        check_log_file_contains(s);
+    }
+
+    #[test]
+    fn test_log_fatal_flushes_sinks() {
+        struct Flushes(Arc<AtomicUsize>);
+        impl Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let logger = Logger::get_instance();
+        let id = logger.add_sink(Flushes(flushes.clone()));
+        log_fatal!("{} sinks", function!());
+        logger.remove_sink(id);
+        // One flush after the record is written, and one more before returning.
+        assert!(flushes.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn test_fatal_is_above_error() {
+        assert!(LogLevel::Fatal > LogLevel::Error);
+        assert_eq!(LogLevel::parse("FATAL"), LogLevel::Fatal);
+        assert_eq!(LogLevel::Fatal.to_string(), "FATAL");
+    }
+
     #[test]
     fn test_log_trace() {
         let f = function!();
@@ -549,6 +1810,514 @@ mod tests {
         check_log_file_contains(s);
     }
 
+    #[test]
+    fn test_durability_parse() {
+        assert_eq!(Durability::parse("none"), Some(Durability::None));
+        assert_eq!(Durability::parse("Flush"), Some(Durability::Flush));
+        assert_eq!(Durability::parse("fsync"), Some(Durability::Fsync));
+        assert_eq!(
+            Durability::parse("fsync:100"),
+            Some(Durability::FsyncEveryN(100))
+        );
+        assert_eq!(Durability::parse("fsync:lots"), None);
+        assert_eq!(Durability::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn test_record_id() {
+        let mut logger = Logger::get_instance();
+        logger.set_id_generator(Some(|| "record-1".to_string()));
+        let info = Record {
+            level: LogLevel::Info,
+            message: "Hello, world!".to_string(),
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: None,
+            fields: Fields::new(),
+        };
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(
+            contents.contains("[id=record-1] Hello, world!"),
+            "Contents: {contents}"
+        );
+    }
+
+    #[test]
+    fn test_console_echo() {
+        let mut logger = Logger::get_instance();
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        logger.console.captured = Some(captured.clone());
+        logger.set_console(true);
+        let info = Record {
+            level: LogLevel::Warning,
+            message: "Hello, console!".to_string(),
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: None,
+            fields: Fields::new(),
+        };
+
+        logger.log(&info);
+        let contents = String::from_utf8(captured.lock().unwrap().clone()).unwrap();
+        assert!(contents.contains("[WARNING]"), "Contents: {contents}");
+        assert!(contents.contains("Hello, console!"), "Contents: {contents}");
+    }
+
+    #[test]
+    fn test_log_diff() {
+        let f = function!();
+        let old = vec![f, "old"];
+        let new = vec![f, "new"];
+        log_diff!(LogLevel::Info, f, &old, &new);
+        check_log_file_contains(format!("{f} value=\"[1]: \\\"old\\\" -> \\\"new\\\"\""));
+    }
+
+    #[test]
+    fn test_source_filter() {
+        let mut logger = Logger::get_instance();
+        logger.set_source_filter(SourceFilter::new().deny_crate("woody"));
+        let info = Record {
+            level: LogLevel::Error,
+            message: "Hello, world!".to_string(),
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: None,
+            fields: Fields::new(),
+        };
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        assert!(writer.is_empty());
+
+        logger.set_source_filter(SourceFilter::new().allow_path(file!()));
+        logger.write_to(&info, &mut writer).unwrap();
+        assert!(!writer.is_empty());
+    }
+
+    /// Check that a hook which logs doesn't deadlock or recurse forever.
+    #[test]
+    fn test_reentrant_hook() {
+        let mut logger = Logger::get_instance();
+        logger.set_id_generator(Some(|| {
+            log_warning!("generating an id");
+            "reentrant-1".to_string()
+        }));
+        let info = Record {
+            level: LogLevel::Info,
+            message: "Hello, world!".to_string(),
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: None,
+            fields: Fields::new(),
+        };
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(
+            contents.contains("[id=reentrant-1] Hello, world!"),
+            "Contents: {contents}"
+        );
+    }
+
+    /// Check that non-ASCII `file!()` paths and messages survive formatting and filtering.
+    #[test]
+    fn test_non_ascii_paths() {
+        let mut logger = Logger::get_instance();
+        logger.set_source_filter(SourceFilter::new().allow_path("src/módulo/"));
+        let info = Record {
+            level: LogLevel::Info,
+            message: "héllo, 世界 🌲".to_string(),
+            filepath: "src/módulo/日本語.rs",
+            line_number: 7,
+            module_path: module_path!(),
+            thread: Some("wörker".to_string()),
+            fields: Fields::new(),
+        };
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(
+            contents.contains("[wörker] [src/módulo/日本語.rs:7] héllo, 世界 🌲\n"),
+            "Contents: {contents}"
+        );
+    }
+
+    /// Check that structured fields are written after the message.
+    #[test]
+    fn test_fields() {
+        let info = Record::new(LogLevel::Info, "request handled", file!(), line!(), "")
+            .with_field("status", 200)
+            .with_field("path", "/users/42");
+        let mut writer = Vec::new();
+        Logger::get_instance().write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(
+            contents.ends_with("request handled status=200 path=/users/42\n"),
+            "Contents: {contents}"
+        );
+
+        log!(LogLevel::Info, "fields-macro"; user = "ada", admin = true);
+        check_log_file_contains("fields-macro user=ada admin=true".to_string());
+    }
+
+    /// Check that the macros work as expressions, e.g. in match arms and without a `;`.
+    #[test]
+    fn test_macros_as_expressions() {
+        for attempt in 1..=2 {
+            match attempt {
+                1 => log_warning!("expression-arm {}", attempt),
+                _ => log!(LogLevel::Warning, "expression-arm"; attempt = attempt),
+            }
+        }
+        let cached = true;
+        if cached {
+            log_info!("expression-if")
+        } else {
+            log_error!("expression-else")
+        }
+        check_log_file_contains("expression-arm 1".to_string());
+        check_log_file_contains("expression-arm attempt=2".to_string());
+        check_log_file_contains("expression-if".to_string());
+    }
+
+    /// Check that redacted fields don't reach the output.
+    #[test]
+    fn test_redacted_fields() {
+        let mut logger = Logger::get_instance();
+        logger.set_redacted_fields(&["password"], RedactMode::Mask);
+        let info = Record::new(LogLevel::Info, "login", file!(), line!(), "")
+            .with_field("user", "ada")
+            .with_field("password", "hunter2");
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(
+            contents.ends_with("login user=ada password=***\n"),
+            "Contents: {contents}"
+        );
+    }
+
+    /// Check that a message can't forge a second record.
+    #[test]
+    fn test_log_injection() {
+        let logger = Logger::get_instance();
+        let info = Record {
+            level: LogLevel::Error,
+            message: "bad input\n[2024-01-01] [INFO] [main] [src/lib.rs:1] all good".to_string(),
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: Some("main] [spoofed".to_string()),
+            fields: Fields::new(),
+        };
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert_eq!(contents.lines().count(), 1, "Contents: {contents}");
+        assert!(
+            contents.contains("[main\\] [spoofed] ") && contents.contains("bad input\\n[2024"),
+            "Contents: {contents}"
+        );
+    }
+
+    /// Check that records below the level are written before the next error.
+    #[test]
+    fn test_flight_recorder() {
+        let mut logger = Logger::get_instance();
+        logger.set_level(LogLevel::Error);
+        logger.set_flight_recorder(2);
+        for step in ["fr-step-1", "fr-step-2", "fr-step-3"] {
+            log!(logger: logger, LogLevel::Debug, step);
+        }
+        let contents = std::fs::read_to_string(logger.filename()).unwrap();
+        assert!(!contents.contains("fr-step-3"), "Contents: {contents}");
+        log!(logger: logger, LogLevel::Error, "fr-failed");
+
+        let contents = std::fs::read_to_string(logger.filename()).unwrap();
+        let dump = contents
+            .find("2 earlier records below the log level")
+            .unwrap();
+        let (step2, step3) = (
+            contents.find("fr-step-2").unwrap(),
+            contents.find("fr-step-3").unwrap(),
+        );
+        assert!(dump < step2 && step2 < step3 && step3 < contents.find("fr-failed").unwrap());
+        assert!(!contents.contains("fr-step-1"), "Contents: {contents}");
+
+        log!(logger: logger, LogLevel::Trace, "fr-after");
+        logger.dump_flight_recorder();
+        check_log_file_contains("fr-after".to_string());
+    }
+
+    /// Check that a level window replaces the root level while it's open.
+    #[test]
+    fn test_level_schedule() {
+        let mut logger = Logger::get_instance();
+        logger.set_level(LogLevel::Error);
+        logger.set_named_level("quiet", LogLevel::Off);
+        let now = chrono::Local::now().time();
+        let minute = chrono::Duration::minutes(1);
+        logger.set_level_schedule(vec![LevelWindow::new(
+            now - minute,
+            now + minute,
+            LogLevel::Trace,
+        )]);
+        assert_eq!(logger.effective_level(), LogLevel::Trace);
+        assert_eq!(logger.named("quiet").effective_level(), LogLevel::Off);
+
+        logger.set_level_schedule(vec![LevelWindow::new(
+            now + minute,
+            now + minute * 2,
+            LogLevel::Trace,
+        )]);
+        assert_eq!(logger.effective_level(), LogLevel::Error);
+    }
+
+    #[test]
+    fn test_level_inheritance() {
+        let mut root = Logger::get_instance();
+        root.set_level(LogLevel::Warning);
+        root.set_named_level("app", LogLevel::Error);
+        root.set_named_level("app.db", LogLevel::Trace);
+
+        assert_eq!(root.effective_level(), LogLevel::Warning);
+        assert_eq!(root.named("other").effective_level(), LogLevel::Warning);
+        assert_eq!(root.named("app.http").effective_level(), LogLevel::Error);
+        let pool = root.named("app.db").child("pool");
+        assert_eq!(pool.name.as_deref(), Some("app.db.pool"));
+        assert_eq!(pool.effective_level(), LogLevel::Trace);
+
+        let info = Record {
+            level: LogLevel::Debug,
+            message: "checked out".to_string(),
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: None,
+            fields: Fields::new(),
+        };
+        let mut writer = Vec::new();
+        root.named("app.http").write_to(&info, &mut writer).unwrap();
+        assert!(writer.is_empty());
+        pool.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(
+            contents.contains("[app.db.pool] checked out"),
+            "Contents: {contents}"
+        );
+    }
+
+    #[test]
+    fn test_stopwatch() {
+        let f = function!();
+        let sw = stopwatch(f);
+        sw.lap("parsed");
+        sw.finish();
+        check_log_file_contains(format!("{f}: parsed lap_ms="));
+        check_log_file_contains(format!("{f}: finished elapsed_ms="));
+    }
+
+    #[test]
+    fn test_crlf_line_ending() {
+        let mut logger = Logger::get_instance();
+        logger.set_line_ending(LineEnding::CrLf);
+        let info = Record {
+            level: LogLevel::Info,
+            message: "Hello, world!".to_string(),
+            filepath: file!(),
+            line_number: line!(),
+            module_path: module_path!(),
+            thread: None,
+            fields: Fields::new(),
+        };
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(contents.ends_with("Hello, world!\r\n"), "{contents:?}");
+    }
+
+    #[test]
+    #[cfg(feature = "file")]
+    fn test_parse_encoding() {
+        assert_eq!(Encoding::parse("utf-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::parse("UTF8_BOM"), Some(Encoding::Utf8Bom));
+        assert_eq!(Encoding::parse("latin1"), None);
+    }
+
+    #[test]
+    fn test_json_pretty_format() {
+        let mut logger = Logger::get_instance();
+        logger.set_format(Format::JsonPretty);
+        let info = Record::new(LogLevel::Warning, "low \"disk\"", "src/a.rs", 7, "app")
+            .with_field("free_mb", 12);
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(contents.starts_with("{\n  \"time\": \""), "{contents}");
+        assert!(contents.contains("\n  \"level\": \"WARN"), "{contents}");
+        assert!(contents.contains("\n  \"location\": \"src/a.rs:7\",\n"));
+        assert!(contents.contains("\n  \"message\": \"low \\\"disk\\\"\",\n"));
+        assert!(contents.ends_with("\"fields\": {\n    \"free_mb\": 12\n  }\n}\n"));
+        assert_eq!(json::compact(&contents).lines().count(), 1);
+        assert_eq!(Format::parse("JSON-pretty"), Some(Format::JsonPretty));
+    }
+
+    #[test]
+    fn test_json_stays_valid_with_sequence_and_timing() {
+        for format in [Format::Json, Format::JsonPretty] {
+            let path = std::env::temp_dir().join(format!("woody-json-seq-{format:?}.log"));
+            let file = File::create(&path).unwrap();
+            let mut logger = Logger::get_instance();
+            logger.file = Writer::sync(writer::LogFile::new(file, None).numbered());
+            logger.set_format(format);
+            logger.set_console(false);
+            logger.set_level(LogLevel::Info);
+            logger.timing = Some(timing::Timing {
+                elapsed: true,
+                delta: true,
+            });
+            logger.log(&Record::new(LogLevel::Info, "one", file!(), line!(), ""));
+            logger.log_batch(&[
+                Record::new(LogLevel::Info, "two\nlines", file!(), line!(), "").with_field("n", 2),
+                Record::new(LogLevel::Error, "three }", file!(), line!(), ""),
+            ]);
+            logger.flush();
+
+            let contents = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let contents = json::compact(&contents);
+            let lines: Vec<&str> = contents.lines().collect();
+            assert_eq!(lines.len(), 3, "{contents}");
+            for (seq, line) in lines.iter().enumerate() {
+                let (json::Json::Object(members), "") = json::parse(line).unwrap() else {
+                    panic!("not one JSON object: {line}");
+                };
+                let keys: Vec<&str> = members.iter().map(|(key, _)| key.as_str()).collect();
+                assert!(keys.contains(&"elapsed_ms"), "{line}");
+                assert!(seq == 0 || keys.contains(&"delta_ms"), "{line}");
+                assert!(line.ends_with(&format!(",\"seq\":{seq}}}")), "{line}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_json_id_is_a_key() {
+        let mut logger = Logger::get_instance();
+        logger.set_format(Format::Json);
+        logger.set_id_generator(Some(Snowflake::new(3)));
+        let info = Record::new(LogLevel::Error, "failed", "src/a.rs", 7, "app");
+
+        let mut writer = Vec::new();
+        logger.write_to(&info, &mut writer).unwrap();
+        let contents = String::from_utf8(writer).unwrap();
+        assert!(
+            contents.contains("\"location\":\"src/a.rs:7\",\"id\":\""),
+            "{contents}"
+        );
+        assert!(!contents.contains("[id="), "{contents}");
+    }
+
+    #[test]
+    fn test_log_batch() {
+        let f = function!();
+        let mut logger = Logger::get_instance();
+        logger.set_level(LogLevel::Info);
+        let infos: Vec<Record> = [LogLevel::Info, LogLevel::Trace, LogLevel::Error]
+            .into_iter()
+            .map(|level| Record {
+                level,
+                message: format!("{f} {level}"),
+                filepath: file!(),
+                line_number: line!(),
+                module_path: module_path!(),
+                thread: None,
+                fields: Fields::new(),
+            })
+            .collect();
+
+        logger.log_batch(&infos);
+        let contents = std::fs::read_to_string(logger.filename()).unwrap();
+        let batch: Vec<&str> = contents.lines().filter(|l| l.contains(f)).collect();
+        assert_eq!(batch.len(), 2, "Contents: {contents}");
+        assert!(batch[0].ends_with(&format!("{f} INFO")));
+        assert!(batch[1].ends_with(&format!("{f} ERROR")));
+    }
+
+    #[test]
+    fn test_record_group() {
+        let f = function!();
+        let mut logger = Logger::get_instance();
+        logger.set_level(LogLevel::Info);
+
+        let ok = logger.group();
+        log!(logger: ok, LogLevel::Debug, format!("{f} ok debug"));
+        log!(logger: ok, LogLevel::Warning, format!("{f} ok warning"));
+        let ok_id = ok.id().to_string();
+        ok.success();
+
+        let failed = logger.group();
+        log!(logger: failed, LogLevel::Debug, format!("{f} failed debug"));
+        let failed_id = failed.id().to_string();
+        drop(failed);
+
+        let contents = std::fs::read_to_string(logger.filename()).unwrap();
+        assert!(!contents.contains(&format!("{f} ok debug")));
+        assert!(contents.contains(&format!("[group={ok_id}] {f} ok warning")));
+        assert!(contents.contains(&format!("[group={failed_id}] {f} failed debug")));
+    }
+
+    #[test]
+    fn test_record_group_keeps_time_and_thread() {
+        let f = function!();
+        let group = Logger::get_instance().group();
+        let logged = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .name(format!("{f}-worker"))
+                .spawn_scoped(scope, || {
+                    log!(logger: group, LogLevel::Info, format!("{f} buffered"));
+                    chrono::Local::now()
+                })
+                .unwrap()
+                .join()
+                .unwrap()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        group.failure();
+
+        let contents = std::fs::read_to_string(Logger::get_instance().filename()).unwrap();
+        let line = contents
+            .lines()
+            .find(|line| line.ends_with(&format!("{f} buffered")))
+            .unwrap();
+        assert!(line.contains(&format!("[{f}-worker]")), "{line}");
+        let time = chrono::NaiveDateTime::parse_from_str(&line[1..24], "%Y-%m-%d %H:%M:%S%.3f");
+        assert!(time.unwrap() <= logged.naive_local(), "{line}");
+    }
+
+    #[test]
+    fn test_record_group_keeps_context() {
+        let f = function!();
+        let group = Logger::get_instance().group();
+        {
+            let _context = context::Context::current().with("request_id", 42).attach();
+            log!(logger: group, LogLevel::Info, format!("{f} buffered"));
+        }
+        group.failure();
+        check_log_file_contains(format!("[request_id=42] {f} buffered"));
+    }
+
     #[test]
     fn test_random_file_name() {
         let filename = generate_temp_file_name();
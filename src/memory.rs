@@ -0,0 +1,115 @@
+//! A cap on the memory woody holds records in, for containers with tight limits. Set
+//! `WOODY_MEMORY_LIMIT` to a number of bytes to cap the records queued for the writer thread,
+//! kept by the flight recorder, and buffered by record groups and streams.
+//!
+//! Logging never blocks or allocates past the cap. When a record doesn't fit, the flight
+//! recorder forgets its oldest records to make room, the writer queue and groups drop the new
+//! record (counted as dropped), and streams drop the new output. Fixed-size buffers, like the log file's, aren't counted, and
+//! subscribers are bounded by a number of records instead.
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    OnceLock,
+};
+
+/// Bytes in use, and the cap on them.
+#[derive(Debug)]
+struct Budget {
+    limit: Option<usize>,
+    used: AtomicUsize,
+}
+
+static BUDGET: OnceLock<Budget> = OnceLock::new();
+
+fn budget() -> &'static Budget {
+    BUDGET.get_or_init(|| Budget {
+        limit: crate::config::setting("WOODY_MEMORY_LIMIT").and_then(|x| x.trim().parse().ok()),
+        used: AtomicUsize::new(0),
+    })
+}
+
+/// The bytes of records woody is holding in memory right now, counted against
+/// `WOODY_MEMORY_LIMIT` whether or not it's set.
+pub fn memory_used() -> usize {
+    budget().used.load(Ordering::Relaxed)
+}
+
+/// Bytes counted against the cap until dropped.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    budget: &'static Budget,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Counts `bytes` more against the cap, or returns `false` if they don't fit.
+    pub(crate) fn grow(&mut self, bytes: usize) -> bool {
+        match reserve_in(self.budget, bytes) {
+            Some(mut more) => {
+                self.bytes += std::mem::take(&mut more.bytes);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Counts `bytes` against the cap, or returns `None` if they don't fit.
+pub(crate) fn reserve(bytes: usize) -> Option<Reservation> {
+    reserve_in(budget(), bytes)
+}
+
+fn reserve_in(budget: &'static Budget, bytes: usize) -> Option<Reservation> {
+    let fits = |used: usize| {
+        let total = used.checked_add(bytes)?;
+        budget
+            .limit
+            .is_none_or(|limit| total <= limit)
+            .then_some(total)
+    };
+    budget
+        .used
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, fits)
+        .ok()?;
+    Some(Reservation { budget, bytes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservations_respect_the_limit() {
+        let budget = Box::leak(Box::new(Budget {
+            limit: Some(10),
+            used: AtomicUsize::new(0),
+        }));
+        let first = reserve_in(budget, 6).unwrap();
+        assert!(reserve_in(budget, 5).is_none());
+        let second = reserve_in(budget, 4).unwrap();
+        assert_eq!(budget.used.load(Ordering::Relaxed), 10);
+        drop(first);
+        drop(second);
+        assert!(reserve_in(budget, 10).is_some());
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_grow() {
+        let budget = Box::leak(Box::new(Budget {
+            limit: Some(10),
+            used: AtomicUsize::new(0),
+        }));
+        let mut reservation = reserve_in(budget, 4).unwrap();
+        assert!(reservation.grow(6));
+        assert!(!reservation.grow(1));
+        assert_eq!(budget.used.load(Ordering::Relaxed), 10);
+        drop(reservation);
+        assert_eq!(budget.used.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,399 @@
+//! Counters describing the health of the logger, rendered in the Prometheus text format so they
+//! can be served from an existing scrape endpoint, and timers summarized into the log.
+use std::{
+    collections::BTreeMap,
+    panic::Location,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Once, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{LogLevel, Logger, Record};
+
+/// The built-in levels records can be logged at, in the order they're rendered, with their
+/// labels. The labels are fixed, so renaming a level doesn't start a new series.
+const LEVELS: [(LogLevel, &str); 7] = [
+    (LogLevel::Fatal, "fatal"),
+    (LogLevel::Error, "error"),
+    (LogLevel::Warning, "warning"),
+    (LogLevel::Debug, "debug"),
+    (LogLevel::Info, "info"),
+    (LogLevel::Trace, "trace"),
+    (LogLevel::Off, "off"),
+];
+
+static RECORDS: [AtomicU64; 7] = [const { AtomicU64::new(0) }; 7];
+/// Records logged at custom levels, labelled by the custom level's name.
+static CUSTOM: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static SINK_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a record that passed the filters and is about to be written.
+pub(crate) fn record(level: LogLevel) {
+    if let LogLevel::Custom { name, .. } = level {
+        let mut custom = CUSTOM.lock().unwrap_or_else(|e| e.into_inner());
+        *custom.entry(name).or_default() += 1;
+    } else if let Some(i) = LEVELS.iter().position(|(l, _)| *l == level) {
+        RECORDS[i].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a record that was accepted but never made it to the file.
+pub(crate) fn dropped() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a failed write or flush.
+pub(crate) fn sink_error() {
+    SINK_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The number of records logged at `level` so far.
+pub fn records_total(level: LogLevel) -> u64 {
+    if let LogLevel::Custom { name, .. } = level {
+        let custom = CUSTOM.lock().unwrap_or_else(|e| e.into_inner());
+        return custom.get(name).copied().unwrap_or(0);
+    }
+    LEVELS
+        .iter()
+        .position(|(l, _)| *l == level)
+        .map_or(0, |i| RECORDS[i].load(Ordering::Relaxed))
+}
+
+/// The number of records that were accepted but lost before reaching the file.
+pub fn dropped_total() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// The number of failed writes and flushes.
+pub fn sink_errors_total() -> u64 {
+    SINK_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Renders all counters in the Prometheus text exposition format.
+///
+/// # Examples
+/// ```
+/// let body = woody::metrics::render_prometheus();
+/// assert!(body.contains("woody_records_total{level=\"error\"}"));
+/// ```
+pub fn render_prometheus() -> String {
+    let mut out = String::new();
+    out.push_str("# HELP woody_records_total Records logged, by level.\n");
+    out.push_str("# TYPE woody_records_total counter\n");
+    for (level, name) in LEVELS {
+        let count = records_total(level);
+        out.push_str(&format!(
+            "woody_records_total{{level=\"{name}\"}} {count}\n"
+        ));
+    }
+    let custom = CUSTOM.lock().unwrap_or_else(|e| e.into_inner());
+    for (name, count) in custom.iter() {
+        let name = label(name);
+        out.push_str(&format!(
+            "woody_records_total{{level=\"{name}\"}} {count}\n"
+        ));
+    }
+    drop(custom);
+    out.push_str("# HELP woody_dropped_total Records lost before reaching the file.\n");
+    out.push_str("# TYPE woody_dropped_total counter\n");
+    out.push_str(&format!("woody_dropped_total {}\n", dropped_total()));
+    out.push_str("# HELP woody_sink_errors_total Failed writes and flushes.\n");
+    out.push_str("# TYPE woody_sink_errors_total counter\n");
+    out.push_str(&format!(
+        "woody_sink_errors_total {}\n",
+        sink_errors_total()
+    ));
+    out
+}
+
+/// Escapes a label value as the exposition format requires.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Sub-buckets per power of two in a timer's histogram, so percentiles are within 12.5%.
+const SUB_BUCKETS: u32 = 8;
+const BUCKETS: usize = 64 * SUB_BUCKETS as usize + 1;
+
+static TIMERS: OnceLock<Mutex<BTreeMap<String, Timer>>> = OnceLock::new();
+
+/// How often a timer logs its summary: `WOODY_TIMER_INTERVAL` seconds, or a minute.
+fn timer_interval() -> Duration {
+    static INTERVAL: OnceLock<Duration> = OnceLock::new();
+    *INTERVAL.get_or_init(|| {
+        let secs = crate::config::setting("WOODY_TIMER_INTERVAL").and_then(|x| x.parse().ok());
+        Duration::from_secs(secs.unwrap_or(60))
+    })
+}
+
+/// The timer named `name`, created on first use. Every call with the same name shares one.
+///
+/// # Examples
+/// ```
+/// let start = std::time::Instant::now();
+/// // ... run the query ...
+/// woody::metrics::timer("db_query").record(start.elapsed());
+/// ```
+pub fn timer(name: &str) -> Timer {
+    static TICKER: Once = Once::new();
+    TICKER.call_once(start_ticker);
+    let mut timers = TIMERS
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    timers
+        .entry(name.to_string())
+        .or_insert_with(|| Timer(Arc::new((name.to_string(), Mutex::new(Histogram::new())))))
+        .clone()
+}
+
+/// Aggregates durations, like query times, into a histogram instead of logging each one. Once
+/// `WOODY_TIMER_INTERVAL` has passed, an `INFO` summary with the count and the 50th, 95th, and
+/// 99th percentiles is logged, and the histogram starts over. The summary is logged by the next
+/// sample or by a background thread, whichever comes first, so a timer that stops getting
+/// samples still reports its last window. `Logger::flush` logs every timer's summary early:
+///
+/// ```text
+/// [...] [INFO] [main] [src/db.rs:40] db_query timings timer=db_query count=1200 p50=1.8ms p95=7.5ms p99=15ms max=22.1ms
+/// ```
+#[derive(Clone, Debug)]
+pub struct Timer(Arc<(String, Mutex<Histogram>)>);
+
+impl Timer {
+    /// Adds a sample, logging the summary if the interval is up.
+    #[track_caller]
+    pub fn record(&self, duration: Duration) {
+        let location = Location::caller();
+        self.summarize_if(Some(location), |histogram| {
+            histogram.record(duration, location);
+            histogram.since.elapsed() >= timer_interval()
+        });
+    }
+
+    /// Logs the summary now and starts over, e.g. before exiting. Does nothing without samples.
+    #[track_caller]
+    pub fn summarize(&self) {
+        self.summarize_if(Some(Location::caller()), |_| true);
+    }
+
+    /// Logs the summary and starts over if `due` says so and there are samples. The summary
+    /// points at `location`, or at the last sample without one.
+    fn summarize_if(
+        &self,
+        location: Option<&'static Location<'static>>,
+        due: impl FnOnce(&mut Histogram) -> bool,
+    ) {
+        let (name, histogram) = &*self.0;
+        let summary = {
+            let mut histogram = histogram.lock().unwrap_or_else(|e| e.into_inner());
+            if !due(&mut histogram) || histogram.count == 0 {
+                return;
+            }
+            std::mem::replace(&mut *histogram, Histogram::new())
+        };
+        log_summary(name, &summary, location.or(summary.location));
+    }
+}
+
+/// Every timer created so far.
+fn all_timers() -> Vec<Timer> {
+    let Some(timers) = TIMERS.get() else {
+        return Vec::new();
+    };
+    let timers = timers.lock().unwrap_or_else(|e| e.into_inner());
+    timers.values().cloned().collect()
+}
+
+/// Logs the summary of every timer with samples, for `Logger::flush`.
+pub(crate) fn summarize_timers() {
+    for timer in all_timers() {
+        timer.summarize_if(None, |_| true);
+    }
+}
+
+/// Logs the summary of every timer whose interval is up.
+fn summarize_due() {
+    for timer in all_timers() {
+        timer.summarize_if(None, |histogram| {
+            histogram.since.elapsed() >= timer_interval()
+        });
+    }
+}
+
+/// Starts the thread that logs the summaries of timers whose interval is up. It checks every
+/// second, or every interval if that's shorter.
+fn start_ticker() {
+    let spawned = thread::Builder::new()
+        .name("woody-timers".to_string())
+        .spawn(|| loop {
+            thread::sleep(timer_interval().min(Duration::from_secs(1)));
+            summarize_due();
+        });
+    if let Err(e) = spawned {
+        crate::internal::event(LogLevel::Warning, || {
+            format!("couldn't start the timer thread, so idle timers won't report: {e}")
+        });
+    }
+}
+
+fn log_summary(name: &str, histogram: &Histogram, location: Option<&'static Location<'static>>) {
+    let (file, line) = location.map_or(("", 0), |location| (location.file(), location.line()));
+    let record = Record::new(LogLevel::Info, format!("{name} timings"), file, line, "")
+        .with_field("timer", name.to_string())
+        .with_field("count", histogram.count)
+        .with_field("p50", format!("{:?}", histogram.percentile(50)))
+        .with_field("p95", format!("{:?}", histogram.percentile(95)))
+        .with_field("p99", format!("{:?}", histogram.percentile(99)))
+        .with_field("max", format!("{:?}", histogram.max));
+    Logger::get_instance().log(&record);
+}
+
+/// Counts of durations in buckets that grow exponentially, with `SUB_BUCKETS` per power of
+/// two of nanoseconds.
+#[derive(Debug)]
+struct Histogram {
+    buckets: Box<[u64; BUCKETS]>,
+    count: u64,
+    max: Duration,
+    /// When the histogram started, to tell when to summarize it.
+    since: Instant,
+    /// Where the last sample was recorded, for a summary logged by the ticker or a flush.
+    location: Option<&'static Location<'static>>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: Box::new([0; BUCKETS]),
+            count: 0,
+            max: Duration::ZERO,
+            since: Instant::now(),
+            location: None,
+        }
+    }
+
+    fn record(&mut self, duration: Duration, location: &'static Location<'static>) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[bucket(nanos)] += 1;
+        self.count += 1;
+        self.max = self.max.max(duration);
+        self.location = Some(location);
+    }
+
+    /// The upper bound of the bucket holding the `p`th percentile, at most the maximum.
+    fn percentile(&self, p: u64) -> Duration {
+        let rank = (self.count * p).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(upper_bound(i)).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// The bucket for `nanos`: 0 for zero, then `SUB_BUCKETS` per power of two.
+fn bucket(nanos: u64) -> usize {
+    if nanos == 0 {
+        return 0;
+    }
+    let log = 63 - nanos.leading_zeros();
+    let shift = log.saturating_sub(SUB_BUCKETS.trailing_zeros());
+    let sub = (nanos >> shift) as u32 % SUB_BUCKETS;
+    (log * SUB_BUCKETS + sub) as usize + 1
+}
+
+/// The largest number of nanoseconds in bucket `i`.
+fn upper_bound(i: usize) -> u64 {
+    if i == 0 {
+        return 0;
+    }
+    let (log, sub) = ((i - 1) as u32 / SUB_BUCKETS, (i - 1) as u32 % SUB_BUCKETS);
+    let shift = log.saturating_sub(SUB_BUCKETS.trailing_zeros());
+    let low = (1u64 << log) | (u64::from(sub) << shift);
+    low.saturating_add((1u64 << shift) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Value;
+
+    #[test]
+    fn test_render_prometheus() {
+        let before = records_total(LogLevel::Trace);
+        record(LogLevel::Trace);
+        assert!(records_total(LogLevel::Trace) > before);
+
+        let body = render_prometheus();
+        assert!(body.contains("# TYPE woody_records_total counter\n"));
+        assert!(body.contains("woody_records_total{level=\"trace\"} "));
+        assert!(body.contains("woody_dropped_total "));
+        assert!(body.contains("woody_sink_errors_total "));
+    }
+
+    #[test]
+    fn test_custom_levels_get_their_own_series() {
+        let audit = LogLevel::custom("AUDIT", 45);
+        let warnings = records_total(LogLevel::Warning);
+        record(audit);
+        record(audit);
+        assert_eq!(records_total(audit), 2);
+        assert_eq!(records_total(LogLevel::Warning), warnings);
+        assert!(render_prometheus().contains("woody_records_total{level=\"AUDIT\"} 2\n"));
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = Histogram::new();
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms), Location::caller());
+        }
+        let within = |p: u64, expected: u64| {
+            let actual = histogram.percentile(p).as_secs_f64() * 1000.0;
+            assert!(
+                (expected as f64..=expected as f64 * 1.125).contains(&actual),
+                "p{p} = {actual}ms"
+            );
+        };
+        within(50, 50);
+        within(95, 95);
+        within(99, 99);
+        assert_eq!(histogram.percentile(100), Duration::from_millis(100));
+        assert_eq!(upper_bound(bucket(5)), 5);
+        assert_eq!(bucket(0), 0);
+    }
+
+    #[test]
+    fn test_idle_timer_reports_its_last_window() {
+        let records = crate::subscribe();
+        let summaries = |name: &'static str| {
+            std::iter::from_fn(|| records.recv_timeout(Duration::from_millis(100)).ok())
+                .filter(|record| record.fields.get("timer") == Some(&name.to_string().into()))
+                .collect::<Vec<_>>()
+        };
+
+        let idle = timer("test_idle");
+        idle.record(Duration::from_millis(3));
+        idle.0 .1.lock().unwrap().since -= timer_interval();
+        summarize_due();
+        let logged = summaries("test_idle");
+        assert_eq!(logged.len(), 1);
+        assert_eq!(logged[0].fields.get("count"), Some(&Value::U64(1)));
+        assert_eq!(logged[0].filepath, file!());
+
+        idle.record(Duration::from_millis(3));
+        summarize_due();
+        crate::Logger::get_instance().flush();
+        assert_eq!(summaries("test_idle").len(), 1);
+    }
+}
@@ -0,0 +1,322 @@
+//! The `msgpack` format for the log file: every record a MessagePack map with the same keys as
+//! the JSON format, prefixed with its length as a big-endian `u32`. Appending a record never
+//! touches the ones before it, and a reader can skip a record without decoding it.
+use std::io;
+
+use crate::{
+    escape,
+    fields::Value,
+    json::{self, Json},
+};
+
+/// Converts lines of compact JSON records into length-prefixed MessagePack maps. Text after a
+/// record's object, like the timing fields, is kept under `extra`, and a line that isn't JSON
+/// at all (like the flight recorder's header) becomes a record with only a `message`.
+pub(crate) fn encode_lines(output: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(output.len());
+    for line in output.lines() {
+        let entries = match json::parse(line) {
+            Some((Json::Object(mut entries), rest)) => {
+                if !rest.trim().is_empty() {
+                    let extra = Json::Scalar(Value::from(rest.trim().to_string()));
+                    entries.push(("extra".to_string(), extra));
+                }
+                entries
+            }
+            _ => vec![(
+                "message".to_string(),
+                Json::Scalar(Value::from(line.to_string())),
+            )],
+        };
+        let mut frame = Vec::with_capacity(line.len());
+        encode(&mut frame, &Json::Object(entries));
+        out.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        out.extend_from_slice(&frame);
+    }
+    out
+}
+
+/// Adds `key` as the last entry of every length-prefixed map in `frames`, with the number
+/// `value` returns for it, called once per map in order. Anything else is kept as it is.
+pub(crate) fn append_key(frames: &[u8], key: &str, mut value: impl FnMut() -> u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(frames.len() + frames.len() / 8);
+    let mut rest = frames;
+    while let Some((len, after)) = rest.split_first_chunk::<4>() {
+        let len = u32::from_be_bytes(*len) as usize;
+        let Some(frame) = after.get(..len) else {
+            break;
+        };
+        rest = &after[len..];
+        let (entries, skip) = match frame {
+            [n @ 0x80..=0x8f, ..] => (usize::from(n & 0x0f), 1),
+            [0xde, a, b, ..] => (usize::from(u16::from_be_bytes([*a, *b])), 3),
+            [0xdf, a, b, c, d, ..] => (u32::from_be_bytes([*a, *b, *c, *d]) as usize, 5),
+            _ => {
+                out.extend_from_slice(&(len as u32).to_be_bytes());
+                out.extend_from_slice(frame);
+                continue;
+            }
+        };
+        let mut map = Vec::with_capacity(frame.len() + key.len() + 12);
+        header(&mut map, entries + 1, 0x80, 0xde);
+        map.extend_from_slice(&frame[skip..]);
+        encode(&mut map, &Json::Scalar(Value::from(key.to_string())));
+        encode_uint(&mut map, value());
+        out.extend_from_slice(&(map.len() as u32).to_be_bytes());
+        out.extend_from_slice(&map);
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+fn encode(out: &mut Vec<u8>, value: &Json) {
+    match value {
+        Json::Null => out.push(0xc0),
+        Json::Scalar(Value::Bool(x)) => out.push(if *x { 0xc3 } else { 0xc2 }),
+        Json::Scalar(Value::U64(x)) => encode_uint(out, *x),
+        Json::Scalar(Value::I64(x)) if *x >= 0 => encode_uint(out, *x as u64),
+        Json::Scalar(Value::I64(x)) => match *x {
+            -32..=-1 => out.push(*x as u8),
+            x if x >= i64::from(i8::MIN) => out.extend([0xd0, x as u8]),
+            x if x >= i64::from(i16::MIN) => extend(out, 0xd1, &(x as i16).to_be_bytes()),
+            x if x >= i64::from(i32::MIN) => extend(out, 0xd2, &(x as i32).to_be_bytes()),
+            x => extend(out, 0xd3, &x.to_be_bytes()),
+        },
+        Json::Scalar(Value::F64(x)) => extend(out, 0xcb, &x.to_be_bytes()),
+        Json::Scalar(Value::Str(s)) => {
+            match s.len() {
+                len @ 0..=31 => out.push(0xa0 | len as u8),
+                len @ 32..=0xff => out.extend([0xd9, len as u8]),
+                len @ 0x100..=0xffff => extend(out, 0xda, &(len as u16).to_be_bytes()),
+                len => extend(out, 0xdb, &(len as u32).to_be_bytes()),
+            }
+            out.extend_from_slice(s.as_bytes());
+        }
+        Json::Array(items) => {
+            header(out, items.len(), 0x90, 0xdc);
+            items.iter().for_each(|item| encode(out, item));
+        }
+        Json::Object(entries) => {
+            header(out, entries.len(), 0x80, 0xde);
+            for (key, value) in entries {
+                encode(out, &Json::Scalar(Value::from(key.clone())));
+                encode(out, value);
+            }
+        }
+    }
+}
+
+fn encode_uint(out: &mut Vec<u8>, x: u64) {
+    match x {
+        0..=0x7f => out.push(x as u8),
+        0x80..=0xff => out.extend([0xcc, x as u8]),
+        0x100..=0xffff => extend(out, 0xcd, &(x as u16).to_be_bytes()),
+        0x1_0000..=0xffff_ffff => extend(out, 0xce, &(x as u32).to_be_bytes()),
+        x => extend(out, 0xcf, &x.to_be_bytes()),
+    }
+}
+
+/// The header of an array or map of `len` items: the fix form, or the 16- or 32-bit one.
+fn header(out: &mut Vec<u8>, len: usize, fix: u8, sized: u8) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => extend(out, sized, &(len as u16).to_be_bytes()),
+        len => extend(out, sized + 1, &(len as u32).to_be_bytes()),
+    }
+}
+
+fn extend(out: &mut Vec<u8>, marker: u8, bytes: &[u8]) {
+    out.push(marker);
+    out.extend_from_slice(bytes);
+}
+
+/// Whether `data` starts like a MessagePack log rather than text: with the length prefix of a
+/// record under 16 MiB, whose first byte is zero.
+pub(crate) fn is_msgpack(data: &[u8]) -> bool {
+    data.first() == Some(&0)
+}
+
+/// Decodes length-prefixed records back into lines of the text format.
+///
+/// # Errors
+/// Fails if the data ends in the middle of a record or isn't valid MessagePack.
+pub(crate) fn decode(mut data: &[u8]) -> io::Result<String> {
+    let mut text = String::new();
+    while !data.is_empty() {
+        let len = u32::from_be_bytes(take(&mut data, 4)?.try_into().expect("4 bytes"));
+        let mut frame = take(&mut data, len as usize)?;
+        match decode_value(&mut frame)? {
+            Json::Object(entries) => text.push_str(&to_text(entries)),
+            _ => return Err(invalid("a record isn't a map")),
+        }
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated record",
+        ));
+    }
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Ok(taken)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn decode_value(data: &mut &[u8]) -> io::Result<Json> {
+    let be = |data: &mut &[u8], len: usize| -> io::Result<u64> {
+        Ok(take(data, len)?
+            .iter()
+            .fold(0, |x, byte| (x << 8) | u64::from(*byte)))
+    };
+    let marker = take(data, 1)?[0];
+    let scalar = |value: Value| Ok(Json::Scalar(value));
+    match marker {
+        0x00..=0x7f => scalar(Value::U64(u64::from(marker))),
+        0xe0..=0xff => scalar(Value::I64(i64::from(marker as i8))),
+        0xc0 => Ok(Json::Null),
+        0xc2 => scalar(Value::Bool(false)),
+        0xc3 => scalar(Value::Bool(true)),
+        0xcc..=0xcf => scalar(Value::U64(be(data, 1 << (marker - 0xcc))?)),
+        0xd0 => scalar(Value::I64(i64::from(be(data, 1)? as u8 as i8))),
+        0xd1 => scalar(Value::I64(i64::from(be(data, 2)? as u16 as i16))),
+        0xd2 => scalar(Value::I64(i64::from(be(data, 4)? as u32 as i32))),
+        0xd3 => scalar(Value::I64(be(data, 8)? as i64)),
+        0xcb => scalar(Value::F64(f64::from_bits(be(data, 8)?))),
+        0xa0..=0xbf | 0xd9..=0xdb => {
+            let len = match marker {
+                0xd9 => be(data, 1)?,
+                0xda => be(data, 2)?,
+                0xdb => be(data, 4)?,
+                _ => u64::from(marker & 0x1f),
+            };
+            let bytes = take(data, len as usize)?;
+            let s = std::str::from_utf8(bytes).map_err(|_| invalid("a string isn't UTF-8"))?;
+            scalar(Value::from(s.to_string()))
+        }
+        0x90..=0x9f | 0xdc | 0xdd => {
+            let len = match marker {
+                0xdc => be(data, 2)?,
+                0xdd => be(data, 4)?,
+                _ => u64::from(marker & 0x0f),
+            };
+            let items = (0..len).map(|_| decode_value(data));
+            Ok(Json::Array(items.collect::<io::Result<_>>()?))
+        }
+        0x80..=0x8f | 0xde | 0xdf => {
+            let len = match marker {
+                0xde => be(data, 2)?,
+                0xdf => be(data, 4)?,
+                _ => u64::from(marker & 0x0f),
+            };
+            let mut entries = Vec::new();
+            for _ in 0..len {
+                let Json::Scalar(Value::Str(key)) = decode_value(data)? else {
+                    return Err(invalid("a map key isn't a string"));
+                };
+                entries.push((key.into_owned(), decode_value(data)?));
+            }
+            Ok(Json::Object(entries))
+        }
+        _ => Err(invalid("unsupported MessagePack type")),
+    }
+}
+
+/// Formats a decoded record like the text format would have.
+fn to_text(entries: Vec<(String, Json)>) -> String {
+    let mut record = std::collections::HashMap::new();
+    let mut fields = String::new();
+    for (key, value) in entries {
+        match (key.as_str(), value) {
+            ("fields", Json::Object(entries)) => {
+                for (key, value) in entries {
+                    fields.push_str(&crate::fields::format_field(&key, &scalar(value)));
+                }
+            }
+            (_, value) => {
+                record.insert(key, scalar(value).to_string());
+            }
+        }
+    }
+    let get = |key: &str| record.get(key).map_or("", String::as_str);
+    let message = escape::message(get("message")) + &fields;
+    let mut extra = match get("extra") {
+        "" => String::new(),
+        extra => format!(" {extra}"),
+    };
+    // Last, where `WOODY_SEQUENCE` puts it in text.
+    if let Some(seq) = record.get("seq") {
+        extra.push_str(&format!(" seq={}", escape::token(seq)));
+    }
+    if !record.contains_key("level") {
+        return message + &extra;
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(get("time"))
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S%.3f %:z").to_string())
+        .unwrap_or_else(|_| get("time").to_string());
+    let mut tags = match get("tags") {
+        "" => String::new(),
+        tags => format!("{tags} "),
+    };
+    if let Some(id) = record.get("id") {
+        tags.insert_str(0, &format!("[id={}] ", escape::token(id)));
+    }
+    format!(
+        "[{time}] [{}] [{}] [{}] {tags}{message}{extra}",
+        escape::token(get("level")),
+        escape::token(get("thread")),
+        escape::token(get("location")),
+    )
+}
+
+/// A decoded value as a field value. Arrays, maps, and nulls aren't written by woody, so they're
+/// kept as their JSON-ish debug form.
+fn scalar(value: Json) -> Value {
+    match value {
+        Json::Scalar(value) => value,
+        other => Value::from(format!("{other:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_to_text() {
+        let mut fields = crate::Fields::new();
+        fields.push("big", u64::MAX);
+        fields.push("small", -200);
+        fields.push("ratio", 0.25);
+        fields.push("path", "/a b");
+        let long = "x".repeat(300);
+        let entries = [
+            ("time", "2024-01-01T12:00:00.000+01:00"),
+            ("level", "ERROR"),
+            ("thread", "main"),
+            ("location", "src/main.rs:3"),
+            ("tags", "[id=7]"),
+            ("message", long.as_str()),
+        ];
+        let line = json::record(&entries, &fields);
+        let output = format!("{line} elapsed=1s\n[woody: flight recorder] 0 earlier records\n");
+        let encoded = encode_lines(&output);
+        assert!(is_msgpack(&encoded));
+        assert_eq!(
+            decode(&encoded).unwrap(),
+            format!(
+                "[2024-01-01 12:00:00.000 +01:00] [ERROR] [main] [src/main.rs:3] [id=7] {long} \
+                 big=18446744073709551615 small=-200 ratio=0.25 path=\"/a b\" elapsed=1s\n\
+                 [woody: flight recorder] 0 earlier records\n"
+            )
+        );
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! Site-specific filters, enrichment, and sinks loaded from dynamic libraries (`.so`, `.dylib`,
+//! or `.dll`), so they can be shared across binaries without recompiling them.
+//!
+//! A plugin exports `woody_plugin_abi_version`, returning [`PLUGIN_ABI_VERSION`], and any of
+//! these C functions:
+//!
+//! ```c
+//! // Return 0 to drop the record.
+//! int32_t woody_plugin_filter(const WoodyRecord *record);
+//! // Call `add_field(ctx, key, key_len, value, value_len)` for every field to add.
+//! void woody_plugin_enrich(const WoodyRecord *record, WoodyAddField add_field, void *ctx);
+//! // Receives every formatted record that was logged.
+//! void woody_plugin_write(const uint8_t *line, size_t len);
+//! ```
+//!
+//! where `WoodyRecord` is laid out like [`PluginRecord`]. Strings are UTF-8 and not
+//! NUL-terminated, and are only valid for the duration of the call.
+use std::{
+    borrow::Cow,
+    ffi::{c_char, c_void, CString},
+    io,
+};
+
+use crate::{fields::intern, redact::Redaction, Record};
+
+/// The ABI version plugins must report from `woody_plugin_abi_version`.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// A record as plugins see it.
+#[repr(C)]
+#[derive(Debug)]
+pub struct PluginRecord {
+    /// The level's severity, in tenths (`Info` is 20, `Error` is 50).
+    pub severity: i16,
+    /// The level's name.
+    pub level: *const u8,
+    /// The length of `level` in bytes.
+    pub level_len: usize,
+    /// The message.
+    pub message: *const u8,
+    /// The length of `message` in bytes.
+    pub message_len: usize,
+    /// The file that logged the record.
+    pub file: *const u8,
+    /// The length of `file` in bytes.
+    pub file_len: usize,
+    /// The line that logged the record.
+    pub line: u32,
+    /// The module that logged the record.
+    pub module: *const u8,
+    /// The length of `module` in bytes.
+    pub module_len: usize,
+}
+
+/// Called by `woody_plugin_enrich` to add a field.
+pub type AddFieldFn = unsafe extern "C" fn(*mut c_void, *const u8, usize, *const u8, usize);
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type FilterFn = unsafe extern "C" fn(*const PluginRecord) -> i32;
+type EnrichFn = unsafe extern "C" fn(*const PluginRecord, AddFieldFn, *mut c_void);
+type WriteFn = unsafe extern "C" fn(*const u8, usize);
+
+/// A loaded plugin. The library stays loaded for the rest of the process.
+pub(crate) struct Plugin {
+    path: String,
+    filter: Option<FilterFn>,
+    enrich: Option<EnrichFn>,
+    write: Option<WriteFn>,
+}
+
+impl std::fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugin").field("path", &self.path).finish()
+    }
+}
+
+impl Plugin {
+    /// Loads the plugin at `path`, checking its ABI version.
+    pub(crate) fn load(path: &str) -> io::Result<Self> {
+        let library = sys::open(path)?;
+        let symbol = |name: &str| sys::symbol(library, name);
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let version = symbol("woody_plugin_abi_version")
+            .ok_or_else(|| invalid(format!("{path}: not a woody plugin")))?;
+        // SAFETY: the symbol is documented to be a function with this signature, and these
+        // casts only reinterpret function pointers.
+        unsafe {
+            let version = std::mem::transmute::<*mut c_void, AbiVersionFn>(version)();
+            if version != PLUGIN_ABI_VERSION {
+                return Err(invalid(format!("{path}: unsupported plugin ABI {version}")));
+            }
+            Ok(Self {
+                path: path.to_string(),
+                filter: symbol("woody_plugin_filter")
+                    .map(|f| std::mem::transmute::<*mut c_void, FilterFn>(f)),
+                enrich: symbol("woody_plugin_enrich")
+                    .map(|f| std::mem::transmute::<*mut c_void, EnrichFn>(f)),
+                write: symbol("woody_plugin_write")
+                    .map(|f| std::mem::transmute::<*mut c_void, WriteFn>(f)),
+            })
+        }
+    }
+}
+
+/// Runs the filters and enrichment of every plugin, redacting the fields they add. Returns
+/// `None` if a plugin dropped the record, and only clones it if a plugin added fields.
+pub(crate) fn apply<'a>(
+    plugins: &[std::sync::Arc<Plugin>],
+    record: &'a Record,
+    redaction: &Redaction,
+) -> Option<Cow<'a, Record>> {
+    let mut record = Cow::Borrowed(record);
+    for plugin in plugins {
+        let level = record.level.to_string();
+        let view = PluginRecord {
+            severity: record.level.severity(),
+            level: level.as_ptr(),
+            level_len: level.len(),
+            message: record.message.as_ptr(),
+            message_len: record.message.len(),
+            file: record.filepath.as_ptr(),
+            file_len: record.filepath.len(),
+            line: record.line_number,
+            module: record.module_path.as_ptr(),
+            module_len: record.module_path.len(),
+        };
+        if let Some(filter) = plugin.filter {
+            // SAFETY: `view` and the strings it points to outlive the call.
+            if unsafe { filter(&view) } == 0 {
+                return None;
+            }
+        }
+        if let Some(enrich) = plugin.enrich {
+            let mut added: Vec<(String, String)> = Vec::new();
+            // SAFETY: `add_field` only casts `ctx` back to `added`, which outlives the call.
+            unsafe {
+                enrich(
+                    &view,
+                    add_field,
+                    (&mut added as *mut Vec<(String, String)>).cast(),
+                )
+            };
+            for (key, value) in added {
+                let value = redaction.field(&key, value.into());
+                record.to_mut().fields.push(intern(&key), value);
+            }
+        }
+    }
+    Some(record)
+}
+
+/// Hands a formatted record to every plugin sink.
+pub(crate) fn write(plugins: &[std::sync::Arc<Plugin>], output: &str) {
+    for write in plugins.iter().filter_map(|plugin| plugin.write) {
+        // SAFETY: `output` outlives the call.
+        unsafe { write(output.as_ptr(), output.len()) };
+    }
+}
+
+unsafe extern "C" fn add_field(
+    ctx: *mut c_void,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) {
+    if ctx.is_null() || key.is_null() || value.is_null() {
+        return;
+    }
+    // SAFETY: `ctx` is the `Vec` passed to `woody_plugin_enrich`, and the plugin promises the
+    // strings are valid for the given lengths.
+    let added = unsafe { &mut *ctx.cast::<Vec<(String, String)>>() };
+    let (key, value) = unsafe {
+        (
+            std::slice::from_raw_parts(key, key_len),
+            std::slice::from_raw_parts(value, value_len),
+        )
+    };
+    added.push((
+        String::from_utf8_lossy(key).into_owned(),
+        String::from_utf8_lossy(value).into_owned(),
+    ));
+}
+
+fn c_string(s: &str) -> io::Result<CString> {
+    CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::*;
+
+    const RTLD_NOW: i32 = 2;
+
+    #[cfg_attr(target_os = "linux", link(name = "dl"))]
+    extern "C" {
+        fn dlopen(filename: *const c_char, flag: i32) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *const c_char;
+    }
+
+    pub(super) fn open(path: &str) -> io::Result<*mut c_void> {
+        let path = c_string(path)?;
+        // SAFETY: `path` is NUL-terminated, and `dlerror` returns a NUL-terminated string.
+        unsafe {
+            let handle = dlopen(path.as_ptr(), RTLD_NOW);
+            if handle.is_null() {
+                let error = dlerror();
+                let message = match error.is_null() {
+                    true => "dlopen failed".to_string(),
+                    false => std::ffi::CStr::from_ptr(error)
+                        .to_string_lossy()
+                        .into_owned(),
+                };
+                return Err(io::Error::other(message));
+            }
+            Ok(handle)
+        }
+    }
+
+    pub(super) fn symbol(handle: *mut c_void, name: &str) -> Option<*mut c_void> {
+        let name = c_string(name).ok()?;
+        // SAFETY: `handle` came from `dlopen` and `name` is NUL-terminated.
+        let symbol = unsafe { dlsym(handle, name.as_ptr()) };
+        (!symbol.is_null()).then_some(symbol)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::*;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LoadLibraryW(filename: *const u16) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+    }
+
+    pub(super) fn open(path: &str) -> io::Result<*mut c_void> {
+        let wide: Vec<u16> = path.encode_utf16().chain(Some(0)).collect();
+        // SAFETY: `wide` is NUL-terminated.
+        let handle = unsafe { LoadLibraryW(wide.as_ptr()) };
+        if handle.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(handle)
+    }
+
+    pub(super) fn symbol(handle: *mut c_void, name: &str) -> Option<*mut c_void> {
+        let name = c_string(name).ok()?;
+        // SAFETY: `handle` came from `LoadLibraryW` and `name` is NUL-terminated.
+        let symbol = unsafe { GetProcAddress(handle, name.as_ptr()) };
+        (!symbol.is_null()).then_some(symbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LogLevel, Value};
+    use std::sync::Arc;
+
+    unsafe extern "C" fn drop_debug(record: *const PluginRecord) -> i32 {
+        i32::from(unsafe { (*record).severity } != 30)
+    }
+
+    unsafe extern "C" fn add_region(_: *const PluginRecord, add: AddFieldFn, ctx: *mut c_void) {
+        let (key, value) = ("region", "eu-west-1");
+        unsafe { add(ctx, key.as_ptr(), key.len(), value.as_ptr(), value.len()) };
+    }
+
+    #[test]
+    fn test_filter_and_enrich() {
+        let plugins = [Arc::new(Plugin {
+            path: "test".to_string(),
+            filter: Some(drop_debug),
+            enrich: Some(add_region),
+            write: None,
+        })];
+        let record = |level| Record::new(level, "hello", file!(), line!(), "");
+
+        assert!(apply(&plugins, &record(LogLevel::Debug), &Redaction::default()).is_none());
+        let info = record(LogLevel::Info);
+        let enriched = apply(&plugins, &info, &Redaction::default()).unwrap();
+        assert_eq!(
+            enriched.fields.get("region"),
+            Some(&Value::from("eu-west-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_added_fields_are_redacted() {
+        let mut logger = crate::Logger::get_instance();
+        logger.plugins.push(Arc::new(Plugin {
+            path: "test".to_string(),
+            filter: None,
+            enrich: Some(add_region),
+            write: None,
+        }));
+        logger.set_redacted_fields(&["region"], crate::RedactMode::Mask);
+        let record = Record::new(LogLevel::Info, "hello", file!(), line!(), "");
+        let mut out = Vec::new();
+        logger.write_to(&record, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("region=***"), "{out}");
+        assert!(!out.contains("eu-west-1"), "{out}");
+    }
+
+    #[test]
+    fn test_load_missing() {
+        assert!(Plugin::load("/nonexistent/libwoody_plugin.so").is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! Reading logs back: the active file along with its rotated archives, which may be compressed,
+//! in chronological order.
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{index, json, msgpack, LogLevel};
+
+/// Reads a log file and its rotated archives as one stream of records.
+///
+/// Archives are the files next to the active one whose names start with its name, like
+/// `woody.log.1` or `woody.log.2024-01-01.gz`. They're read oldest first (by modification
+/// time), followed by the active file. `.gz` and `.zst` archives are decompressed with the
+/// system's `gzip` or `zstd`, which must be installed to read them.
+///
+/// # Examples
+/// ```no_run
+/// use woody::LogReader;
+/// let reader = LogReader::open("woody.log")?;
+/// for line in reader.lines()? {
+///     println!("{}", line?);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct LogReader {
+    /// Archives oldest first, then the active file.
+    files: Vec<PathBuf>,
+}
+
+impl LogReader {
+    /// Finds the archives of the log file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let mut archives: Vec<(SystemTime, PathBuf)> = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let is_archive = file_name.len() > name.len()
+                && file_name.starts_with(&name)
+                && file_name[name.len()..].starts_with(['.', '-', '_'])
+                && !file_name.ends_with(".idx");
+            if is_archive && entry.file_type()?.is_file() {
+                archives.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        archives.sort();
+
+        let mut files: Vec<PathBuf> = archives.into_iter().map(|(_, path)| path).collect();
+        if path.exists() {
+            files.push(path.to_path_buf());
+        }
+        Ok(Self { files })
+    }
+
+    /// The files that will be read, in order.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Every record, oldest first.
+    pub fn lines(&self) -> io::Result<impl Iterator<Item = io::Result<String>>> {
+        let readers = self
+            .files
+            .iter()
+            .map(|path| open_decompressed(path))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(readers.into_iter().flat_map(|reader| reader.lines()))
+    }
+
+    /// The records logged between `from` and `to` (inclusive) at `min_level` or above. Lines
+    /// without a timestamp, like the flight recorder's header, go with the record before them.
+    /// Records in the JSON formats are matched by their `time` and `level` keys, and an indented
+    /// one comes back as a single item spanning its lines.
+    ///
+    /// Archives last written before `from` are skipped without being read. Uncompressed files
+    /// are read through their sidecar index (see `WOODY_INDEX`), which is built first if it's
+    /// missing, so only the minutes that can match are read.
+    pub fn query(
+        &self,
+        from: Option<DateTime<FixedOffset>>,
+        to: Option<DateTime<FixedOffset>>,
+        min_level: LogLevel,
+    ) -> io::Result<impl Iterator<Item = io::Result<String>>> {
+        let files = self.files.iter().filter(|path| {
+            let Some(from) = from else {
+                return true;
+            };
+            let modified = std::fs::metadata(path).and_then(|m| m.modified());
+            modified.map_or(true, |modified| {
+                DateTime::<chrono::Utc>::from(modified) >= from
+            })
+        });
+        let mut readers = Vec::new();
+        for path in files {
+            if is_compressed(path) || is_binary(path)? {
+                readers.push(open_decompressed(path)?);
+                continue;
+            }
+            let Some(entries) = index::load(path) else {
+                readers.push(open_decompressed(path)?);
+                continue;
+            };
+            for (start, end) in ranges(&entries, from, to, min_level) {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(start))?;
+                let reader: Box<dyn BufRead> = match end {
+                    Some(end) => Box::new(BufReader::new(file.take(end - start))),
+                    None => Box::new(BufReader::new(file)),
+                };
+                readers.push(reader);
+            }
+        }
+
+        let mut keep = from.is_none() && min_level == LogLevel::ALL;
+        let lines = readers
+            .into_iter()
+            .flat_map(|reader| records(reader.lines()));
+        Ok(lines.filter(move |line| {
+            let Ok(line) = line else {
+                return true;
+            };
+            if let Some(time) = timestamp(line) {
+                let in_range =
+                    from.is_none_or(|from| time >= from) && to.is_none_or(|to| time <= to);
+                keep = in_range && level(line).is_none_or(|level| level >= min_level);
+            }
+            keep
+        }))
+    }
+}
+
+/// The byte ranges of a file to read for a query, given its index. Gaps between entries and
+/// everything after the last one aren't indexed, so they're always read.
+fn ranges(
+    entries: &[index::Entry],
+    from: Option<DateTime<FixedOffset>>,
+    to: Option<DateTime<FixedOffset>>,
+    min_level: LogLevel,
+) -> Vec<(u64, Option<u64>)> {
+    let mut ranges: Vec<(u64, Option<u64>)> = Vec::new();
+    let mut push = |start: u64, end: Option<u64>| match ranges.last_mut() {
+        Some((_, last_end)) if *last_end == Some(start) => *last_end = end,
+        _ => ranges.push((start, end)),
+    };
+    let mut cursor = 0;
+    for entry in entries {
+        if entry.start > cursor {
+            push(cursor, Some(entry.start));
+        }
+        // Records are indexed when they're written, which can be in the minute after their
+        // timestamp, so the window is widened by a minute on each side.
+        let start = (entry.minute - 1) * 60;
+        let end = (entry.minute + 2) * 60;
+        let selected = entry.max_severity >= min_level.severity()
+            && from.is_none_or(|from| end > from.timestamp())
+            && to.is_none_or(|to| start <= to.timestamp());
+        if selected {
+            push(entry.start, Some(entry.end));
+        }
+        cursor = cursor.max(entry.end);
+    }
+    push(cursor, None);
+    ranges
+}
+
+/// Joins the lines of each indented JSON record into one item, so it's filtered as a whole.
+fn records(
+    mut lines: impl Iterator<Item = io::Result<String>>,
+) -> impl Iterator<Item = io::Result<String>> {
+    std::iter::from_fn(move || {
+        let mut record = match lines.next()? {
+            Ok(line) => line,
+            error => return Some(error),
+        };
+        while json::is_unfinished(&record) {
+            match lines.next() {
+                Some(Ok(line)) => {
+                    record.push('\n');
+                    record.push_str(&line);
+                }
+                Some(error) => return Some(error),
+                None => break,
+            }
+        }
+        Some(Ok(record))
+    })
+}
+
+/// Parses the timestamp of a record: the `time` key of a JSON one, or the start of a text
+/// one, e.g. `[2024-01-01 12:00:00.000 +01:00]`.
+pub fn timestamp(line: &str) -> Option<DateTime<FixedOffset>> {
+    if line.trim_start().starts_with('{') {
+        return DateTime::parse_from_rfc3339(&json::string_member(line, "time")?).ok();
+    }
+    let end = line.find(']')?;
+    let time = line.get(1..end)?;
+    DateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S%.3f %:z").ok()
+}
+
+/// Parses the level of a record: the `level` key of a JSON one, or e.g. `ERROR` in a text
+/// one's `[...] [ERROR] [main] ...`.
+pub(crate) fn level(line: &str) -> Option<LogLevel> {
+    let name = match line.trim_start().starts_with('{') {
+        true => json::string_member(line, "level")?,
+        false => {
+            let rest = &line[line.find("] [")? + 3..];
+            rest[..rest.find(']')?].to_string()
+        }
+    };
+    match LogLevel::parse(&name) {
+        LogLevel::ALL => None,
+        level => Some(level),
+    }
+}
+
+fn is_compressed(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gz" | "zst")
+    )
+}
+
+/// Whether a file is in the `msgpack` format, which has no lines to index.
+fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut first = [0; 1];
+    let read = File::open(path)?.read(&mut first)?;
+    Ok(read == 1 && msgpack::is_msgpack(&first))
+}
+
+/// Opens a file as text, decompressing `.gz` and `.zst` files with the system's tools and
+/// decoding the `msgpack` format.
+fn open_decompressed(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = open_raw(path)?;
+    if msgpack::is_msgpack(reader.fill_buf()?) {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let text = msgpack::decode(&data)?;
+        return Ok(Box::new(io::Cursor::new(text.into_bytes())));
+    }
+    Ok(reader)
+}
+
+fn open_raw(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let tool = match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => "gzip",
+        Some("zst") => "zstd",
+        _ => return Ok(Box::new(BufReader::new(File::open(path)?))),
+    };
+    let mut child = Command::new(tool)
+        .arg("-dc")
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("{tool} is needed to read {}: {e}", path.display()),
+            )
+        })?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    Ok(Box::new(BufReader::new(Decompressed { child, stdout })))
+}
+
+/// The output of a decompressor, reaped once read.
+struct Decompressed {
+    child: std::process::Child,
+    stdout: std::process::ChildStdout,
+}
+
+impl Read for Decompressed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for Decompressed {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn record(time: &str, message: &str) -> String {
+        format!("[{time} +00:00] [INFO] [main] [src/main.rs:1] {message}\n")
+    }
+
+    #[test]
+    fn test_archives_in_order() {
+        let dir = std::env::temp_dir().join(format!("woody-reader-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, contents: String, age: u64| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(age);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+            path
+        };
+
+        let old = write(
+            "woody.log.2",
+            record("2024-01-01 10:00:00.000", "oldest"),
+            300,
+        );
+        let compressed = Command::new("gzip")
+            .arg(&old)
+            .status()
+            .is_ok_and(|s| s.success());
+        write(
+            "woody.log.1",
+            record("2024-01-02 10:00:00.000", "older"),
+            200,
+        );
+        write("woody.log", record("2024-01-03 10:00:00.000", "newest"), 0);
+        write(
+            "other.log",
+            record("2024-01-03 10:00:00.000", "unrelated"),
+            0,
+        );
+
+        let reader = LogReader::open(dir.join("woody.log")).unwrap();
+        let lines: Vec<String> = reader.lines().unwrap().map(Result::unwrap).collect();
+        let messages: Vec<&str> = lines
+            .iter()
+            .map(|l| l.rsplit(' ').next().unwrap())
+            .collect();
+        match compressed {
+            true => assert_eq!(messages, ["oldest", "older", "newest"]),
+            false => assert_eq!(messages, ["older", "newest"]),
+        }
+
+        let from = DateTime::parse_from_rfc3339("2024-01-02T00:00:00+00:00").unwrap();
+        let to = DateTime::parse_from_rfc3339("2024-01-02T23:00:00+00:00").unwrap();
+        let lines: Vec<String> = reader
+            .query(Some(from), Some(to), LogLevel::ALL)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("older"));
+
+        let errors: Vec<String> = reader
+            .query(None, None, LogLevel::Error)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(errors.is_empty(), "{errors:?}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_query_json() {
+        let dir = std::env::temp_dir().join(format!("woody-json-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = |time: &str, level: &str, message: &str| {
+            let entries = [("time", time), ("level", level), ("message", message)];
+            crate::json::record(&entries, &crate::Fields::new()) + "\n"
+        };
+        let output = json("2024-01-01T10:00:00.000+00:00", "INFO", "started")
+            + &json("2024-01-01T10:00:01.000+00:00", "ERROR", "failed")
+            + &json("2024-01-01T11:00:02.000+01:00", "ERROR", "later")
+            + &json("2024-01-01T10:05:00.000+00:00", "INFO", "next minute");
+        let from = DateTime::parse_from_rfc3339("2024-01-01T10:00:01+00:00").unwrap();
+        let to = DateTime::parse_from_rfc3339("2024-01-01T10:30:00+00:00").unwrap();
+
+        for (name, contents) in [
+            ("woody.log", output.clone()),
+            ("pretty.log", json::pretty(&output)),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, &contents).unwrap();
+            let reader = LogReader::open(&path).unwrap();
+            let errors: Vec<String> = reader
+                .query(None, None, LogLevel::Error)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(errors.len(), 2, "{errors:?}");
+            assert!(errors[0].contains("failed") && errors[1].contains("later"));
+
+            let lines: Vec<String> = reader
+                .query(Some(from), Some(to), LogLevel::ALL)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(lines.len(), 3, "{lines:?}");
+            // Indexed like text, with an indented record's lines counted as one.
+            let entries = index::load(&path).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].max_severity, LogLevel::Error.severity());
+            let next_minute = contents[..contents.find("10:05").unwrap()].rfind('{');
+            assert_eq!(Some(entries[0].end as usize), next_minute);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_query_msgpack() {
+        let dir = std::env::temp_dir().join(format!("woody-msgpack-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = |level: &str, message: &str| {
+            let entries = [
+                ("time", "2024-01-01T10:00:00.000+00:00"),
+                ("level", level),
+                ("thread", "main"),
+                ("location", "src/main.rs:1"),
+                ("message", message),
+            ];
+            crate::json::record(&entries, &crate::Fields::new()) + "\n"
+        };
+        let output = json("INFO", "started") + &json("ERROR", "failed");
+        let path = dir.join("woody.log");
+        std::fs::write(&path, msgpack::encode_lines(&output)).unwrap();
+
+        let reader = LogReader::open(&path).unwrap();
+        let errors: Vec<String> = reader
+            .query(None, None, LogLevel::Error)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            errors,
+            ["[2024-01-01 10:00:00.000 +00:00] [ERROR] [main] [src/main.rs:1] failed"]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,87 @@
+//! A "flight recorder": an in-memory ring of the most recent records that were below the log
+//! level, dumped to the file when an error occurs so it comes with its full lead-up without
+//! verbose logging all the time.
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::memory::{self, Reservation};
+
+/// Keeps the last `capacity` formatted records that weren't written, fewer if they'd go over
+/// the memory limit.
+#[derive(Debug)]
+pub(crate) struct FlightRecorder {
+    capacity: usize,
+    records: Mutex<VecDeque<Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    record: String,
+    /// The record as a MessagePack frame when the log file is in that format, so the crash
+    /// handler can write it without encoding.
+    #[cfg_attr(not(all(unix, feature = "file")), allow(dead_code))]
+    frame: Option<Vec<u8>>,
+    _reservation: Reservation,
+}
+
+impl FlightRecorder {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Remembers a formatted record, and its frame for a binary log file, forgetting the
+    /// oldest ones if full or over the memory limit. A record that doesn't fit even then isn't
+    /// kept.
+    pub(crate) fn push(&self, record: String, frame: Option<Vec<u8>>) {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        let reservation = loop {
+            match memory::reserve(record.len() + frame.as_ref().map_or(0, Vec::len)) {
+                Some(reservation) => break reservation,
+                None if records.pop_front().is_some() => {}
+                None => return,
+            }
+        };
+        records.push_back(Entry {
+            record,
+            frame,
+            _reservation: reservation,
+        });
+    }
+
+    /// Calls `f` with every record and its frame, if it has one, oldest first, without
+    /// allocating, unless the recorder is locked. Used from the crash handler.
+    #[cfg(all(unix, feature = "file"))]
+    pub(crate) fn try_for_each(&self, mut f: impl FnMut(&str, Option<&[u8]>)) {
+        if let Ok(records) = self.records.try_lock() {
+            for entry in records.iter() {
+                f(&entry.record, entry.frame.as_deref());
+            }
+        }
+    }
+
+    /// Empties the recorder, returning its records oldest first.
+    pub(crate) fn take(&self) -> Vec<String> {
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.drain(..).map(|entry| entry.record).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_last_records() {
+        let recorder = FlightRecorder::new(2);
+        for line in ["one", "two", "three"] {
+            recorder.push(line.to_string(), None);
+        }
+        assert_eq!(recorder.take(), ["two", "three"]);
+        assert!(recorder.take().is_empty());
+    }
+}
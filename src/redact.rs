@@ -0,0 +1,194 @@
+//! Redaction of structured fields by name, so secrets like `password=...` never reach a log
+//! file, console, subscriber, or plugin, whichever format they use.
+use std::borrow::Cow;
+
+use crate::{Record, Value};
+
+/// What redacted fields are replaced with.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RedactMode {
+    /// `***`. This is the default.
+    #[default]
+    Mask,
+    /// A hash of the value, e.g. `hash:5b1f3c0e9d2a4f67`, so equal values can still be
+    /// matched up across records. Not a cryptographic hash: values with few possibilities,
+    /// like PINs, can be recovered by trying them all, so set a salt or mask those instead.
+    Hash,
+}
+
+impl RedactMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "mask" => Some(RedactMode::Mask),
+            "hash" => Some(RedactMode::Hash),
+            _ => None,
+        }
+    }
+}
+
+/// The field names to redact and how.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Redaction {
+    /// Lowercased field names.
+    fields: Vec<String>,
+    mode: RedactMode,
+    salt: String,
+}
+
+impl Redaction {
+    pub(crate) fn new(fields: &[&str], mode: RedactMode, salt: &str) -> Self {
+        Self {
+            fields: fields.iter().map(|f| f.trim().to_lowercase()).collect(),
+            mode,
+            salt: salt.to_string(),
+        }
+    }
+
+    /// Reads `WOODY_REDACT_FIELDS` (`password,ssn`, or `["password", "ssn"]` in the config
+    /// file), `WOODY_REDACT_MODE` (`mask` or `hash`), and `WOODY_REDACT_SALT`.
+    pub(crate) fn from_config() -> Self {
+        let fields = crate::config::setting("WOODY_REDACT_FIELDS").unwrap_or_default();
+        let fields: Vec<&str> = fields
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .map(|f| f.trim().trim_matches('"').trim_matches('\''))
+            .filter(|f| !f.is_empty())
+            .collect();
+        let mode = crate::config::setting("WOODY_REDACT_MODE")
+            .and_then(|x| RedactMode::parse(&x))
+            .unwrap_or_default();
+        let salt = crate::config::setting("WOODY_REDACT_SALT").unwrap_or_default();
+        Self::new(&fields, mode, &salt)
+    }
+
+    /// Returns the record with matching fields redacted, only cloning it if any match.
+    pub(crate) fn apply<'a>(&self, record: &'a Record) -> Cow<'a, Record> {
+        if self.fields.is_empty() || !record.fields.iter().any(|(key, _)| self.matches(key)) {
+            return Cow::Borrowed(record);
+        }
+        let mut redacted = record.clone();
+        redacted.fields = record
+            .fields
+            .iter()
+            .map(|(key, value)| (*key, self.field(key, value.clone())))
+            .collect();
+        Cow::Owned(redacted)
+    }
+
+    /// Returns `value` redacted if `key` is one of the fields, for fields added after
+    /// [`Redaction::apply`], like those from plugins.
+    pub(crate) fn field(&self, key: &str, value: Value) -> Value {
+        match self.matches(key) {
+            true => self.replace(&value),
+            false => value,
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|f| f.eq_ignore_ascii_case(key) || f.eq_ignore_ascii_case(leaf(key)))
+    }
+
+    fn replace(&self, value: &Value) -> Value {
+        match self.mode {
+            RedactMode::Mask => Value::from("***"),
+            RedactMode::Hash => {
+                let hash = fnv1a(self.salt.bytes().chain(value.to_string().bytes()));
+                Value::from(format!("hash:{hash:016x}"))
+            }
+        }
+    }
+}
+
+/// The last name in a path like `db.password` or `tokens[0]`, as `log_diff!` keys its fields,
+/// so those are redacted by the name of the field that changed.
+fn leaf(key: &str) -> &str {
+    let key = match key.strip_suffix(']').and_then(|key| key.rsplit_once('[')) {
+        Some((name, index)) if !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) => {
+            name
+        }
+        _ => key,
+    };
+    key.rsplit('.').next().unwrap_or(key).trim_matches('"')
+}
+
+/// The 64-bit FNV-1a hash, which unlike the standard library's hasher is the same in every
+/// process and Rust version.
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LogLevel;
+
+    fn record() -> Record {
+        Record::new(LogLevel::Info, "login", file!(), line!(), "")
+            .with_field("user", "ada")
+            .with_field("Password", "hunter2")
+    }
+
+    #[test]
+    fn test_mask() {
+        let redaction = Redaction::new(&["password", "ssn"], RedactMode::Mask, "");
+        let record = record();
+        let redacted = redaction.apply(&record);
+        assert_eq!(redacted.fields.get("Password"), Some(&Value::from("***")));
+        assert_eq!(redacted.fields.get("user"), Some(&Value::from("ada")));
+
+        let clean = Record::new(LogLevel::Info, "ok", file!(), line!(), "");
+        assert!(matches!(redaction.apply(&clean), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_matches_diff_paths() {
+        let redaction = Redaction::new(&["password"], RedactMode::Mask, "");
+        let record = Record::new(LogLevel::Info, "config reloaded", file!(), line!(), "")
+            .with_field("db.password", "\"a\" -> \"b\"")
+            .with_field("db.passwords[1]", "1 -> 2")
+            .with_field("db.port", "1 -> 2");
+        let redacted = redaction.apply(&record);
+        assert_eq!(
+            redacted.fields.get("db.password"),
+            Some(&Value::from("***"))
+        );
+        assert_eq!(
+            redacted.fields.get("db.passwords[1]"),
+            Some(&Value::from("1 -> 2"))
+        );
+        assert_eq!(redacted.fields.get("db.port"), Some(&Value::from("1 -> 2")));
+    }
+
+    #[test]
+    fn test_leaf_strips_only_an_index() {
+        assert_eq!(leaf("users[0]"), "users");
+        assert_eq!(leaf("db.tokens[12]"), "tokens");
+        assert_eq!(leaf("token2"), "token2");
+        assert_eq!(leaf("sha256"), "sha256");
+        assert_eq!(leaf("labels.\"key\""), "key");
+        let redaction = Redaction::new(&["token"], RedactMode::Mask, "");
+        let record = Record::new(LogLevel::Info, "hashed", file!(), line!(), "")
+            .with_field("token2", "x")
+            .with_field("token[3]", "z");
+        let redacted = redaction.apply(&record);
+        assert_eq!(redacted.fields.get("token2"), Some(&Value::from("x")));
+        assert_eq!(redacted.fields.get("token[3]"), Some(&Value::from("***")));
+    }
+
+    #[test]
+    fn test_hash_is_stable() {
+        let redaction = Redaction::new(&["password"], RedactMode::Hash, "pepper");
+        let record = record();
+        let first = redaction.apply(&record).fields.get("Password").cloned();
+        let second = redaction.apply(&record).fields.get("Password").cloned();
+        assert_eq!(first, second);
+        assert_ne!(first, Some(Value::from("hunter2")));
+        assert_eq!(fnv1a(b"a".iter().copied()), 0xaf63_dc4c_8601_ec8c);
+    }
+}
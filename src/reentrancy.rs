@@ -0,0 +1,60 @@
+//! Detects a thread logging from inside the logger, e.g. from an ID generator, a console
+//! suspend hook, or a panic hook that fires mid-write. Such records take a fallback path
+//! straight to stderr instead of recursing into the logger and deadlocking on its locks.
+use std::{cell::Cell, io::Write};
+
+thread_local! {
+    static LOGGING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as logging until dropped, including when unwinding from a panic.
+pub(crate) struct Guard(());
+
+impl Guard {
+    /// Returns `None` if the current thread is already logging.
+    pub(crate) fn enter() -> Option<Guard> {
+        LOGGING.with(|logging| {
+            if logging.replace(true) {
+                None
+            } else {
+                Some(Guard(()))
+            }
+        })
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        LOGGING.with(|logging| logging.set(false));
+    }
+}
+
+/// Writes a record logged from inside the logger. This touches nothing the outer call might
+/// be holding, so it can't deadlock.
+pub(crate) fn fallback(output: &str) {
+    let _ = std::io::stderr().write_all(format!("[woody: reentrant] {output}").as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_nesting() {
+        let outer = Guard::enter();
+        assert!(outer.is_some());
+        assert!(Guard::enter().is_none());
+        drop(outer);
+        assert!(Guard::enter().is_some());
+    }
+
+    #[test]
+    fn test_guard_released_on_panic() {
+        let result = std::panic::catch_unwind(|| {
+            let _guard = Guard::enter().unwrap();
+            panic!("boom");
+        });
+        assert!(result.is_err());
+        assert!(Guard::enter().is_some());
+    }
+}
@@ -0,0 +1,104 @@
+//! Level overrides for times of day, so a nightly batch can be logged verbosely without
+//! someone toggling the level by hand.
+use chrono::{NaiveTime, Timelike};
+
+use crate::LogLevel;
+
+/// A daily window during which the root level is replaced, e.g. `Trace` from 02:00 to 03:00.
+///
+/// Windows may wrap around midnight (`22:00-06:00`). Named loggers with a level of their own
+/// keep it.
+///
+/// # Examples
+/// ```
+/// use woody::{LevelWindow, LogLevel};
+/// let window = LevelWindow::parse("02:00-03:00=trace").unwrap();
+/// assert_eq!(window.level(), LogLevel::Trace);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LevelWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    level: LogLevel,
+}
+
+impl LevelWindow {
+    /// Creates a window from `start` (inclusive) to `end` (exclusive), local time.
+    pub fn new(start: NaiveTime, end: NaiveTime, level: LogLevel) -> Self {
+        Self { start, end, level }
+    }
+
+    /// Parses `HH:MM-HH:MM=level`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (times, level) = s.split_once('=')?;
+        let (start, end) = times.split_once('-')?;
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").ok();
+        let level = LogLevel::parse(level);
+        if level == LogLevel::ALL {
+            return None;
+        }
+        Some(Self::new(time(start)?, time(end)?, level))
+    }
+
+    /// Parses a list of windows separated by `,` or `;`, skipping invalid ones.
+    pub(crate) fn parse_list(s: &str) -> Vec<Self> {
+        s.split([',', ';']).filter_map(Self::parse).collect()
+    }
+
+    /// The level used during the window.
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+
+    /// Whether `time` falls in the window.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        // Compare at minute precision so `02:00-03:00` ends exactly at 03:00.
+        let time = time
+            .with_second(0)
+            .unwrap_or(time)
+            .with_nanosecond(0)
+            .unwrap_or(time);
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// The level of the first window containing the current local time, if any.
+pub(crate) fn active(windows: &[LevelWindow]) -> Option<LogLevel> {
+    if windows.is_empty() {
+        return None;
+    }
+    let now = chrono::Local::now().time();
+    windows.iter().find(|w| w.contains(now)).map(|w| w.level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 30).unwrap()
+    }
+
+    #[test]
+    fn test_window_contains() {
+        let batch = LevelWindow::parse("02:00-03:00=trace").unwrap();
+        assert!(batch.contains(at(2, 0)) && batch.contains(at(2, 59)));
+        assert!(!batch.contains(at(3, 0)) && !batch.contains(at(1, 59)));
+
+        let night = LevelWindow::parse("22:00-06:00=debug").unwrap();
+        assert!(night.contains(at(23, 0)) && night.contains(at(5, 0)));
+        assert!(!night.contains(at(12, 0)));
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let windows = LevelWindow::parse_list("02:00-03:00=trace; 12:00-12:30=debug, bogus");
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[1].level(), LogLevel::Debug);
+        assert!(LevelWindow::parse("02:00-03:00=loud").is_none());
+    }
+}
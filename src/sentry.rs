@@ -0,0 +1,130 @@
+//! Forwarding errors to Sentry, so a team already on Sentry gets its error tracking from the
+//! records it logs anyway instead of instrumenting everything twice.
+use std::borrow::Cow;
+
+use ::sentry::protocol::{Event, Level, Map, Value as Json};
+
+use crate::{LogLevel, Record, Value};
+
+/// Sends records at `ERROR` and above to Sentry as events, through the Sentry SDK's current
+/// hub, so they go wherever `sentry::init` set up and get its batching and retries. Without a
+/// client bound to the hub, events are dropped.
+///
+/// Events have the message, level, module as `logger`, `file:line` as `culprit`, the thread and
+/// context as tags, the fields as `extra`, and the `fingerprint` field (see
+/// [`Logger::set_fingerprint`]) as the grouping fingerprint.
+///
+/// [`Logger::set_fingerprint`]: crate::Logger::set_fingerprint
+///
+/// # Examples
+/// ```
+/// use woody::{sentry::Sentry, Logger};
+/// // After `sentry::init(...)`:
+/// let mut logger = Logger::get_instance();
+/// logger.set_sentry(Some(Sentry::new()));
+/// Logger::set_instance(logger);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Sentry {
+    _private: (),
+}
+
+impl Sentry {
+    /// Sends events through the current hub.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends `record` if it's at `ERROR` or above. `tags` are the rendered context tags.
+    pub(crate) fn send(&self, record: &Record, tags: &str) {
+        if record.level >= LogLevel::Error {
+            ::sentry::capture_event(event(record, tags));
+        }
+    }
+}
+
+/// The Sentry event for a record.
+fn event(record: &Record, tags: &str) -> Event<'static> {
+    let level = match record.level {
+        LogLevel::Fatal => Level::Fatal,
+        LogLevel::Error => Level::Error,
+        LogLevel::Warning => Level::Warning,
+        LogLevel::Debug | LogLevel::Trace => Level::Debug,
+        _ => Level::Info,
+    };
+    let thread = record.thread.clone().unwrap_or_else(|| {
+        let thread = std::thread::current();
+        thread.name().unwrap_or("unnamed").to_string()
+    });
+    let mut event_tags = Map::new();
+    event_tags.insert("thread".to_string(), thread);
+    if !tags.trim().is_empty() {
+        event_tags.insert("context".to_string(), tags.trim().to_string());
+    }
+    let extra = record
+        .fields
+        .iter()
+        .filter(|(key, _)| *key != "fingerprint")
+        .map(|(key, value)| (key.to_string(), json(value)))
+        .collect();
+    let mut event = Event {
+        level,
+        logger: Some(record.module_path.to_string()).filter(|x| !x.is_empty()),
+        culprit: Some(format!("{}:{}", record.filepath, record.line_number)),
+        message: Some(record.message.clone()),
+        tags: event_tags,
+        extra,
+        ..Event::default()
+    };
+    if let Some(fingerprint) = record.fields.get("fingerprint") {
+        event.fingerprint = Cow::Owned(vec![Cow::Owned(fingerprint.to_string())]);
+    }
+    event
+}
+
+/// A field's value as JSON, keeping numbers and booleans typed. Non-finite floats are `null`.
+fn json(value: &Value) -> Json {
+    match value {
+        Value::I64(x) => Json::from(*x),
+        Value::U64(x) => Json::from(*x),
+        Value::F64(x) => Json::from(*x),
+        Value::Bool(x) => Json::from(*x),
+        Value::Str(x) => Json::from(x.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        let record = Record::new(LogLevel::Error, "disk \"full\"", "src/io.rs", 9, "app::io")
+            .with_field("volume", "/data")
+            .with_field("free", 0u64)
+            .with_field("fingerprint", "0123456789abcdef");
+        let event = event(&record, "[request_id=7] ");
+        assert_eq!(event.level, Level::Error);
+        assert_eq!(event.logger.as_deref(), Some("app::io"));
+        assert_eq!(event.culprit.as_deref(), Some("src/io.rs:9"));
+        assert_eq!(event.message.as_deref(), Some("disk \"full\""));
+        assert_eq!(event.tags["context"], "[request_id=7]");
+        assert_eq!(event.extra["volume"], Json::from("/data"));
+        assert_eq!(event.extra["free"], Json::from(0u64));
+        assert!(!event.extra.contains_key("fingerprint"));
+        assert_eq!(&*event.fingerprint, ["0123456789abcdef"]);
+    }
+
+    #[test]
+    fn test_send_captures_errors_only() {
+        let events = ::sentry::test::with_captured_events(|| {
+            let sentry = Sentry::new();
+            let record = |level| Record::new(level, "disk full", "src/io.rs", 9, "app::io");
+            sentry.send(&record(LogLevel::Warning), "");
+            sentry.send(&record(LogLevel::Error), "");
+        });
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].message.as_deref(), Some("disk full"));
+        assert_eq!(events[0].level, Level::Error);
+    }
+}
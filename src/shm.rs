@@ -0,0 +1,345 @@
+//! A sink that writes records into a ring buffer in shared memory, so another process (say, an
+//! observability agent) can read them live without either side touching a disk. Put the ring
+//! on a memory filesystem like `/dev/shm` to keep it off the disk entirely.
+//!
+//! The ring is a file mapped by both processes: a 64-byte header, then the data.
+//!
+//! | Offset | Size | Contents                                                     |
+//! | ------ | ---- | ------------------------------------------------------------ |
+//! | 0      | 8    | `WOODYSHM`                                                   |
+//! | 8      | 4    | Version, `1`, native-endian                                  |
+//! | 16     | 8    | Capacity of the data in bytes, native-endian                 |
+//! | 24     | 8    | Bytes ever written, native-endian, stored after the data     |
+//! | 32     | 8    | Bytes written once the write in progress is done, native-endian, stored before the data |
+//!
+//! Records are written as text lines at `position % capacity`, wrapping around. Like a seqlock,
+//! the writer stores where the write it's starting will end (offset 32) before copying any
+//! data, and how far it got (offset 24) after. A reader remembers how far it got, reads offset
+//! 24, copies what's new, and then reads offset 32: if the writer had started overwriting what
+//! was copied by then, it may be torn, so the reader throws it away and, once the writer is
+//! done, skips ahead to the oldest whole line still in the ring and counts the rest as lost.
+//! Only one process may write to a ring.
+use std::{
+    ffi::{c_int, c_void},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+const MAGIC: &[u8; 8] = b"WOODYSHM";
+const VERSION: u32 = 1;
+const HEADER: usize = 64;
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// A file mapped into memory, unmapped when dropped.
+#[derive(Debug)]
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is only reached through `&mut self` methods of its owner, and the only
+// state shared with other processes is read and written as described in the module docs.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn new(file: &File, len: usize) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of a file at least `len` bytes long.
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    fn capacity(&self) -> usize {
+        self.len - HEADER
+    }
+
+    fn read_header(&self) -> [u8; HEADER] {
+        let mut header = [0; HEADER];
+        // SAFETY: the mapping is longer than the header.
+        unsafe { std::ptr::copy_nonoverlapping(self.ptr, header.as_mut_ptr(), HEADER) };
+        header
+    }
+
+    /// The count of bytes ever written, shared with the other process.
+    fn position(&self) -> &AtomicU64 {
+        // SAFETY: the header is inside the mapping, and offset 24 of a page is 8-byte aligned.
+        unsafe { &*self.ptr.add(24).cast::<AtomicU64>() }
+    }
+
+    /// The count of bytes written once the write in progress is done, shared with the other
+    /// process.
+    fn writing(&self) -> &AtomicU64 {
+        // SAFETY: the header is inside the mapping, and offset 32 of a page is 8-byte aligned.
+        unsafe { &*self.ptr.add(32).cast::<AtomicU64>() }
+    }
+
+    /// Copies `bytes` into the ring at `position`, wrapping around.
+    fn write_at(&mut self, position: u64, bytes: &[u8]) {
+        let start = (position % self.capacity() as u64) as usize;
+        let first = bytes.len().min(self.capacity() - start);
+        // SAFETY: both ranges are within the data, and `bytes` is at most the capacity.
+        unsafe {
+            let data = self.ptr.add(HEADER);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), data.add(start), first);
+            std::ptr::copy_nonoverlapping(bytes[first..].as_ptr(), data, bytes.len() - first);
+        }
+    }
+
+    /// Copies the `len` bytes at `position` out of the ring, wrapping around.
+    fn read_at(&self, position: u64, len: usize) -> Vec<u8> {
+        let start = (position % self.capacity() as u64) as usize;
+        let first = len.min(self.capacity() - start);
+        let mut out = Vec::with_capacity(len);
+        // SAFETY: both ranges are within the data, and `len` is at most the capacity.
+        unsafe {
+            let data = self.ptr.add(HEADER);
+            out.extend_from_slice(std::slice::from_raw_parts(data.add(start), first));
+            out.extend_from_slice(std::slice::from_raw_parts(data, len - first));
+        }
+        out
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was made by `mmap` with this length and isn't used after this.
+        unsafe {
+            munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+/// Writes records into a shared-memory ring of `capacity` bytes, for [`Logger::add_sink`].
+/// Read it from another process with [`ShmReader`].
+///
+/// [`Logger::add_sink`]: crate::Logger::add_sink
+///
+/// # Examples
+/// ```no_run
+/// use woody::{shm::ShmSink, Logger};
+/// let ring = ShmSink::create("/dev/shm/myapp.log", 1 << 20).unwrap();
+/// Logger::get_instance().add_sink(ring);
+/// ```
+#[derive(Debug)]
+pub struct ShmSink {
+    map: Mapping,
+}
+
+impl ShmSink {
+    /// Creates the ring at `path`, replacing what was there.
+    ///
+    /// # Errors
+    /// Fails if the file can't be created or mapped, or `capacity` is zero.
+    pub fn create(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        if capacity == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a ring needs some capacity",
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut header = [0; HEADER];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&VERSION.to_ne_bytes());
+        header[16..24].copy_from_slice(&(capacity as u64).to_ne_bytes());
+        (&file).write_all(&header)?;
+        file.set_len((HEADER + capacity) as u64)?;
+        Ok(Self {
+            map: Mapping::new(&file, HEADER + capacity)?,
+        })
+    }
+}
+
+impl Write for ShmSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let position = self.map.position().load(Ordering::Relaxed);
+        let end = position + buf.len() as u64;
+        // Readers have to see where this write ends before any of its data.
+        self.map.writing().store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+        // Only the end of a write bigger than the ring would survive it anyway.
+        let skipped = buf.len().saturating_sub(self.map.capacity());
+        self.map
+            .write_at(position + skipped as u64, &buf[skipped..]);
+        self.map.position().store(end, Ordering::Release);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the records written to a ring by a [`ShmSink`], possibly in another process.
+///
+/// # Examples
+/// ```no_run
+/// use woody::shm::ShmReader;
+/// let mut ring = ShmReader::open("/dev/shm/myapp.log").unwrap();
+/// loop {
+///     print!("{}", ring.read_new());
+///     std::thread::sleep(std::time::Duration::from_millis(100));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ShmReader {
+    map: Mapping,
+    position: u64,
+    lost: u64,
+}
+
+impl ShmReader {
+    /// Opens the ring at `path`, starting from the oldest whole record still in it.
+    ///
+    /// # Errors
+    /// Fails if the file can't be mapped or isn't a ring.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let len = file.metadata()?.len() as usize;
+        if len <= HEADER {
+            return Err(invalid("not a woody ring"));
+        }
+        let map = Mapping::new(&file, len)?;
+        let header = map.read_header();
+        let capacity = u64::from_ne_bytes(header[16..24].try_into().expect("8 bytes"));
+        if &header[..8] != MAGIC || capacity != map.capacity() as u64 {
+            return Err(invalid("not a woody ring"));
+        }
+        if u32::from_ne_bytes(header[8..12].try_into().expect("4 bytes")) != VERSION {
+            return Err(invalid("unsupported ring version"));
+        }
+        let mut reader = Self {
+            map,
+            position: 0,
+            lost: 0,
+        };
+        let written = reader.map.position().load(Ordering::Acquire);
+        reader.catch_up(written);
+        reader.lost = 0;
+        Ok(reader)
+    }
+
+    /// The lines written since the last call, as text.
+    pub fn read_new(&mut self) -> String {
+        let written = self.map.position().load(Ordering::Acquire);
+        self.catch_up(written);
+        let bytes = self
+            .map
+            .read_at(self.position, (written - self.position) as usize);
+        // If the writer has started overwriting what was just copied, it may be torn. It's
+        // skipped once the writer is done and `catch_up` sees it was lapped.
+        fence(Ordering::Acquire);
+        let writing = self.map.writing().load(Ordering::Relaxed);
+        if writing.saturating_sub(self.position) > self.map.capacity() as u64 {
+            return String::new();
+        }
+        self.position = written;
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// Bytes the writer overwrote before they were read.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// If the writer has lapped the reader, skips to the first line that's still whole.
+    fn catch_up(&mut self, written: u64) {
+        let capacity = self.map.capacity() as u64;
+        if written - self.position <= capacity {
+            return;
+        }
+        let oldest = written - capacity;
+        let tail = self.map.read_at(oldest, capacity as usize);
+        let start = tail
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(capacity, |i| i as u64 + 1);
+        self.lost += oldest + start - self.position;
+        self.position = oldest + start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_round_trip_and_overrun() {
+        let path = std::env::temp_dir().join(format!("woody-shm-{}", std::process::id()));
+        let mut sink = ShmSink::create(&path, 16).unwrap();
+        let mut reader = ShmReader::open(&path).unwrap();
+        sink.write_all(b"one\ntwo\n").unwrap();
+        assert_eq!(reader.read_new(), "one\ntwo\n");
+        assert_eq!(reader.read_new(), "");
+
+        // Lapping the reader loses the oldest lines, but never leaves a partial one.
+        sink.write_all(b"three\nfour\nfive\nsix\n").unwrap();
+        assert_eq!(reader.read_new(), "four\nfive\nsix\n");
+        assert_eq!(reader.lost(), 6);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_during_write_is_not_torn() {
+        let path = std::env::temp_dir().join(format!("woody-shm-torn-{}", std::process::id()));
+        let mut sink = ShmSink::create(&path, 16).unwrap();
+        let mut reader = ShmReader::open(&path).unwrap();
+        sink.write_all(b"one\ntwo\n").unwrap();
+        assert_eq!(reader.read_new(), "one\ntwo\n");
+        sink.write_all(b"three\n").unwrap();
+
+        // A write that's copied its data over the unread "three" but not published yet.
+        let next = b"four\nfive\nsix\n";
+        sink.map
+            .writing()
+            .store(14 + next.len() as u64, Ordering::Relaxed);
+        sink.map.write_at(14, next);
+        assert_eq!(reader.read_new(), "");
+        assert_eq!(reader.read_new(), "");
+
+        sink.map
+            .position()
+            .store(14 + next.len() as u64, Ordering::Release);
+        assert_eq!(reader.read_new(), "four\nfive\nsix\n");
+        assert_eq!(reader.lost(), 6);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
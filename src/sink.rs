@@ -0,0 +1,134 @@
+//! Extra sinks attached to a running logger, like a connected debug client, that receive the
+//! same formatted records as the log file until they're removed.
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    timing::{LastWrite, Timing},
+    LogLevel,
+};
+
+/// A destination for formatted records, attached with
+/// [`Logger::add_sink`](crate::Logger::add_sink): anything that's `Write` and `Send`.
+///
+/// This contract is stable. Each `write_all` gets one or more complete records in the order they
+/// were logged, each ending with the logger's line ending, and is followed by a `flush`. An
+/// error is counted and the sink stays attached, so it may recover.
+pub trait Sink: Write + Send {}
+
+impl<W: Write + Send + ?Sized> Sink for W {}
+
+/// Identifies a sink added with [`Logger::add_sink`](crate::Logger::add_sink), to remove it
+/// later.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SinkId(u64);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+type BoxedSink = Box<dyn Write + Send>;
+
+/// The sinks of a logger, shared between its clones.
+#[derive(Clone, Default)]
+pub(crate) struct Sinks(Arc<Mutex<Vec<(SinkId, BoxedSink, LastWrite)>>>);
+
+impl Sinks {
+    pub(crate) fn add(&self, sink: BoxedSink) -> SinkId {
+        let id = SinkId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        self.lock().push((id, sink, LastWrite::default()));
+        id
+    }
+
+    /// Removes a sink, returning it (say, to shut a connection down cleanly).
+    pub(crate) fn remove(&self, id: SinkId) -> Option<BoxedSink> {
+        let mut sinks = self.lock();
+        let position = sinks.iter().position(|(sink_id, ..)| *sink_id == id)?;
+        Some(sinks.remove(position).1)
+    }
+
+    /// Writes formatted records to every sink, each with its own `delta` if `timing` asks for
+    /// one. A failing sink stays attached, since it may recover, but the failure is counted.
+    pub(crate) fn write(&self, output: &str, timing: Option<Timing>) {
+        let mut sinks = self.lock();
+        for (id, sink, last) in sinks.iter_mut() {
+            let output = last.stamp(timing, output);
+            let result = sink.write_all(output.as_bytes()).and_then(|_| sink.flush());
+            if let Err(e) = result {
+                crate::internal::event(LogLevel::Warning, || format!("sink {} failed: {e}", id.0));
+                #[cfg(feature = "metrics")]
+                crate::metrics::sink_error();
+            }
+        }
+    }
+
+    /// Flushes every sink, for [`Logger::flush`](crate::Logger::flush). Failures are counted
+    /// like those of writes.
+    pub(crate) fn flush(&self) {
+        let mut sinks = self.lock();
+        for (id, sink, _) in sinks.iter_mut() {
+            if let Err(e) = sink.flush() {
+                crate::internal::event(LogLevel::Warning, || format!("sink {} failed: {e}", id.0));
+                #[cfg(feature = "metrics")]
+                crate::metrics::sink_error();
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(SinkId, BoxedSink, LastWrite)>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl std::fmt::Debug for Sinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.lock().iter().map(|(id, ..)| id))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>, Arc<AtomicU64>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_add_and_remove_sink() {
+        let sinks = Sinks::default();
+        let out = Shared::default();
+        let id = sinks.clone().add(Box::new(out.clone()));
+        sinks.write("attached\n", None);
+        assert!(sinks.remove(id).is_some());
+        assert!(sinks.remove(id).is_none());
+        sinks.write("detached\n", None);
+        assert_eq!(*out.0.lock().unwrap(), b"attached\n");
+    }
+
+    #[test]
+    fn test_flush_reaches_every_sink() {
+        let sinks = Sinks::default();
+        let (first, second) = (Shared::default(), Shared::default());
+        sinks.add(Box::new(first.clone()));
+        sinks.add(Box::new(second.clone()));
+        sinks.flush();
+        assert_eq!(first.1.load(Ordering::Relaxed), 1);
+        assert_eq!(second.1.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,208 @@
+//! A disk-backed spill queue for sinks that can go away, like a TCP log collector, so records
+//! logged during an outage are replayed once it's back instead of being lost.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// How long to wait after a failed connection attempt before trying again.
+const DEFAULT_RETRY: Duration = Duration::from_secs(1);
+
+/// Writes records to a remote writer, spooling them to a local file while it's unavailable.
+///
+/// `connect` opens the remote writer. When it fails, or a write to it fails, records are
+/// appended to the spool file (up to `max_bytes`; records past that are dropped). On the next
+/// successful connection the spool is replayed, oldest first, before any new record.
+///
+/// Every `write` is treated as one record, which is how `Logger::write_to` writes.
+///
+/// # Examples
+/// ```no_run
+/// use std::net::TcpStream;
+/// use woody::{LogLevel, Logger, Record, Spill};
+/// # fn main() -> std::io::Result<()> {
+/// let mut collector = Spill::new(|| TcpStream::connect("logs.internal:5140"), "woody.spool", 64 << 20);
+/// let record = Record::new(LogLevel::Info, "hello", file!(), line!(), module_path!());
+/// Logger::get_instance().write_to(&record, &mut collector)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Spill<W, C> {
+    connect: C,
+    remote: Option<W>,
+    spool_path: PathBuf,
+    max_bytes: u64,
+    retry: Duration,
+    last_attempt: Option<Instant>,
+}
+
+impl<W: Write, C: FnMut() -> io::Result<W>> Spill<W, C> {
+    /// Creates a spilling writer. Nothing is connected or opened until the first write.
+    pub fn new(connect: C, spool_path: impl AsRef<Path>, max_bytes: u64) -> Self {
+        Self {
+            connect,
+            remote: None,
+            spool_path: spool_path.as_ref().to_path_buf(),
+            max_bytes,
+            retry: DEFAULT_RETRY,
+            last_attempt: None,
+        }
+    }
+
+    /// Sets how long to wait after a failed connection attempt before trying again. Records
+    /// logged in the meantime go straight to the spool.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// The number of bytes waiting in the spool.
+    pub fn spooled(&self) -> u64 {
+        std::fs::metadata(&self.spool_path).map_or(0, |m| m.len())
+    }
+
+    /// Returns the remote writer, connecting and replaying the spool if needed.
+    fn remote(&mut self) -> Option<&mut W> {
+        if self.remote.is_none() {
+            let due = self.last_attempt.is_none_or(|t| t.elapsed() >= self.retry);
+            if !due {
+                return None;
+            }
+            self.last_attempt = Some(Instant::now());
+            let mut remote = (self.connect)().ok()?;
+            self.replay(&mut remote).ok()?;
+            self.remote = Some(remote);
+        }
+        self.remote.as_mut()
+    }
+
+    /// Writes the spool to `remote` and empties it.
+    fn replay(&self, remote: &mut W) -> io::Result<()> {
+        let mut spool = match File::open(&self.spool_path) {
+            Ok(spool) => spool,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        spool.read_to_end(&mut records)?;
+        if records.is_empty() {
+            return Ok(());
+        }
+        remote.write_all(&records)?;
+        remote.flush()?;
+        // Only forget the records once they've been handed over.
+        OpenOptions::new()
+            .write(true)
+            .open(&self.spool_path)?
+            .set_len(0)
+    }
+
+    /// Appends a record to the spool, unless that would exceed `max_bytes`.
+    fn spool(&self, record: &[u8]) -> io::Result<()> {
+        let mut spool = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spool_path)?;
+        let len = spool.seek(SeekFrom::End(0))?;
+        if len + record.len() as u64 > self.max_bytes {
+            crate::internal::event(crate::LogLevel::Warning, || {
+                format!(
+                    "the spool {} is full, dropped a record",
+                    self.spool_path.display()
+                )
+            });
+            #[cfg(feature = "metrics")]
+            crate::metrics::dropped();
+            return Ok(());
+        }
+        spool.write_all(record)
+    }
+}
+
+impl<W: Write, C: FnMut() -> io::Result<W>> Write for Spill<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(remote) = self.remote() {
+            if remote.write_all(buf).is_ok() {
+                return Ok(buf.len());
+            }
+            // The connection broke; reconnect on the next write.
+            self.remote = None;
+        }
+        self.spool(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.remote {
+            Some(remote) => remote.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W, C> std::fmt::Debug for Spill<W, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Spill")
+            .field("connected", &self.remote.is_some())
+            .field("spool_path", &self.spool_path)
+            .field("max_bytes", &self.max_bytes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// A remote that's down while `up` is false.
+    struct Remote {
+        up: Rc<RefCell<bool>>,
+        received: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Write for Remote {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !*self.up.borrow() {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+            self.received.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_spill_and_replay() {
+        let path = std::env::temp_dir().join(format!("woody-spill-{}.spool", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let up = Rc::new(RefCell::new(true));
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let (connect_up, connect_received) = (up.clone(), received.clone());
+        let connect = move || match *connect_up.borrow() {
+            true => Ok(Remote {
+                up: connect_up.clone(),
+                received: connect_received.clone(),
+            }),
+            false => Err(io::ErrorKind::ConnectionRefused.into()),
+        };
+        let mut spill = Spill::new(connect, &path, 8).with_retry(Duration::ZERO);
+
+        spill.write_all(b"one\n").unwrap();
+        *up.borrow_mut() = false;
+        spill.write_all(b"two\n").unwrap();
+        spill.write_all(b"three\n").unwrap(); // Doesn't fit, so it's dropped.
+        assert_eq!(spill.spooled(), 4);
+
+        *up.borrow_mut() = true;
+        spill.write_all(b"four\n").unwrap();
+        assert_eq!(*received.borrow(), b"one\ntwo\nfour\n");
+        assert_eq!(spill.spooled(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,122 @@
+//! Times the stages of a multi-stage job and logs how long each one took.
+use std::{
+    cell::Cell,
+    panic::Location,
+    time::{Duration, Instant},
+};
+
+use crate::{Fields, LogLevel, Logger, Record};
+
+/// Starts a stopwatch named `name`. See [`Stopwatch`].
+///
+/// # Examples
+/// ```
+/// let sw = woody::stopwatch("import");
+/// // ... parse ...
+/// sw.lap("parsed"); // import: parsed lap_ms=1.2 elapsed_ms=1.2
+/// // ... store ...
+/// sw.finish(); // import: finished elapsed_ms=3.4
+/// ```
+pub fn stopwatch(name: &str) -> Stopwatch {
+    Stopwatch::new(name)
+}
+
+/// Logs the time taken by each lap and the total time at the end, as `lap_ms` and `elapsed_ms`
+/// fields in milliseconds so the durations are easy to pull out of the log.
+#[derive(Debug)]
+pub struct Stopwatch {
+    name: String,
+    level: LogLevel,
+    start: Instant,
+    last_lap: Cell<Instant>,
+}
+
+impl Stopwatch {
+    /// Starts a stopwatch that logs at the `Info` level.
+    pub fn new(name: &str) -> Self {
+        let now = Instant::now();
+        Self {
+            name: name.to_string(),
+            level: LogLevel::Info,
+            start: now,
+            last_lap: Cell::new(now),
+        }
+    }
+
+    /// Sets the level laps and the total are logged at.
+    pub fn with_level(mut self, level: LogLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// The time since the stopwatch was started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Logs the time since the previous lap (or the start) and the total so far, and returns
+    /// the lap time.
+    #[track_caller]
+    pub fn lap(&self, label: &str) -> Duration {
+        let now = Instant::now();
+        let lap = now - self.last_lap.replace(now);
+        let elapsed = now - self.start;
+        let mut fields = Fields::new();
+        fields.push("lap_ms", millis(lap));
+        fields.push("elapsed_ms", millis(elapsed));
+        self.log(format!("{}: {label}", self.name), fields);
+        lap
+    }
+
+    /// Logs the total time and returns it.
+    #[track_caller]
+    pub fn finish(self) -> Duration {
+        let elapsed = self.elapsed();
+        let mut fields = Fields::new();
+        fields.push("elapsed_ms", millis(elapsed));
+        self.log(format!("{}: finished", self.name), fields);
+        elapsed
+    }
+
+    /// Logs `message` with `fields`, attributed to whoever called the public method.
+    #[track_caller]
+    fn log(&self, message: String, fields: Fields) {
+        let caller = Location::caller();
+        let info = Record {
+            level: self.level,
+            message,
+            filepath: caller.file(),
+            line_number: caller.line(),
+            // The caller's module isn't available outside of a macro.
+            module_path: "",
+            thread: None,
+            fields,
+        };
+        Logger::get_instance().log(&info);
+    }
+}
+
+/// `duration` in milliseconds, to the microsecond.
+fn millis(duration: Duration) -> f64 {
+    (duration.as_micros() as f64) / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_laps_add_up() {
+        let sw = stopwatch("test_laps_add_up").with_level(LogLevel::Debug);
+        std::thread::sleep(Duration::from_millis(5));
+        let first = sw.lap("first");
+        std::thread::sleep(Duration::from_millis(5));
+        let second = sw.lap("second");
+        let total = sw.finish();
+
+        assert!(first >= Duration::from_millis(5));
+        assert!(second >= Duration::from_millis(5));
+        assert!(total >= first + second);
+        assert_eq!(millis(Duration::from_micros(1_234_567)), 1234.567);
+    }
+}
@@ -0,0 +1,264 @@
+//! One record built up over time from streamed output, like a compiler's, written as a single
+//! multi-line entry when the operation ends instead of hundreds of unrelated lines.
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use crate::{
+    escape,
+    memory::{self, Reservation},
+    LogLevel, Logger, Record,
+};
+
+/// What continuation lines start with in the text format. No record starts like this, so a
+/// streamed line can't pass for a record of its own.
+pub(crate) const CONTINUATION: &str = "    | ";
+
+/// Formats the lines after a record's first as continuation lines, each escaped like a message.
+pub(crate) fn continuation(rest: &str, line_ending: &str) -> String {
+    rest.split('\n')
+        .map(|line| format!("{line_ending}{CONTINUATION}{}", escape::message(line)))
+        .collect()
+}
+
+/// Collects lines of output for one record, written when the stream is dropped or finished:
+///
+/// ```text
+/// [...] [INFO] [main] [src/build.rs:12] cargo build
+///     | Compiling serde v1.0.203
+///     | Finished `release` profile [optimized] target(s) in 41.2s
+/// ```
+///
+/// Lines go in with [`RecordStream::append`], or by writing to the stream, e.g. with
+/// `io::copy` from a child process's output. Nothing is buffered if the level is filtered out,
+/// and output that would go over the memory limit is dropped. Once the lines buffered reach
+/// [`RecordStream::with_max_bytes`], they're written as a record and the stream continues in a
+/// new one with the same first line.
+///
+/// # Examples
+/// ```
+/// use woody::{LogLevel, Logger};
+/// let stream = Logger::get_instance().stream(LogLevel::Info, "cargo build");
+/// stream.append("Compiling serde v1.0.203");
+/// stream.append("Finished `release` profile");
+/// stream.finish();
+/// ```
+#[derive(Debug)]
+pub struct RecordStream {
+    logger: Logger,
+    record: Record,
+    enabled: bool,
+    /// The bytes buffered before they're written as a record of their own.
+    max_bytes: usize,
+    lines: Mutex<Lines>,
+}
+
+/// How many bytes of output a stream buffers by default before writing them.
+pub const DEFAULT_STREAM_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Default)]
+struct Lines {
+    lines: Vec<String>,
+    /// The start of a line written without its line break yet.
+    partial: Vec<u8>,
+    /// The bytes buffered.
+    bytes: usize,
+    /// The bytes buffered, counted against the memory limit.
+    reservation: Option<Reservation>,
+}
+
+impl Lines {
+    /// Counts `bytes` more output against the memory limit. Output that doesn't fit is dropped,
+    /// returning `false`.
+    fn reserve(&mut self, bytes: usize) -> bool {
+        let fits = match &mut self.reservation {
+            Some(reservation) => reservation.grow(bytes),
+            None => {
+                self.reservation = memory::reserve(bytes);
+                self.reservation.is_some()
+            }
+        };
+        match fits {
+            true => self.bytes += bytes,
+            false => crate::internal::event(LogLevel::Warning, || {
+                format!("over the memory limit, dropped {bytes} bytes of streamed output")
+            }),
+        }
+        fits
+    }
+
+    /// Takes the complete lines, or the partial one if there are none, as a message after
+    /// `first`, and stops counting them.
+    fn take(&mut self, first: &str) -> String {
+        if self.lines.is_empty() && !self.partial.is_empty() {
+            let partial = std::mem::take(&mut self.partial);
+            self.lines
+                .push(String::from_utf8_lossy(&partial).into_owned());
+        }
+        let mut message = first.to_string();
+        for line in self.lines.drain(..) {
+            message.push('\n');
+            message.push_str(line.strip_suffix('\r').unwrap_or(&line));
+        }
+        self.reservation = None;
+        self.bytes = self.partial.len();
+        self.reservation = memory::reserve(self.bytes);
+        message
+    }
+}
+
+impl RecordStream {
+    pub(crate) fn new(logger: Logger, record: Record) -> Self {
+        let level = logger.effective_level();
+        let enabled = record.level >= level && level != crate::LogLevel::Off;
+        Self {
+            logger: Logger {
+                multiline: true,
+                ..logger
+            },
+            record,
+            enabled,
+            max_bytes: DEFAULT_STREAM_BYTES,
+            lines: Mutex::default(),
+        }
+    }
+
+    /// Sets how many bytes of output are buffered before they're written as a record of their
+    /// own. Defaults to [`DEFAULT_STREAM_BYTES`].
+    pub fn with_max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Adds a line, or several separated by line breaks.
+    pub fn append(&self, text: &str) {
+        if !self.enabled {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if !lines.reserve(text.len()) {
+            return;
+        }
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        lines.lines.extend(text.split('\n').map(str::to_string));
+        self.write_if_full(&mut lines);
+    }
+
+    /// Writes the record with every line appended so far.
+    pub fn finish(self) {}
+
+    /// The record's message: its first line, then the appended ones.
+    fn message(&self) -> String {
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        let partial = std::mem::take(&mut lines.partial);
+        if !partial.is_empty() {
+            lines
+                .lines
+                .push(String::from_utf8_lossy(&partial).into_owned());
+        }
+        lines.take(&self.record.message)
+    }
+
+    /// Writes the lines buffered as a record if they've reached the cap.
+    fn write_if_full(&self, lines: &mut Lines) {
+        if lines.bytes >= self.max_bytes {
+            let record = Record {
+                message: lines.take(&self.record.message),
+                ..self.record.clone()
+            };
+            self.logger.log(&record);
+        }
+    }
+}
+
+impl Write for RecordStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.enabled {
+            return Ok(buf.len());
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if !lines.reserve(buf.len()) {
+            return Ok(buf.len());
+        }
+        lines.partial.extend_from_slice(buf);
+        if let Some(end) = lines.partial.iter().rposition(|b| *b == b'\n') {
+            let complete: Vec<u8> = lines.partial.drain(..=end).collect();
+            let text = String::from_utf8_lossy(&complete[..end]);
+            lines.lines.extend(text.split('\n').map(str::to_string));
+        }
+        self.write_if_full(&mut lines);
+        Ok(buf.len())
+    }
+
+    /// Lines are kept until the stream finishes, so this does nothing.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecordStream {
+    fn drop(&mut self) {
+        if self.enabled {
+            let record = Record {
+                message: self.message(),
+                ..self.record.clone()
+            };
+            self.logger.log(&record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LogLevel;
+
+    #[test]
+    fn test_stream_renders_continuation_lines() {
+        let record = Record::new(LogLevel::Fatal, "cargo build", file!(), line!(), "");
+        let mut stream = RecordStream::new(Logger::get_instance(), record);
+        stream.append("Compiling a\nCompiling b");
+        stream
+            .write_all(b"warning: unused\r\n[forged] \x1b[1mline")
+            .unwrap();
+        let message = stream.message();
+        assert_eq!(
+            message,
+            "cargo build\nCompiling a\nCompiling b\nwarning: unused\n[forged] \x1b[1mline"
+        );
+
+        let mut out = Vec::new();
+        let record = Record::new(LogLevel::Fatal, message, file!(), line!(), "");
+        stream.logger.write_to(&record, &mut out).unwrap();
+        stream.enabled = false;
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.ends_with(
+                "cargo build\n    | Compiling a\n    | Compiling b\n    | warning: unused\n    \
+                 | [forged] \\u{1b}[1mline\n"
+            ),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn test_stream_continues_in_a_new_record_past_the_cap() {
+        let f = "test_stream_continues_in_a_new_record_past_the_cap";
+        let record = Record::new(LogLevel::Fatal, f, file!(), line!(), "");
+        let mut stream = RecordStream::new(Logger::get_instance(), record).with_max_bytes(10);
+        stream.append("first");
+        stream.write_all(b"second\nthi").unwrap();
+        #[cfg(feature = "file")]
+        let contents = std::fs::read_to_string(Logger::get_instance().filename()).unwrap();
+        #[cfg(feature = "file")]
+        assert!(
+            contents.contains(&format!("{f}\n    | first\n    | second\n")),
+            "{contents}"
+        );
+        assert_eq!(stream.lines.lock().unwrap().bytes, 3);
+        stream.write_all(b"rd").unwrap();
+        assert_eq!(stream.message(), format!("{f}\nthird"));
+        stream.enabled = false;
+    }
+}
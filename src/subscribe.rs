@@ -0,0 +1,94 @@
+//! Live streams of records for application code, like an in-app log viewer, independent of
+//! where the logger writes.
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, SyncSender, TrySendError},
+    Mutex,
+};
+
+use crate::Record;
+
+/// How many records a subscriber can fall behind by before new ones are dropped, by default.
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 1024;
+
+static SUBSCRIBERS: Mutex<Vec<SyncSender<Record>>> = Mutex::new(Vec::new());
+/// Whether there are any subscribers, so logging doesn't take the lock when there are none.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Returns a receiver of every record logged from now on that passes the logger's filters.
+///
+/// Buffering is bounded: a subscriber that falls more than [`DEFAULT_SUBSCRIBER_CAPACITY`]
+/// records behind misses new ones until it catches up, so a stalled viewer can't stall the
+/// application. Dropping the receiver unsubscribes.
+///
+/// # Examples
+/// ```
+/// let records = woody::subscribe();
+/// woody::log_info!("subscribed");
+/// let record = records.recv().unwrap();
+/// assert!(record.message.contains("subscribed"));
+/// ```
+pub fn subscribe() -> Receiver<Record> {
+    subscribe_with_capacity(DEFAULT_SUBSCRIBER_CAPACITY)
+}
+
+/// Like [`subscribe`], buffering up to `capacity` records. A capacity of 0 buffers one, since
+/// records are only ever sent without waiting for the subscriber, so without a buffer none would
+/// arrive.
+pub fn subscribe_with_capacity(capacity: usize) -> Receiver<Record> {
+    let (sender, receiver) = mpsc::sync_channel(capacity.max(1));
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner());
+    subscribers.push(sender);
+    ACTIVE.store(true, Ordering::Release);
+    receiver
+}
+
+/// Sends a record to every subscriber, forgetting those that are gone.
+pub(crate) fn publish(record: &Record) {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner());
+    send(&mut subscribers, record);
+    ACTIVE.store(!subscribers.is_empty(), Ordering::Release);
+}
+
+fn send(subscribers: &mut Vec<SyncSender<Record>>, record: &Record) {
+    subscribers.retain(|sender| match sender.try_send(record.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            crate::internal::event(crate::LogLevel::Warning, || {
+                "a subscriber is full, dropped a record".to_string()
+            });
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LogLevel;
+
+    #[test]
+    fn test_bounded_and_unsubscribes() {
+        let record = |message: &str| Record::new(LogLevel::Info, message, file!(), line!(), "");
+        let (sender, records) = mpsc::sync_channel(2);
+        let (gone, _) = mpsc::sync_channel(2);
+        let mut subscribers = vec![sender, gone];
+        for message in ["one", "two", "three"] {
+            send(&mut subscribers, &record(message));
+        }
+        assert_eq!(subscribers.len(), 1);
+        let received: Vec<String> = records.try_iter().map(|r| r.message).collect();
+        assert_eq!(received, ["one", "two"]);
+    }
+
+    #[test]
+    fn test_zero_capacity_still_receives() {
+        let records = subscribe_with_capacity(0);
+        publish(&Record::new(LogLevel::Info, "one", file!(), line!(), ""));
+        assert!(records.try_recv().is_ok());
+    }
+}
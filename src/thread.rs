@@ -0,0 +1,125 @@
+//! Threads whose panics end up in the log, even when nobody joins them.
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe, Location},
+    thread::JoinHandle,
+};
+
+use crate::{context, LogLevel, Logger, Record};
+
+/// Spawns a thread named `name` that starts with the current thread's context, like
+/// [`context::Builder`]. If it panics, an `ERROR` record with the panic message in its `panic`
+/// field is logged from the thread, attributed to where it was spawned. The panic then carries
+/// on as usual, so joining the handle still returns it.
+///
+/// The record has no module path, so source filters by module (`allow=<crate>`) drop it. The
+/// [`spawn_logged!`](crate::spawn_logged!) macro fills it in.
+///
+/// # Panics
+/// Panics if the thread can't be spawned, like `std::thread::spawn`.
+///
+/// # Examples
+/// ```
+/// let worker = woody::thread::spawn_logged("importer", || {
+///     let rows: Vec<u32> = Vec::new();
+///     rows[0] // logs "thread 'importer' panicked panic=\"index out of bounds: ...\""
+/// });
+/// assert!(worker.join().is_err());
+/// ```
+#[track_caller]
+pub fn spawn_logged<F, T>(name: impl Into<String>, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_logged_in("", name, f)
+}
+
+/// Like [`spawn_logged`], with the panic record attributed to `module_path`. Used by
+/// [`spawn_logged!`](crate::spawn_logged!).
+#[track_caller]
+pub fn spawn_logged_in<F, T>(
+    module_path: &'static str,
+    name: impl Into<String>,
+    f: F,
+) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let name = name.into();
+    let spawned_at = Location::caller();
+    context::Builder::new()
+        .name(name.clone())
+        .spawn(move || match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => value,
+            Err(payload) => {
+                let record = Record::new(
+                    LogLevel::Error,
+                    format!("thread '{name}' panicked"),
+                    spawned_at.file(),
+                    spawned_at.line(),
+                    module_path,
+                )
+                .with_field("panic", message(payload.as_ref()));
+                Logger::get_instance().log(&record);
+                panic::resume_unwind(payload)
+            }
+        })
+        .expect("failed to spawn thread")
+}
+
+/// Spawns a thread like [`thread::spawn_logged`](crate::thread::spawn_logged), with its panic
+/// record attributed to the calling module, so source filters by module apply to it.
+///
+/// # Examples
+/// ```
+/// let worker = woody::spawn_logged!("importer", || {
+///     let rows: Vec<u32> = Vec::new();
+///     rows[0]
+/// });
+/// assert!(worker.join().is_err());
+/// ```
+#[macro_export]
+macro_rules! spawn_logged {
+    ($name:expr, $f:expr) => {
+        $crate::thread::spawn_logged_in(module_path!(), $name, $f)
+    };
+}
+
+/// The message of a panic, if it was a string (which it is for `panic!` with a message).
+fn message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&'static str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "Box<dyn Any>".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("disk {} full", 1)).unwrap_err();
+        assert_eq!(message(payload.as_ref()), "disk 1 full");
+        let payload = panic::catch_unwind(|| panic::panic_any(42)).unwrap_err();
+        assert_eq!(message(payload.as_ref()), "Box<dyn Any>");
+    }
+
+    #[test]
+    fn test_macro_attributes_the_panic_to_the_module() {
+        let records = crate::subscribe();
+        let worker = crate::spawn_logged!("test_macro_attributes", || panic!("boom"));
+        assert!(worker.join().is_err());
+        let record = records
+            .try_iter()
+            .find(|record| record.message == "thread 'test_macro_attributes' panicked")
+            .unwrap();
+        assert_eq!(record.module_path, module_path!());
+        assert_eq!(record.line_number, line!() - 7);
+    }
+}
@@ -0,0 +1,132 @@
+//! `elapsed` and `delta` fields, for reading durations straight off the log instead of
+//! subtracting timestamps by hand.
+use std::{
+    borrow::Cow,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// When the logger was created, which `elapsed` counts from.
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Marks the start `elapsed` counts from, if it isn't marked yet.
+pub(crate) fn start() {
+    START.get_or_init(Instant::now);
+}
+
+/// Which timing fields to append to every record.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Timing {
+    /// Time since the logger was created.
+    pub(crate) elapsed: bool,
+    /// Time since the previous record written to the same sink.
+    pub(crate) delta: bool,
+}
+
+impl Timing {
+    /// Parses `WOODY_TIMING`, e.g. `elapsed,delta`. `None` if neither is asked for.
+    pub(crate) fn from_config() -> Option<Self> {
+        let setting = crate::config::setting("WOODY_TIMING")?;
+        let mut timing = Timing::default();
+        for token in setting.split(',') {
+            match token.trim().to_lowercase().as_str() {
+                "elapsed" => timing.elapsed = true,
+                "delta" => timing.delta = true,
+                _ => {}
+            }
+        }
+        (timing.elapsed || timing.delta).then_some(timing)
+    }
+
+    /// Adds the fields to every record in `output`, which is about to be written to the sink
+    /// whose previous record was written at `last`. JSON records get them as `elapsed_ms` and
+    /// `delta_ms` numbers.
+    fn stamp(self, output: &str, last: &mut Option<Instant>) -> String {
+        let now = Instant::now();
+        crate::json::append(output, |json| {
+            let mut fields = Vec::new();
+            if self.elapsed {
+                let elapsed = now.saturating_duration_since(*START.get_or_init(Instant::now));
+                fields.push(field(("elapsed", "elapsed_ms"), elapsed, json));
+            }
+            if let (true, Some(last)) = (self.delta, *last) {
+                let delta = now.saturating_duration_since(last);
+                fields.push(field(("delta", "delta_ms"), delta, json));
+            }
+            *last = Some(now);
+            fields
+        })
+    }
+}
+
+/// A duration field under its text or JSON key, e.g. `elapsed=1.2ms` or `"elapsed_ms":1.2`.
+fn field(
+    (text, json_key): (&'static str, &'static str),
+    duration: Duration,
+    json: bool,
+) -> (&'static str, String) {
+    match json {
+        true => (json_key, (duration.as_micros() as f64 / 1000.0).to_string()),
+        false => (text, format!("{duration:?}")),
+    }
+}
+
+/// When the previous record was written to one sink.
+#[derive(Debug, Default)]
+pub(crate) struct LastWrite(Mutex<Option<Instant>>);
+
+impl LastWrite {
+    /// Stamps `output` with `timing`'s fields, if any.
+    pub(crate) fn stamp<'a>(&self, timing: Option<Timing>, output: &'a str) -> Cow<'a, str> {
+        match timing {
+            Some(timing) => {
+                let mut last = self.0.lock().unwrap_or_else(|e| e.into_inner());
+                Cow::Owned(timing.stamp(output, &mut last))
+            }
+            None => Cow::Borrowed(output),
+        }
+    }
+}
+
+/// The previous writes of the built-in sinks, which each have their own `delta`.
+#[derive(Debug, Default)]
+pub(crate) struct SinkTimes {
+    #[cfg(feature = "file")]
+    pub(crate) file: LastWrite,
+    pub(crate) console: LastWrite,
+    #[cfg(feature = "file")]
+    pub(crate) error_file: LastWrite,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stamp_lines() {
+        let timing = Timing {
+            elapsed: false,
+            delta: true,
+        };
+        let mut last = None;
+        let first = timing.stamp("one\r\n", &mut last);
+        assert_eq!(first, "one\r\n");
+        let second = timing.stamp("two\nthree\n", &mut last);
+        let lines: Vec<&str> = second.lines().collect();
+        assert!(lines[0].starts_with("two delta="), "{second}");
+        assert!(lines[1].starts_with("three delta="), "{second}");
+        assert!(second.ends_with('\n'));
+
+        let timing = Timing {
+            elapsed: true,
+            delta: false,
+        };
+        assert!(timing.stamp("four\n", &mut last).contains(" elapsed="));
+        let json = timing.stamp("{\"message\":\"five\"}\n", &mut last);
+        assert!(
+            json.starts_with("{\"message\":\"five\",\"elapsed_ms\":"),
+            "{json}"
+        );
+        assert!(json.ends_with("}\n"), "{json}");
+    }
+}
@@ -0,0 +1,148 @@
+//! The data behind a "logs" tab in a GUI application: a capped buffer of recent records fed by
+//! [`subscribe`](crate::subscribe), filtered by level and text. It doesn't draw anything, so it
+//! works with egui, iced, or any other toolkit.
+//!
+//! # Examples
+//! ```
+//! use woody::{viewer::LogView, LogLevel};
+//! let mut view = LogView::new(10_000);
+//! woody::log_warning!("disk almost full");
+//!
+//! // Every frame:
+//! view.poll();
+//! view.set_min_level(LogLevel::Warning);
+//! view.set_query("disk");
+//! for record in view.visible() {
+//!     // e.g. `ui.label(&record.message)` in egui
+//! }
+//! ```
+use std::{collections::VecDeque, sync::mpsc::Receiver};
+
+use crate::{LogLevel, Record};
+
+/// A filtered, capped, searchable view of the records logged since it was created.
+#[derive(Debug)]
+pub struct LogView {
+    receiver: Receiver<Record>,
+    records: VecDeque<Record>,
+    capacity: usize,
+    min_level: LogLevel,
+    /// Lowercased search text.
+    query: String,
+}
+
+impl LogView {
+    /// Creates a view keeping the last `capacity` records. With a capacity of 0, it keeps
+    /// none.
+    pub fn new(capacity: usize) -> Self {
+        Self::from_receiver(crate::subscribe(), capacity)
+    }
+
+    fn from_receiver(receiver: Receiver<Record>, capacity: usize) -> Self {
+        Self {
+            receiver,
+            records: VecDeque::with_capacity(capacity.min(4096)),
+            capacity,
+            min_level: LogLevel::ALL,
+            query: String::new(),
+        }
+    }
+
+    /// Takes in records logged since the last call, dropping the oldest past the capacity.
+    /// Returns whether anything arrived, i.e. whether to redraw.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for record in self.receiver.try_iter() {
+            changed = true;
+            if self.capacity == 0 {
+                continue;
+            }
+            while self.records.len() >= self.capacity {
+                self.records.pop_front();
+            }
+            self.records.push_back(record);
+        }
+        changed
+    }
+
+    /// Only show records at or above `level`.
+    pub fn set_min_level(&mut self, level: LogLevel) {
+        self.min_level = level;
+    }
+
+    /// Only show records whose message or fields contain `query`, ignoring case. An empty
+    /// query shows everything.
+    pub fn set_query(&mut self, query: &str) {
+        self.query = query.to_lowercase();
+    }
+
+    /// The records that pass the filters, oldest first.
+    pub fn visible(&self) -> impl Iterator<Item = &Record> + '_ {
+        self.records.iter().filter(|record| self.matches(record))
+    }
+
+    /// All buffered records, ignoring the filters.
+    pub fn records(&self) -> &VecDeque<Record> {
+        &self.records
+    }
+
+    /// Forgets every buffered record.
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        if record.level < self.min_level {
+            return false;
+        }
+        if self.query.is_empty() {
+            return true;
+        }
+        let contains = |s: &str| s.to_lowercase().contains(&self.query);
+        contains(&record.message)
+            || record
+                .fields
+                .iter()
+                .any(|(key, value)| contains(key) || contains(&value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_filter_and_cap() {
+        let (sender, receiver) = mpsc::channel();
+        let mut view = LogView::from_receiver(receiver, 3);
+        let record = |level, message: &str| Record::new(level, message, file!(), line!(), "");
+        sender.send(record(LogLevel::Debug, "connecting")).unwrap();
+        sender.send(record(LogLevel::Info, "Disk ok")).unwrap();
+        sender.send(record(LogLevel::Error, "disk full")).unwrap();
+        sender
+            .send(record(LogLevel::Warning, "slow").with_field("device", "disk0"))
+            .unwrap();
+        assert!(view.poll());
+        assert!(!view.poll());
+        assert_eq!(view.records().len(), 3);
+
+        view.set_query("DISK");
+        let visible: Vec<&str> = view.visible().map(|r| r.message.as_str()).collect();
+        assert_eq!(visible, ["Disk ok", "disk full", "slow"]);
+
+        view.set_min_level(LogLevel::Warning);
+        assert_eq!(view.visible().count(), 2);
+    }
+
+    #[test]
+    fn test_zero_capacity_keeps_nothing() {
+        let (sender, receiver) = mpsc::channel();
+        let mut view = LogView::from_receiver(receiver, 0);
+        let record = Record::new(LogLevel::Info, "dropped", file!(), line!(), "");
+        sender.send(record.clone()).unwrap();
+        sender.send(record).unwrap();
+        assert!(view.poll());
+        assert!(view.records().is_empty());
+    }
+}
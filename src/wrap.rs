@@ -0,0 +1,427 @@
+//! Wrappers that add a capability to any sink, e.g. one passed to
+//! [`Logger::add_sink`](crate::Logger::add_sink), instead of each sink implementing it. They
+//! nest, and records pass through the outermost first:
+//!
+//! ```
+//! use woody::wrap::{RateLimit, Sign};
+//! let sink = RateLimit::new(Sign::new(Vec::new(), b"signing key"), 100);
+//! woody::Logger::get_instance().add_sink(sink);
+//! ```
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "encrypt")]
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305,
+};
+
+use crate::crypto;
+
+/// The length of the tag [`Encrypt`] adds to every frame.
+#[cfg(feature = "encrypt")]
+const TAG: usize = 16;
+
+/// The size of the blocks [`Compress`] compresses, by default.
+#[cfg(feature = "compress")]
+pub const DEFAULT_COMPRESS_BLOCK: usize = 64 << 10;
+
+/// Gzips records in blocks of about `block` bytes, each a gzip member of its own, so the output
+/// is a valid `.gz` stream that `gzip -d` and `woody cat` read. Compresses in-process, without
+/// spawning anything.
+///
+/// A partial block is compressed when the wrapper is dropped or [finished](Compress::finish),
+/// not on flush, since compressing every record on its own would barely shrink it.
+#[cfg(feature = "compress")]
+#[derive(Debug)]
+pub struct Compress<W: Write> {
+    sink: Option<W>,
+    block: usize,
+    buffer: Vec<u8>,
+}
+
+#[cfg(feature = "compress")]
+impl<W: Write> Compress<W> {
+    /// Compresses blocks of [`DEFAULT_COMPRESS_BLOCK`] bytes.
+    pub fn new(sink: W) -> Self {
+        Self::with_block_size(sink, DEFAULT_COMPRESS_BLOCK)
+    }
+
+    /// Compresses blocks of `block` bytes.
+    pub fn with_block_size(sink: W, block: usize) -> Self {
+        Self {
+            sink: Some(sink),
+            block,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Compresses what's left and returns the sink.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress()?;
+        Ok(self.sink.take().expect("only taken here"))
+    }
+
+    fn compress(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let input = std::mem::take(&mut self.buffer);
+        let out = Vec::with_capacity(input.len() / 4);
+        let mut encoder = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+        encoder.write_all(&input)?;
+        let output = encoder.finish()?;
+        let sink = self.sink.as_mut().expect("only taken by finish");
+        sink.write_all(&output)?;
+        sink.flush()
+    }
+}
+
+#[cfg(feature = "compress")]
+impl<W: Write> Write for Compress<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= self.block {
+            self.compress()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.as_mut().map_or(Ok(()), |sink| sink.flush())
+    }
+}
+
+#[cfg(feature = "compress")]
+impl<W: Write> Drop for Compress<W> {
+    fn drop(&mut self) {
+        if self.sink.is_some() {
+            let _ = self.compress();
+        }
+    }
+}
+
+/// Encrypts records with ChaCha20-Poly1305 under a 256-bit key. Every write becomes a frame: a
+/// 12-byte nonce, the length of the rest as a big-endian `u32`, and the ciphertext with its
+/// 16-byte tag. Read the frames back with [`decrypt`].
+///
+/// # Threat model
+/// This protects records from someone who can read or change what reaches the sink (a log
+/// shipper, a shared disk, a backup) but doesn't have the key: they learn nothing but the
+/// length and number of the writes, and any frame they change or forge fails to decrypt.
+/// Whole frames can still be dropped, replayed, or reordered, and the end of the log can be
+/// cut off; wrap this in [`Sign`] to have every line signed before it's encrypted, and check
+/// the chain after decrypting to detect that. It doesn't protect the key itself, which is kept in the process's memory, nor
+/// the records before they're encrypted, e.g. in the log file or on the console.
+///
+/// Each wrapper picks a random nonce prefix from the operating system's generator and counts
+/// frames after it, so nonces don't repeat under one key for any practical number of
+/// wrappers.
+#[cfg(feature = "encrypt")]
+pub struct Encrypt<W: Write> {
+    sink: W,
+    cipher: ChaCha20Poly1305,
+    /// Random, so two wrappers with the same key don't reuse nonces.
+    stream: [u8; 8],
+    frame: u32,
+}
+
+#[cfg(feature = "encrypt")]
+impl<W: Write> Encrypt<W> {
+    /// Encrypts with `key`, which should come from a secret store rather than the source.
+    pub fn new(sink: W, key: [u8; 32]) -> Self {
+        Self {
+            sink,
+            cipher: ChaCha20Poly1305::new(&key.into()),
+            stream: new_stream(),
+            frame: 0,
+        }
+    }
+}
+
+/// A random 8-byte nonce prefix.
+#[cfg(feature = "encrypt")]
+fn new_stream() -> [u8; 8] {
+    let mut stream = [0; 8];
+    OsRng.fill_bytes(&mut stream);
+    stream
+}
+
+#[cfg(feature = "encrypt")]
+impl<W: Write> Write for Encrypt<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.frame == u32::MAX {
+            self.stream = new_stream();
+            self.frame = 0;
+        }
+        let mut nonce = [0; 12];
+        nonce[..8].copy_from_slice(&self.stream);
+        nonce[8..].copy_from_slice(&self.frame.to_be_bytes());
+        self.frame += 1;
+
+        let len = u32::try_from(buf.len() + TAG)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "write too large"))?;
+        let payload = Payload {
+            msg: buf,
+            aad: &len.to_be_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce.into(), payload)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        let mut frame = Vec::with_capacity(16 + ciphertext.len());
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend_from_slice(&ciphertext);
+        self.sink.write_all(&frame)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+#[cfg(feature = "encrypt")]
+impl<W: Write> std::fmt::Debug for Encrypt<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encrypt").finish_non_exhaustive()
+    }
+}
+
+/// Decrypts the frames written by [`Encrypt`].
+///
+/// # Errors
+/// Fails if the data ends in the middle of a frame, or a frame was changed or encrypted under
+/// another key.
+#[cfg(feature = "encrypt")]
+pub fn decrypt(key: &[u8; 32], mut data: &[u8]) -> io::Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(&(*key).into());
+    let mut plaintext = Vec::with_capacity(data.len());
+    while !data.is_empty() {
+        let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame");
+        let header = data.get(..16).ok_or_else(truncated)?;
+        let nonce: [u8; 12] = header[..12].try_into().expect("12 bytes");
+        let len = u32::from_be_bytes(header[12..].try_into().expect("4 bytes")) as usize;
+        let payload = Payload {
+            msg: data.get(16..16 + len).ok_or_else(truncated)?,
+            aad: &header[12..],
+        };
+        let frame = cipher.decrypt(&nonce.into(), payload).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "frame failed authentication")
+        })?;
+        plaintext.extend_from_slice(&frame);
+        data = &data[16 + len..];
+    }
+    Ok(plaintext)
+}
+
+/// Signs every line with HMAC-SHA256, appending ` sig=<hex>`. Each signature covers the
+/// previous one too, so removed, reordered, or edited lines all break the chain from there on.
+/// Check a signed log with [`verify`].
+pub struct Sign<W: Write> {
+    sink: W,
+    key: Vec<u8>,
+    previous: [u8; 32],
+    /// The start of a line whose end hasn't been written yet.
+    partial: Vec<u8>,
+}
+
+impl<W: Write> Sign<W> {
+    /// Signs with `key`, starting a new chain.
+    pub fn new(sink: W, key: &[u8]) -> Self {
+        Self {
+            sink,
+            key: key.to_vec(),
+            previous: [0; 32],
+            partial: Vec::new(),
+        }
+    }
+}
+
+/// The signature of a line, chained to the previous line's.
+fn signature(key: &[u8], previous: &[u8; 32], line: &[u8]) -> [u8; 32] {
+    let mut message = previous.to_vec();
+    message.extend_from_slice(line);
+    crypto::hmac_sha256(key, &message)
+}
+
+impl<W: Write> Write for Sign<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(buf.len());
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        let mut out = Vec::with_capacity(complete.len() + 80);
+        for line in complete.split_inclusive(|&b| b == b'\n') {
+            let record = line.strip_suffix(b"\n").unwrap_or(line);
+            let record = record.strip_suffix(b"\r").unwrap_or(record);
+            self.previous = signature(&self.key, &self.previous, record);
+            out.extend_from_slice(record);
+            out.extend_from_slice(b" sig=");
+            out.extend_from_slice(crypto::hex(&self.previous).as_bytes());
+            out.extend_from_slice(&line[record.len()..]);
+        }
+        self.sink.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+impl<W: Write> std::fmt::Debug for Sign<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sign").finish_non_exhaustive()
+    }
+}
+
+/// Checks the signatures written by [`Sign`], returning the number (from 1) of the first line
+/// whose signature doesn't match.
+pub fn verify(key: &[u8], signed: &str) -> Result<(), usize> {
+    let mut previous = [0; 32];
+    for (number, line) in signed.lines().enumerate() {
+        let (record, sig) = line.rsplit_once(" sig=").ok_or(number + 1)?;
+        previous = signature(key, &previous, record.as_bytes());
+        if crypto::hex(&previous) != sig {
+            return Err(number + 1);
+        }
+    }
+    Ok(())
+}
+
+/// Passes at most `per_second` lines a second to the sink and drops the rest, so a burst of
+/// records can't swamp a slow or metered destination. How many were dropped is noted in a
+/// line of its own once lines are let through again.
+#[derive(Debug)]
+pub struct RateLimit<W: Write> {
+    sink: W,
+    per_second: u32,
+    window: Instant,
+    passed: u32,
+    dropped: u64,
+}
+
+impl<W: Write> RateLimit<W> {
+    /// Lets `per_second` lines through each second.
+    pub fn new(sink: W, per_second: u32) -> Self {
+        Self {
+            sink,
+            per_second,
+            window: Instant::now(),
+            passed: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for RateLimit<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        if now.duration_since(self.window) >= Duration::from_secs(1) {
+            self.window = now;
+            self.passed = 0;
+        }
+        let mut out = Vec::with_capacity(buf.len());
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            if self.passed >= self.per_second {
+                self.dropped += 1;
+                continue;
+            }
+            if self.dropped > 0 {
+                let note = format!("[woody: rate limit] {} records dropped\n", self.dropped);
+                out.extend_from_slice(note.as_bytes());
+                self.dropped = 0;
+            }
+            out.extend_from_slice(line);
+            self.passed += 1;
+        }
+        if !out.is_empty() {
+            self.sink.write_all(&out)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        let mut sink = Sign::new(Vec::new(), b"secret");
+        sink.write_all(b"[INFO] one\n[INFO] tw").unwrap();
+        sink.write_all(b"o\n").unwrap();
+        let plaintext = String::from_utf8(sink.sink).unwrap();
+        assert!(plaintext.starts_with("[INFO] one sig="), "{plaintext}");
+        assert_eq!(verify(b"secret", &plaintext), Ok(()));
+        assert_eq!(verify(b"wrong", &plaintext), Err(1));
+        let tampered = plaintext.replacen("one", "uno", 1);
+        assert_eq!(verify(b"secret", &tampered), Err(1));
+        let reordered: Vec<&str> = plaintext.lines().rev().collect();
+        assert_eq!(verify(b"secret", &reordered.join("\n")), Err(1));
+    }
+
+    #[cfg(feature = "encrypt")]
+    #[test]
+    fn test_sign_then_encrypt() {
+        let key = [3; 32];
+        let mut sink = Sign::new(Encrypt::new(Vec::new(), key), b"secret");
+        sink.write_all(b"[INFO] one\n").unwrap();
+        sink.write_all(b"[INFO] two\n").unwrap();
+        let encrypted = sink.sink.sink.clone();
+        assert!(!encrypted.windows(3).any(|w| w == b"one"));
+
+        let plaintext = String::from_utf8(decrypt(&key, &encrypted).unwrap()).unwrap();
+        assert!(plaintext.starts_with("[INFO] one sig="), "{plaintext}");
+        assert_eq!(verify(b"secret", &plaintext), Ok(()));
+        assert!(decrypt(&key, &encrypted[..encrypted.len() - 1]).is_err());
+        assert!(decrypt(&[4; 32], &encrypted).is_err());
+
+        let mut tampered = encrypted.clone();
+        tampered[20] ^= 1;
+        let error = decrypt(&key, &tampered).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // Two wrappers under one key never share a nonce.
+        let other = Encrypt::new(Vec::new(), key);
+        assert_ne!(other.stream, sink.sink.stream);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limited = RateLimit::new(Vec::new(), 2);
+        limited.write_all(b"a\nb\nc\nd\n").unwrap();
+        assert_eq!(limited.sink, b"a\nb\n");
+        limited.window -= Duration::from_secs(1);
+        limited.write_all(b"e\n").unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&limited.sink),
+            "a\nb\n[woody: rate limit] 2 records dropped\ne\n"
+        );
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn test_compress() {
+        use std::io::Read;
+        let mut compressed = Compress::with_block_size(Vec::new(), 8);
+        compressed.write_all(b"first block\n").unwrap();
+        compressed.write_all(b"rest\n").unwrap();
+        let gz = compressed.finish().unwrap();
+        // Two blocks, so two gzip members.
+        assert_eq!(gz.windows(3).filter(|w| w == &[0x1f, 0x8b, 8]).count(), 2);
+        let mut plain = String::new();
+        flate2::read::MultiGzDecoder::new(&gz[..])
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, "first block\nrest\n");
+    }
+}
@@ -0,0 +1,453 @@
+//! Where formatted records end up: either written directly under a lock, or handed off to a
+//! dedicated writer thread.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    index::IndexWriter,
+    memory::{self, Reservation},
+    Durability, LogLevel,
+};
+
+/// How long the writer thread waits for new records before flushing, by default.
+pub(crate) const DEFAULT_IDLE_FLUSH: Duration = Duration::from_millis(200);
+
+/// The log file along with the bookkeeping needed to honor the durability setting.
+#[derive(Debug)]
+pub(crate) struct LogFile {
+    writer: BufWriter<File>,
+    /// Records written since the last `sync_data()`.
+    unsynced: u32,
+    /// Whether the buffer holds records that haven't been flushed yet.
+    dirty: bool,
+    /// The sidecar index, with `WOODY_INDEX`.
+    index: Option<IndexWriter>,
+    /// The sequence number of the next record, with `WOODY_SEQUENCE`.
+    sequence: Option<u64>,
+}
+
+impl LogFile {
+    pub(crate) fn new(file: File, index: Option<IndexWriter>) -> Self {
+        Self {
+            writer: BufWriter::new(file),
+            unsynced: 0,
+            dirty: false,
+            index,
+            sequence: None,
+        }
+    }
+
+    /// Numbers every record written from now on with a `seq=N` field, or a `seq` key in
+    /// MessagePack. Numbers are assigned while holding the file (or in the writer thread, in
+    /// queue order), so they always increase down the file.
+    pub(crate) fn numbered(mut self) -> Self {
+        self.sequence = Some(0);
+        self
+    }
+
+    /// Writes `records` formatted records, the most severe at `level`, then flushes and syncs
+    /// according to `durability`. `lines` says whether they're lines of text or MessagePack
+    /// frames, which are numbered differently.
+    pub(crate) fn write_records(
+        &mut self,
+        output: &[u8],
+        records: u32,
+        level: LogLevel,
+        durability: Durability,
+        lines: bool,
+    ) -> io::Result<()> {
+        let numbered;
+        let output = match &mut self.sequence {
+            Some(next) if lines => {
+                numbered = number(output, next);
+                &numbered
+            }
+            Some(next) => {
+                numbered = crate::msgpack::append_key(output, "seq", || {
+                    *next += 1;
+                    *next - 1
+                });
+                &numbered
+            }
+            None => output,
+        };
+        self.writer.write_all(output)?;
+        self.dirty = true;
+        if let Some(index) = &mut self.index {
+            index.record(output.len(), level)?;
+        }
+        match durability {
+            Durability::None => Ok(()),
+            Durability::Flush => self.flush(),
+            Durability::Fsync => self.sync(),
+            Durability::FsyncEveryN(n) => {
+                self.unsynced += records;
+                if self.unsynced >= n {
+                    self.sync()
+                } else {
+                    self.flush()
+                }
+            }
+        }
+    }
+
+    /// Switches to another file, flushing the buffer to the old one first. Records keep their
+    /// sequence numbers going.
+    pub(crate) fn replace(&mut self, file: File, index: Option<IndexWriter>) -> io::Result<()> {
+        let flushed = self.flush();
+        self.writer = BufWriter::new(file);
+        self.index = index;
+        self.unsynced = 0;
+        flushed
+    }
+
+    /// The file descriptor of the file.
+    #[cfg(unix)]
+    pub(crate) fn raw_fd(&self) -> std::os::fd::RawFd {
+        std::os::fd::AsRawFd::as_raw_fd(self.writer.get_ref())
+    }
+
+    /// Records written but not yet flushed to the OS.
+    #[cfg(unix)]
+    pub(crate) fn buffer(&self) -> &[u8] {
+        self.writer.buffer()
+    }
+
+    /// Flushes the buffer to the OS.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Flushes the buffer and syncs the file contents to disk.
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// Numbers every record in `output` with a `seq` field, counting up from `next`: a ` seq=N`
+/// for text records and a `"seq"` key for JSON ones.
+fn number(output: &[u8], next: &mut u64) -> Vec<u8> {
+    let Ok(output) = std::str::from_utf8(output) else {
+        return output.to_vec();
+    };
+    let numbered = crate::json::append(output, |_| {
+        *next += 1;
+        vec![("seq", (*next - 1).to_string())]
+    });
+    numbered.into_bytes()
+}
+
+/// A message sent to the writer thread.
+#[derive(Debug)]
+pub(crate) enum Command {
+    /// Write a number of formatted records, the most severe at the given level, and whether
+    /// they're lines of text. The reservation counts them against the memory limit until
+    /// they're written.
+    Records(Vec<u8>, u32, LogLevel, Durability, bool, Reservation),
+    /// Flush and sync the file, then acknowledge on the given channel.
+    Flush(Sender<()>),
+    /// Switch to another file, then report how flushing the old one went.
+    Replace(File, Option<IndexWriter>, Sender<io::Result<()>>),
+}
+
+/// Writes formatted records to the log file.
+#[derive(Clone, Debug)]
+pub(crate) enum Writer {
+    /// Records are written on the calling thread while holding the file lock.
+    Sync(Arc<Mutex<LogFile>>),
+    /// Records are queued for a dedicated writer thread, so callers never wait on I/O.
+    Async(Sender<Command>),
+}
+
+impl Writer {
+    pub(crate) fn sync(file: LogFile) -> Self {
+        Writer::Sync(Arc::new(Mutex::new(file)))
+    }
+
+    /// Spawns the writer thread. It flushes the buffer whenever no new records have arrived
+    /// for `idle_flush`, so records show up promptly even when traffic is low.
+    pub(crate) fn spawn(mut file: LogFile, idle_flush: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("woody-writer".to_string())
+            .spawn(move || loop {
+                match receiver.recv_timeout(idle_flush) {
+                    Ok(Command::Records(
+                        output,
+                        records,
+                        level,
+                        durability,
+                        lines,
+                        _reservation,
+                    )) => {
+                        check(file.write_records(&output, records, level, durability, lines));
+                    }
+                    Ok(Command::Flush(done)) => {
+                        check(file.sync());
+                        let _ = done.send(());
+                    }
+                    Ok(Command::Replace(new_file, index, done)) => {
+                        let _ = done.send(file.replace(new_file, index));
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        if file.dirty {
+                            check(file.flush());
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        check(file.flush());
+                        return;
+                    }
+                }
+            })
+            .unwrap();
+
+        Writer::Async(sender)
+    }
+
+    /// The file shared between clones, in sync mode.
+    #[cfg(unix)]
+    pub(crate) fn shared_file(&self) -> Option<Arc<Mutex<LogFile>>> {
+        match self {
+            Writer::Sync(file) => Some(file.clone()),
+            Writer::Async(_) => None,
+        }
+    }
+
+    /// Writes `records` formatted records, the most severe at `level`, with a single write.
+    /// `lines` is whether they're lines of text, as opposed to binary.
+    pub(crate) fn write(
+        &self,
+        output: &[u8],
+        records: u32,
+        level: LogLevel,
+        durability: Durability,
+        lines: bool,
+    ) {
+        match self {
+            Writer::Sync(file) => {
+                let mut file = lock(file);
+                check(file.write_records(output, records, level, durability, lines));
+            }
+            Writer::Async(sender) => {
+                let Some(reservation) = memory::reserve(output.len()) else {
+                    crate::internal::event(LogLevel::Warning, || {
+                        format!("over the memory limit, dropped {records} records")
+                    });
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::dropped();
+                    return;
+                };
+                let command = Command::Records(
+                    output.to_vec(),
+                    records,
+                    level,
+                    durability,
+                    lines,
+                    reservation,
+                );
+                if sender.send(command).is_err() {
+                    crate::internal::event(LogLevel::Warning, || {
+                        format!("the writer thread is gone, dropped {records} records")
+                    });
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::dropped();
+                }
+            }
+        }
+    }
+
+    /// Switches to another file. Records written before this call go to the old file, and those
+    /// after it to the new one; in async mode this waits until the writer thread has switched.
+    pub(crate) fn replace(&self, file: File, index: Option<IndexWriter>) -> io::Result<()> {
+        match self {
+            Writer::Sync(log_file) => lock(log_file).replace(file, index),
+            Writer::Async(sender) => {
+                let (done, wait) = mpsc::channel();
+                let gone = || io::Error::new(io::ErrorKind::BrokenPipe, "writer thread is gone");
+                sender
+                    .send(Command::Replace(file, index, done))
+                    .map_err(|_| gone())?;
+                wait.recv().map_err(|_| gone())?
+            }
+        }
+    }
+
+    /// Flushes any buffered records and syncs the file. In async mode this waits until the
+    /// writer thread has caught up.
+    pub(crate) fn flush(&self) {
+        match self {
+            Writer::Sync(file) => check(lock(file).sync()),
+            Writer::Async(sender) => {
+                let (done, wait) = mpsc::channel();
+                if sender.send(Command::Flush(done)).is_ok() {
+                    let _ = wait.recv();
+                }
+            }
+        }
+    }
+}
+
+/// Locks the log file. A panic while the lock was held (say, from a panic hook that logs)
+/// leaves it poisoned, but the file itself is still fine to write to.
+fn lock(file: &Mutex<LogFile>) -> std::sync::MutexGuard<'_, LogFile> {
+    file.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Handles the result of a write or flush. A failing log file shouldn't take the application
+/// down with it, so errors are only counted.
+fn check(result: io::Result<()>) {
+    if let Err(e) = result {
+        crate::internal::event(LogLevel::Warning, || {
+            format!("couldn't write to the log file: {e}")
+        });
+        #[cfg(feature = "metrics")]
+        crate::metrics::sink_error();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, path::PathBuf};
+
+    use super::*;
+
+    fn temp_file(name: &str) -> (File, PathBuf) {
+        let path = std::env::temp_dir().join(format!("woody-{name}-{}.log", std::process::id()));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .unwrap();
+        (file, path)
+    }
+
+    /// Check that `FsyncEveryN` syncs on every Nth record and flushes the rest.
+    #[test]
+    fn test_fsync_every_n() {
+        let (file, path) = temp_file("durability");
+        let mut log_file = LogFile::new(file, None);
+
+        let durability = Durability::FsyncEveryN(3);
+        let level = LogLevel::Info;
+        log_file
+            .write_records(b"one\n", 1, level, durability, true)
+            .unwrap();
+        assert_eq!(log_file.unsynced, 1);
+        log_file
+            .write_records(b"two\nthree\n", 2, level, durability, true)
+            .unwrap();
+        assert_eq!(log_file.unsynced, 0);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "one\ntwo\nthree\n");
+    }
+
+    /// Check that the writer thread flushes buffered records once it goes idle.
+    #[test]
+    fn test_idle_flush() {
+        let (file, path) = temp_file("idle-flush");
+        let writer = Writer::spawn(LogFile::new(file, None), Duration::from_millis(20));
+        writer.write(b"buffered\n", 1, LogLevel::Info, Durability::None, true);
+
+        std::thread::sleep(Duration::from_millis(200));
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "buffered\n");
+    }
+
+    /// Check that MessagePack records are numbered with a key in their maps.
+    #[test]
+    fn test_sequence_in_msgpack() {
+        let (file, path) = temp_file("sequence-msgpack");
+        let writer = Writer::sync(LogFile::new(file, None).numbered());
+        let frames = crate::msgpack::encode_lines("{\"message\":\"a\"}\n{\"message\":\"b\"}\n");
+        writer.write(&frames, 2, LogLevel::Info, Durability::None, false);
+        writer.write(&frames, 2, LogLevel::Info, Durability::None, false);
+        writer.flush();
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let text = crate::msgpack::decode(&contents).unwrap();
+        assert_eq!(text, "a seq=0\nb seq=1\na seq=2\nb seq=3\n");
+    }
+
+    /// Check that records from many threads are numbered in the order they're in the file.
+    #[test]
+    fn test_sequence_follows_file_order() {
+        let (file, path) = temp_file("sequence");
+        let writer = Writer::sync(LogFile::new(file, None).numbered());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let writer = writer.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        writer.write(b"a\r\nb\n", 2, LogLevel::Info, Durability::None, true);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        writer.flush();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let numbers: Vec<u64> = contents
+            .lines()
+            .map(|line| {
+                line.trim_end()
+                    .rsplit("seq=")
+                    .next()
+                    .unwrap()
+                    .parse()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(numbers, (0..400).collect::<Vec<_>>());
+        assert!(contents.starts_with("a seq=0\r\nb seq=1\n"));
+    }
+
+    /// Check that records go to the new file once it's swapped in, in both modes.
+    #[test]
+    fn test_replace_file() {
+        for (mode, spawn) in [("sync", false), ("async", true)] {
+            let (old, old_path) = temp_file(&format!("replace-old-{mode}"));
+            let (new, new_path) = temp_file(&format!("replace-new-{mode}"));
+            let file = LogFile::new(old, None);
+            let writer = match spawn {
+                true => Writer::spawn(file, Duration::from_secs(60)),
+                false => Writer::sync(file),
+            };
+            writer.write(b"before\n", 1, LogLevel::Info, Durability::None, true);
+            writer.replace(new, None).unwrap();
+            writer.write(b"after\n", 1, LogLevel::Info, Durability::None, true);
+            writer.flush();
+
+            let old = std::fs::read_to_string(&old_path).unwrap();
+            let new = std::fs::read_to_string(&new_path).unwrap();
+            std::fs::remove_file(&old_path).unwrap();
+            std::fs::remove_file(&new_path).unwrap();
+            assert_eq!(
+                (old.as_str(), new.as_str()),
+                ("before\n", "after\n"),
+                "{mode}"
+            );
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Uses the stable extension points the way a third-party crate would, so an accidental
+//! breaking change to them fails to compile (or fails here) instead of surfacing downstream.
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use woody::{Fields, Format, LogLevel, Logger, Record, Sink, SinkId, Value};
+
+/// A sink as a third-party crate would write it.
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Collect {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn assert_sink<S: Sink>(_: &S) {}
+
+fn describe(value: &Value) -> &'static str {
+    // Exhaustive on purpose: a new variant is a breaking change.
+    match value {
+        Value::I64(_) => "i64",
+        Value::U64(_) => "u64",
+        Value::F64(_) => "f64",
+        Value::Bool(_) => "bool",
+        Value::Str(_) => "str",
+    }
+}
+
+#[test]
+fn test_extension_points() {
+    if cfg!(feature = "file") {
+        let dir = std::env::temp_dir().join(format!("woody-public-api-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("WOODY_FILE", dir.join("woody.log"));
+    }
+
+    let record: Record = Record::new(LogLevel::Warning, "disk low", file!(), line!(), "app")
+        .with_field("free_mb", 12u64)
+        .with_field("volume", "/data");
+    let level: LogLevel = record.level;
+    let message: &String = &record.message;
+    let (filepath, line): (&'static str, u32) = (record.filepath, record.line_number);
+    let module_path: &'static str = record.module_path;
+    let thread: &Option<String> = &record.thread;
+    let fields: &Fields = &record.fields;
+    assert_eq!(level, LogLevel::Warning);
+    assert_eq!(message, "disk low");
+    assert!(filepath.ends_with("public_api.rs") && line > 0);
+    assert_eq!((module_path, thread), ("app", &None));
+    let kinds: Vec<_> = fields
+        .iter()
+        .map(|(key, value)| (*key, describe(value)))
+        .collect();
+    assert_eq!(kinds, [("free_mb", "u64"), ("volume", "str")]);
+    assert_eq!(fields.get("free_mb"), Some(&Value::U64(12)));
+
+    let sink = Collect::default();
+    assert_sink(&sink);
+    let logger = Logger::get_instance();
+    let id: SinkId = logger.add_sink(sink.clone());
+    logger.log(&record);
+    let removed: Option<Box<dyn Write + Send>> = logger.remove_sink(id);
+    assert!(removed.is_some());
+    let written = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        written.ends_with("disk low free_mb=12 volume=/data\n"),
+        "{written}"
+    );
+
+    let mut json = logger.clone();
+    json.set_format(Format::Json);
+    let mut out = Vec::new();
+    json.write_to(&record, &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().starts_with('{'));
+    assert_eq!(Format::default(), Format::Text);
+}